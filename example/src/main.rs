@@ -1,14 +1,17 @@
-use ariadne::{Color, Label, Report, ReportKind, Source};
-use rhai_trace::{BetterError};
+use ariadne::{Color, Config, IndexType, Label, Report, ReportKind, Source};
+use rhai_trace::{BetterError, Severity};
 
 fn display_error(code: &str, better: &BetterError) {
-    let mut report = Report::build(
-        ReportKind::Error,
-        better.span.start()..better.span.end(), 
-    )
+    let kind = match better.severity {
+        Severity::Error => ReportKind::Error,
+        Severity::Warning => ReportKind::Warning,
+        _ => ReportKind::Advice,
+    };
+    let mut report = Report::build(kind, better.span)
+    .with_config(Config::default().with_index_type(IndexType::Byte))
     .with_message(&better.message)
     .with_label(
-        Label::new(better.span.start()..better.span.end())
+        Label::new(better.span)
             .with_message(
                 better.help
                     .as_deref()
@@ -19,12 +22,20 @@ fn display_error(code: &str, better: &BetterError) {
     
     if let Some(note) = &better.note {
         report = report.with_label(
-            Label::new(better.span.start()..better.span.end())
+            Label::new(better.span)
                 .with_message(note)
                 .with_color(Color::Cyan),
         );
     }
 
+    for (span, message) in &better.labels {
+        report = report.with_label(
+            Label::new(*span)
+                .with_message(message)
+                .with_color(Color::Yellow),
+        );
+    }
+
     report.finish().print(Source::from(code)).unwrap();
 }
 
@@ -43,7 +54,7 @@ return "test complete"
 
     let result = engine.eval_with_scope::<rhai::Dynamic>(&mut scope, code);
     if let Err(err) = result {
-        let better = rhai_trace::BetterError::improve_eval_error(&err, code, &engine, None)?;
+        let better = rhai_trace::BetterError::improve_eval_error(&err, code, &engine, None, None)?;
         display_error(code, &better);
     }
 
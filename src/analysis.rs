@@ -0,0 +1,165 @@
+//! Last-resort diagnostics for scripts that cannot be compiled at all.
+//!
+//! For catastrophically broken input (binary garbage, truncated uploads), the
+//! parse error Rhai reports often points at the end of the file and no spans can
+//! be extracted. [`structural_scan`] works purely on tokens and bracket balance,
+//! so it always finds something anchored in the text.
+
+use crate::error::{BetterError, Severity};
+use crate::error_code::ErrorCode;
+use crate::lexer;
+use crate::span::Span;
+use rhai::{AST, Engine, Token};
+
+/// The outcome of [`structural_scan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructuralDiagnostic {
+    /// The diagnostic, anchored at the earliest structural problem.
+    pub error: BetterError,
+    /// Spans of every opening bracket that is never closed, in source order.
+    pub unclosed: Vec<Span>,
+}
+
+/// Scans `code` lexically for the earliest structural problem.
+///
+/// The problem is, in order of preference:
+///
+/// 1. the first token the tokenizer cannot make sense of,
+/// 2. the first closing bracket that has no matching opener,
+/// 3. the innermost opening bracket that is never closed.
+///
+/// Returns `None` when the tokens and brackets of the script are well formed.
+pub fn structural_scan(code: &str) -> Option<StructuralDiagnostic> {
+    let engine = Engine::new_raw();
+    let lexemes = lexer::tokenize(&engine, code);
+
+    let mut open: Vec<(Span, char)> = Vec::new();
+    let mut problem: Option<(Span, String, String)> = None;
+
+    for lexeme in &lexemes {
+        match &lexeme.token {
+            Token::LexError(err) => {
+                problem = Some((
+                    lexeme.span,
+                    format!("Invalid input: {}", err),
+                    "Remove or replace the characters that are not valid Rhai.".into(),
+                ));
+                break;
+            }
+            Token::LeftParen => open.push((lexeme.span, '(')),
+            Token::LeftBracket | Token::QuestionBracket => open.push((lexeme.span, '[')),
+            Token::LeftBrace | Token::MapStart => open.push((lexeme.span, '{')),
+            Token::RightParen | Token::RightBracket | Token::RightBrace => {
+                let (closer, opener) = match lexeme.token {
+                    Token::RightParen => (')', '('),
+                    Token::RightBracket => (']', '['),
+                    _ => ('}', '{'),
+                };
+                match open.pop() {
+                    Some((_, found)) if found == opener => {}
+                    Some((span, found)) => {
+                        problem = Some((
+                            lexeme.span,
+                            format!("Mismatched closing `{}`.", closer),
+                            format!(
+                                "The `{}` opened on line {} is still open here.",
+                                found,
+                                span.line()
+                            ),
+                        ));
+                        break;
+                    }
+                    None => {
+                        problem = Some((
+                            lexeme.span,
+                            format!("Unmatched closing `{}`.", closer),
+                            format!("Remove it or add a matching `{}` before it.", opener),
+                        ));
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (span, message, help) = match problem {
+        Some(problem) => problem,
+        None => {
+            let (span, opener) = open.last()?;
+            (
+                *span,
+                format!("Unclosed `{}`.", opener),
+                format!("Add the missing `{}`.", closing(*opener)),
+            )
+        }
+    };
+
+    let count = |c: char| open.iter().filter(|(_, opener)| *opener == c).count();
+    let hint = format!(
+        "Bracket balance: {} unclosed `(`, {} unclosed `{{`, {} unclosed `[`.",
+        count('('),
+        count('{'),
+        count('[')
+    );
+
+    Some(StructuralDiagnostic {
+        error: BetterError {
+            message,
+            help: Some(help),
+            hint: Some(hint),
+            note: Some(format!(
+                "This is the earliest structural problem in the script, on line {}.",
+                span.line()
+            )),
+            span,
+            code: Some(ErrorCode::SyntaxError),
+            labels: Vec::new(),
+            severity: Severity::Error,
+        },
+        unclosed: open.into_iter().map(|(span, _)| span).collect(),
+    })
+}
+
+/// Compiles `code` with `engine`, returning the most useful diagnostic on failure.
+///
+/// The parse error is improved with [`BetterError::improve_parse_error`]. When it
+/// has no usable position, or points at the very end of the script (as it does for
+/// unclosed brackets), the [`structural_scan`] diagnostic is returned instead.
+pub fn validate_full(code: &str, engine: &Engine) -> Result<AST, Box<BetterError>> {
+    let err = match engine.compile(&*lexer::compilable(code)) {
+        Ok(ast) => return Ok(ast),
+        Err(err) => err,
+    };
+
+    let improved = BetterError::improve_parse_error(&err, code).ok();
+    let at_end = err.position().is_none()
+        || improved
+            .as_ref()
+            .is_none_or(|better| better.span.start() >= code.trim_end().len());
+
+    if at_end && let Some(structural) = structural_scan(code) {
+        return Err(Box::new(structural.error));
+    }
+
+    Err(Box::new(improved
+        .or_else(|| structural_scan(code).map(|s| s.error))
+        .unwrap_or_else(|| BetterError {
+            message: err.to_string(),
+            help: None,
+            hint: None,
+            note: None,
+            span: Span::from_pos(code, &err.position()),
+            code: Some(ErrorCode::SyntaxError),
+            labels: Vec::new(),
+            severity: Severity::Error,
+        })))
+}
+
+fn closing(opener: char) -> char {
+    match opener {
+        '(' => ')',
+        '[' => ']',
+        _ => '}',
+    }
+}
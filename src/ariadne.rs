@@ -0,0 +1,50 @@
+//! Integration with the [`ariadne`](https://docs.rs/ariadne) crate.
+//!
+//! Enabled with the `ariadne` feature. [`Span`] implements [`ariadne::Span`],
+//! so it can be handed straight to [`ariadne::Report::build`] and
+//! [`ariadne::Label::new`] without building a range by hand.
+//!
+//! Spans hold **byte offsets**, so the report should be configured with
+//! [`ariadne::IndexType::Byte`].
+//!
+//! # Example
+//!
+//! ```rust
+//! use ariadne::{Config, IndexType, Label, Report, ReportKind, Source};
+//! use rhai_trace::BetterError;
+//!
+//! let code = "let x = 1;\nlet y = x + z;";
+//!
+//! let engine = rhai::Engine::new();
+//! let err = engine.eval::<rhai::Dynamic>(code).unwrap_err();
+//! let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+//!
+//! let mut output = Vec::new();
+//! Report::build(ReportKind::Error, better.span)
+//!     .with_config(Config::default().with_index_type(IndexType::Byte).with_color(false))
+//!     .with_message(&better.message)
+//!     .with_label(Label::new(better.span).with_message("here"))
+//!     .finish()
+//!     .write(Source::from(code), &mut output)
+//!     .unwrap();
+//!
+//! assert!(String::from_utf8(output).unwrap().contains("here"));
+//! ```
+
+use crate::span::Span;
+
+impl ariadne::Span for Span {
+    type SourceId = ();
+
+    fn source(&self) -> &Self::SourceId {
+        &()
+    }
+
+    fn start(&self) -> usize {
+        Span::start(self)
+    }
+
+    fn end(&self) -> usize {
+        Span::end(self)
+    }
+}
@@ -0,0 +1,215 @@
+//! `rhai-trace`: check Rhai scripts from the command line.
+//!
+//! A thin shell over the public `rhai_trace` API, built with the `cli` feature.
+
+use rhai::Engine;
+use rhai_trace::analysis::validate_full;
+use rhai_trace::render::{Cascade, render_diagnostics, render_github};
+use rhai_trace::{Diagnostic, Diagnostics, ErrorCode, Severity, SpanTracer};
+use serde::Serialize;
+use std::io::Read;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage:
+  rhai-trace check <path> [--format text|json|github]
+  rhai-trace spans <path> [--format text|json] [--json]
+  rhai-trace explain <code>
+
+Use `-` as the path to read the script from standard input.
+
+Exit status: 0 when there is nothing to report, 1 when the worst diagnostic is a
+warning, 2 when it is an error, and 3 when the command itself fails.";
+
+/// Exit status when the command itself fails, e.g. on bad arguments.
+const EXIT_FAILURE: u8 = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+    Github,
+}
+
+struct Args {
+    command: String,
+    operand: String,
+    format: Format,
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    };
+
+    let result = match args.command.as_str() {
+        "check" => check(&args),
+        "spans" => spans(&args),
+        "explain" => explain(&args.operand),
+        _ => unreachable!("commands are validated by parse_args"),
+    };
+
+    match result {
+        Ok(status) => ExitCode::from(status),
+        Err(message) => {
+            eprintln!("error: {}", message);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+/// Returns `None` when help was asked for.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut positional = Vec::new();
+    let mut format = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--json" => format = Some(Format::Json),
+            "--format" => {
+                let value = args.next().ok_or("`--format` needs a value.")?;
+                format = Some(parse_format(&value)?);
+            }
+            _ if arg.starts_with("--format=") => {
+                format = Some(parse_format(&arg["--format=".len()..])?);
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'.", arg)),
+            _ => positional.push(arg),
+        }
+    }
+
+    let [command, operand]: [String; 2] = positional
+        .try_into()
+        .map_err(|_| "Expected a command and one argument.".to_string())?;
+
+    let format = format.unwrap_or(Format::Text);
+    match command.as_str() {
+        "check" | "explain" => {}
+        "spans" if format != Format::Github => {}
+        "spans" => return Err("`spans` supports only `--format text` and `--format json`.".into()),
+        _ => return Err(format!("Unknown command '{}'.", command)),
+    }
+
+    Ok(Some(Args {
+        command,
+        operand,
+        format,
+    }))
+}
+
+fn parse_format(value: &str) -> Result<Format, String> {
+    match value {
+        "text" => Ok(Format::Text),
+        "json" => Ok(Format::Json),
+        "github" => Ok(Format::Github),
+        _ => Err(format!("Unknown format '{}'.", value)),
+    }
+}
+
+/// Reads the script at `path`, or standard input for `-`. Also returns the
+/// name to show for the script in diagnostics.
+fn read_source(path: &str) -> Result<(String, String), String> {
+    if path == "-" {
+        let mut source = String::new();
+        std::io::stdin()
+            .read_to_string(&mut source)
+            .map_err(|err| format!("Failed to read standard input: {}", err))?;
+        return Ok((source, "<stdin>".into()));
+    }
+
+    let source = std::fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read '{}': {}", path, err))?;
+    Ok((source, path.into()))
+}
+
+/// Validates the script and runs every lint over it.
+fn check(args: &Args) -> Result<u8, String> {
+    let (source, origin) = read_source(&args.operand)?;
+    let engine = Engine::new();
+
+    let mut diagnostics = Diagnostics::new(source);
+    match validate_full(diagnostics.source(), &engine) {
+        Err(error) => {
+            diagnostics.push(Diagnostic::new(*error));
+        }
+        Ok(_) => {
+            let lints = SpanTracer::with_engine(engine)
+                .lint(diagnostics.source())
+                .map_err(|err| err.to_string())?;
+            for lint in lints {
+                diagnostics.push(Diagnostic::new(lint.into()));
+            }
+        }
+    }
+
+    match args.format {
+        Format::Text => print!("{}", render_diagnostics(&diagnostics, &origin, Cascade::Show)),
+        Format::Github => {
+            for diagnostic in &diagnostics {
+                print!("{}", render_github(&diagnostic.error, &origin));
+            }
+        }
+        Format::Json => {
+            let json: Vec<&Diagnostic> = diagnostics.into_iter().collect();
+            println!("{}", to_json(&json)?);
+        }
+    }
+
+    Ok(match diagnostics.max_severity() {
+        Some(Severity::Error) => 2,
+        Some(Severity::Warning) => 1,
+        Some(Severity::Note) | None => 0,
+    })
+}
+
+/// Prints every span extracted from the script.
+fn spans(args: &Args) -> Result<u8, String> {
+    let (source, _) = read_source(&args.operand)?;
+
+    let spans = match SpanTracer::new().extract_from(&source) {
+        Ok(spans) => spans,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return Ok(2);
+        }
+    };
+
+    match args.format {
+        Format::Json => println!("{}", to_json(&spans)?),
+        _ => {
+            for span in &spans {
+                println!(
+                    "{}:{}-{}:{}\t{}..{}",
+                    span.line(),
+                    span.column(),
+                    span.end_line(),
+                    span.end_column(),
+                    span.start(),
+                    span.end()
+                );
+            }
+        }
+    }
+
+    Ok(0)
+}
+
+/// Prints the explanation of an error code.
+fn explain(code: &str) -> Result<u8, String> {
+    let code: ErrorCode = code.parse()?;
+    println!("{}: {}\n\n{}", code, code.title(), code.explanation());
+    Ok(0)
+}
+
+fn to_json(value: &impl Serialize) -> Result<String, String> {
+    serde_json::to_string_pretty(value).map_err(|err| err.to_string())
+}
@@ -0,0 +1,173 @@
+use crate::span::Span;
+use crate::tracer::{SpanTracer, WalkOptions};
+use rhai::OptimizationLevel;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+/// A cache of extracted spans, keyed by the script source and the settings of the
+/// tracer that extracted them, for servers improving errors of the same scripts
+/// over and over. The settings are the optimization level, the
+/// [tab width](SpanTracer::tab_width), the
+/// [nesting limit](SpanTracer::max_nesting_depth) and the
+/// [size limit](SpanTracer::max_source_bytes), so tracers set up differently can
+/// share a cache without seeing each other's spans.
+///
+/// Hits return an `Arc<[Span]>`, so they are cheap to clone. Once `max_entries`
+/// scripts are cached, the least recently used one is evicted. The cache is
+/// `Send + Sync`, so it can be shared between threads in an `Arc`.
+///
+/// Spans also depend on the custom syntax and operators registered on the engine,
+/// which are not part of the key: share a cache only between engines set up alike.
+///
+/// # Example
+///
+/// ```rust
+/// use rhai_trace::{SpanCache, SpanTracer};
+/// use std::sync::Arc;
+///
+/// let cache = SpanCache::new(64);
+/// let tracer = SpanTracer::new();
+///
+/// let first = cache.extract_from(&tracer, "let a = 1;").unwrap();
+/// let second = cache.extract_from(&tracer, "let a = 1;").unwrap();
+/// assert!(Arc::ptr_eq(&first, &second));
+/// assert_eq!(cache.len(), 1);
+/// ```
+#[derive(Debug)]
+pub struct SpanCache {
+    max_entries: usize,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<u64, CacheEntry>,
+    /// Incremented on every access, to find the least recently used entry.
+    clock: u64,
+}
+
+/// The settings of a tracer that change the spans it extracts from a script, or
+/// whether it extracts them at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct ExtractSettings {
+    pub level: OptimizationLevel,
+    pub walk: WalkOptions,
+    pub max_source_bytes: usize,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    source: Box<str>,
+    settings: ExtractSettings,
+    spans: Arc<[Span]>,
+    last_used: u64,
+}
+
+impl SpanCache {
+    /// Creates an empty cache holding the spans of at most `max_entries` scripts.
+    /// With `max_entries` of 0, nothing is cached.
+    pub fn new(max_entries: usize) -> Self {
+        SpanCache {
+            max_entries,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Returns the largest number of scripts this cache holds.
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    /// Returns the number of scripts currently cached.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Returns whether no script is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every cached script.
+    pub fn clear(&self) {
+        self.lock().entries.clear();
+    }
+
+    /// Returns the spans of `script` as extracted by
+    /// [`tracer.extract_from`](SpanTracer::extract_from), extracting them only on
+    /// the first call for this script. Errors are not cached.
+    pub fn extract_from(
+        &self,
+        tracer: &SpanTracer,
+        script: &str,
+    ) -> Result<Arc<[Span]>, Box<dyn Error>> {
+        self.get_or_extract(script, tracer.extract_settings(), || {
+            tracer.extract_from(script)
+        })
+    }
+
+    /// Returns the cached spans of `script` extracted with `settings`, or caches
+    /// the result of `extract`. The lock is not held while extracting.
+    pub(crate) fn get_or_extract(
+        &self,
+        script: &str,
+        settings: ExtractSettings,
+        extract: impl FnOnce() -> Result<Vec<Span>, Box<dyn Error>>,
+    ) -> Result<Arc<[Span]>, Box<dyn Error>> {
+        let key = cache_key(script, &settings);
+
+        {
+            let mut state = self.lock();
+            state.clock += 1;
+            let clock = state.clock;
+            if let Some(entry) = state.entries.get_mut(&key)
+                && &*entry.source == script
+                && entry.settings == settings
+            {
+                entry.last_used = clock;
+                return Ok(entry.spans.clone());
+            }
+        }
+
+        let spans: Arc<[Span]> = extract()?.into();
+        if self.max_entries == 0 {
+            return Ok(spans);
+        }
+
+        let mut state = self.lock();
+        if state.entries.len() >= self.max_entries && !state.entries.contains_key(&key) {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+        state.clock += 1;
+        let entry = CacheEntry {
+            source: script.into(),
+            settings,
+            spans: spans.clone(),
+            last_used: state.clock,
+        };
+        state.entries.insert(key, entry);
+
+        Ok(spans)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn cache_key(script: &str, settings: &ExtractSettings) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    script.hash(&mut hasher);
+    settings.hash(&mut hasher);
+    hasher.finish()
+}
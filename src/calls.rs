@@ -0,0 +1,114 @@
+use crate::lexer::{self, FunctionBody};
+use crate::line_index::LineIndex;
+use crate::span::{Span, pos_to_byte};
+use rhai::{AST, ASTNode, Engine, Expr, FnCallExpr, Position, Stmt};
+use std::collections::VecDeque;
+
+/// A call of a function in a script, as found by [`CallGraph::new`].
+#[derive(Debug, Clone)]
+pub(crate) struct CallSite {
+    /// The index of the function whose body contains the call, or `None` for a
+    /// call at the top level of the script.
+    pub caller: Option<usize>,
+    /// The index of the script function called, or `None` for any other function.
+    pub callee: Option<usize>,
+    /// The name in the call.
+    pub span: Span,
+}
+
+/// The script functions of a script along with every call between them.
+#[derive(Debug, Clone)]
+pub(crate) struct CallGraph {
+    pub functions: Vec<FunctionBody>,
+    pub calls: Vec<CallSite>,
+}
+
+impl CallGraph {
+    /// Finds the calls in an already compiled `AST` of `script`, telling script
+    /// functions apart by their name and number of parameters.
+    pub(crate) fn new(ast: &AST, script: &str, engine: &Engine) -> Self {
+        let index = LineIndex::new(script);
+        let functions = lexer::function_bodies(engine, script);
+        let mut calls = Vec::new();
+
+        ast.walk(&mut |nodes: &[ASTNode]| {
+            // Calls used as statements are statements of their own.
+            let (f, pos, arity) = match nodes.last() {
+                Some(ASTNode::Expr(Expr::FnCall(f, pos))) => (&**f, pos, f.args.len()),
+                Some(ASTNode::Stmt(Stmt::FnCall(f, pos))) => (&**f, pos, f.args.len()),
+                // The object a method is called on is its first argument.
+                Some(ASTNode::Expr(Expr::MethodCall(f, pos))) => (&**f, pos, f.args.len() + 1),
+                _ => return true,
+            };
+            if let Some(span) = call_name(f, pos, &index) {
+                let offset = span.start();
+                calls.push(CallSite {
+                    caller: functions.iter().position(|f| f.body.contains(&offset)),
+                    callee: functions.iter().position(|function| {
+                        function.name == f.name && function.params.len() == arity
+                    }),
+                    span,
+                });
+            }
+            true
+        });
+
+        calls.sort_by_key(|call| call.span);
+        calls.dedup_by_key(|call| call.span);
+
+        CallGraph { functions, calls }
+    }
+
+    /// Returns the call whose name starts at the byte `offset`, if any.
+    pub(crate) fn call_at(&self, offset: usize) -> Option<&CallSite> {
+        self.calls.iter().find(|call| call.span.start() == offset)
+    }
+
+    /// Returns the shortest chain of calls leading from the script function at
+    /// `start` back to itself, or `None` if it is not recursive.
+    pub(crate) fn cycle_from(&self, start: usize) -> Option<Vec<&CallSite>> {
+        // The call that first reached each function.
+        let mut reached_by: Vec<Option<&CallSite>> = vec![None; self.functions.len()];
+        let mut queue = VecDeque::from([start]);
+
+        while let Some(function) = queue.pop_front() {
+            for call in self
+                .calls
+                .iter()
+                .filter(|call| call.caller == Some(function))
+            {
+                let Some(callee) = call.callee else {
+                    continue;
+                };
+                if callee == start {
+                    let mut cycle = vec![call];
+                    let mut current = function;
+                    while current != start {
+                        let call = reached_by[current]?;
+                        cycle.push(call);
+                        current = call.caller?;
+                    }
+                    cycle.reverse();
+                    return Some(cycle);
+                }
+                if reached_by[callee].is_none() {
+                    reached_by[callee] = Some(call);
+                    queue.push_back(callee);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Returns the span of the function name in a call of a script function
+/// positioned at `pos`, or `None` for operators and namespaced calls.
+fn call_name(f: &FnCallExpr, pos: &Position, index: &LineIndex) -> Option<Span> {
+    if pos.is_none() || f.is_operator_call() || f.is_qualified() {
+        return None;
+    }
+    let start = pos_to_byte(index, pos);
+    let name = start..start + f.name.len();
+    (index.source().get(name.clone()) == Some(f.name.as_str()))
+        .then(|| Span::from_range_in(index, name))
+}
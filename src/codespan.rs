@@ -0,0 +1,82 @@
+//! Integration with the [`codespan-reporting`](https://docs.rs/codespan-reporting) crate.
+//!
+//! Enabled with the `codespan` feature, which adds
+//! [`BetterError::to_codespan_diagnostic`]. You supply the `file_id`, which
+//! identifies the script in your own [`Files`](codespan_reporting::files::Files)
+//! database, e.g. the id [`SimpleFiles::add`](codespan_reporting::files::SimpleFiles::add)
+//! returned for the script's [`SimpleFile`](codespan_reporting::files::SimpleFile).
+//!
+//! # Example
+//!
+//! ```rust
+//! use codespan_reporting::files::SimpleFiles;
+//! use codespan_reporting::term::{self, termcolor::NoColor};
+//! use rhai_trace::BetterError;
+//!
+//! let code = "let x = 1;\nlet y = x + z;";
+//!
+//! let engine = rhai::Engine::new();
+//! let err = engine.eval::<rhai::Dynamic>(code).unwrap_err();
+//! let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+//!
+//! let mut files = SimpleFiles::new();
+//! let file_id = files.add("script.rhai", code);
+//!
+//! let diagnostic = better.to_codespan_diagnostic(file_id);
+//! assert_eq!(diagnostic.code.as_deref(), Some("RT0101"));
+//! assert_eq!(diagnostic.labels[0].range, better.span.start()..better.span.end());
+//!
+//! let mut output = NoColor::new(Vec::new());
+//! term::emit(&mut output, &term::Config::default(), &files, &diagnostic).unwrap();
+//!
+//! let output = String::from_utf8(output.into_inner()).unwrap();
+//! assert!(output.contains("script.rhai:2:13"));
+//! ```
+
+use crate::error::{BetterError, Severity};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+
+impl BetterError {
+    /// Converts the error into a [`codespan_reporting`] diagnostic about the file `file_id`.
+    ///
+    /// The primary label covers the [`span`](BetterError::span) and carries the hint,
+    /// and each of the [`labels`](BetterError::labels) becomes a secondary label.
+    /// The help and note become the diagnostic's notes.
+    pub fn to_codespan_diagnostic(&self, file_id: usize) -> Diagnostic<usize> {
+        let severity = match self.severity {
+            Severity::Error => codespan_reporting::diagnostic::Severity::Error,
+            Severity::Warning => codespan_reporting::diagnostic::Severity::Warning,
+            Severity::Note => codespan_reporting::diagnostic::Severity::Note,
+        };
+
+        let mut labels = Vec::new();
+        if !self.span.is_none() {
+            labels.push(
+                Label::primary(file_id, self.span.start()..self.span.end())
+                    .with_message(self.hint.clone().unwrap_or_default()),
+            );
+        }
+        labels.extend(
+            self.labels
+                .iter()
+                .filter(|(span, _)| !span.is_none())
+                .map(|(span, message)| {
+                    Label::secondary(file_id, span.start()..span.end()).with_message(message)
+                }),
+        );
+
+        let notes = [("help", &self.help), ("note", &self.note)]
+            .into_iter()
+            .filter_map(|(label, text)| text.as_ref().map(|text| format!("{}: {}", label, text)))
+            .collect();
+
+        let mut diagnostic = Diagnostic::new(severity)
+            .with_message(&self.message)
+            .with_labels(labels)
+            .with_notes(notes);
+        if let Some(code) = self.code {
+            diagnostic = diagnostic.with_code(code.as_str());
+        }
+        diagnostic
+    }
+}
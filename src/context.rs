@@ -0,0 +1,180 @@
+use crate::error::{BetterError, ImproveOptions, check_source_size};
+use crate::lexer;
+use crate::messages::DiagnosticMessages;
+use crate::span::Span;
+use crate::tracer::{SpanTracer, WalkOptions};
+use rhai::{AST, Engine, EvalAltResult};
+use std::borrow::Cow;
+use std::error::Error;
+
+/// Everything needed to improve evaluation errors for one script:
+/// the source code, its compiled `AST`, and the spans extracted from it.
+///
+/// Implemented by the owned [`ErrorContext`] and the borrowed
+/// [`ErrorContextRef`], which share [`improve`](TraceContext::improve)
+/// so that both always produce the same [`BetterError`].
+///
+/// Neither context needs an `Engine` to improve an error: suggestions are
+/// looked up in the already compiled `AST`. With [`Verbosity::Minimal`], not
+/// even the `AST` is needed.
+///
+/// [`Verbosity::Minimal`]: crate::Verbosity::Minimal
+pub trait TraceContext {
+    /// Returns the script source.
+    fn code(&self) -> &str;
+    /// Returns the compiled `AST` of the script.
+    fn ast(&self) -> &AST;
+    /// Returns the spans extracted from the script.
+    fn spans(&self) -> &[Span];
+    /// Returns the settings errors are improved with.
+    fn options(&self) -> ImproveOptions {
+        ImproveOptions::new()
+    }
+
+    /// Return a more informative Rhai evaluation error for this script.
+    ///
+    /// With [`Verbosity::Minimal`], nothing is allocated besides the strings of
+    /// the returned [`BetterError`] and its labels.
+    ///
+    /// [`Verbosity::Minimal`]: crate::Verbosity::Minimal
+    fn improve(&self, error: &EvalAltResult) -> BetterError {
+        BetterError::improve_with(
+            error,
+            self.code(),
+            || Ok(Cow::Borrowed(self.ast())),
+            self.spans(),
+            None,
+            None,
+            &DiagnosticMessages::new(),
+            self.options(),
+        )
+    }
+}
+
+/// An owned [`TraceContext`], compiling the script and extracting its spans once
+/// so that many errors can be improved without repeating the work.
+///
+/// # Example
+///
+/// ```rust
+/// use rhai_trace::{ErrorContext, TraceContext};
+///
+/// let code = "let a = 1;\nlet b = a + c;";
+/// let engine = rhai::Engine::new();
+/// let context = ErrorContext::new(code, &engine).unwrap();
+///
+/// let err = engine.eval::<rhai::Dynamic>(code).unwrap_err();
+/// let better = context.improve(&err);
+/// assert_eq!(better.span.line(), 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ErrorContext {
+    code: String,
+    ast: AST,
+    spans: Vec<Span>,
+    options: ImproveOptions,
+}
+
+impl ErrorContext {
+    /// Compiles `code` with `engine` and extracts its spans.
+    pub fn new(code: impl Into<String>, engine: &Engine) -> Result<Self, Box<dyn Error>> {
+        Self::with_options(code, engine, ImproveOptions::new())
+    }
+
+    /// Compiles `code` with `engine` and extracts its spans, to improve errors
+    /// with `options`. Fails if `code` is larger than the options allow.
+    pub fn with_options(
+        code: impl Into<String>,
+        engine: &Engine,
+        options: ImproveOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        let code = code.into();
+        check_source_size(&code, options.max_source_bytes)?;
+        let ast = engine.compile(&*lexer::compilable(&code))?;
+        let spans = SpanTracer::spans_from_ast(&ast, &code, engine, WalkOptions::default())?;
+
+        Ok(Self {
+            code,
+            ast,
+            spans,
+            options,
+        })
+    }
+
+    /// Borrows this context as an [`ErrorContextRef`].
+    pub fn borrowed(&self) -> ErrorContextRef<'_> {
+        ErrorContextRef::with_options(&self.code, &self.ast, &self.spans, self.options)
+    }
+}
+
+impl TraceContext for ErrorContext {
+    fn code(&self) -> &str {
+        &self.code
+    }
+
+    fn ast(&self) -> &AST {
+        &self.ast
+    }
+
+    fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    fn options(&self) -> ImproveOptions {
+        self.options
+    }
+}
+
+/// A borrowed [`TraceContext`] for embedders that already hold the script,
+/// its `AST`, and its spans.
+///
+/// It has no interior mutability and never synchronizes, which makes it
+/// suitable for single-threaded hot loops.
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorContextRef<'a> {
+    code: &'a str,
+    ast: &'a AST,
+    spans: &'a [Span],
+    options: ImproveOptions,
+}
+
+impl<'a> ErrorContextRef<'a> {
+    /// Creates a context borrowing the script, its `AST`, and its spans.
+    pub fn new(code: &'a str, ast: &'a AST, spans: &'a [Span]) -> Self {
+        Self::with_options(code, ast, spans, ImproveOptions::new())
+    }
+
+    /// Creates a context borrowing the script, its `AST`, and its spans, to
+    /// improve errors with `options`.
+    pub fn with_options(
+        code: &'a str,
+        ast: &'a AST,
+        spans: &'a [Span],
+        options: ImproveOptions,
+    ) -> Self {
+        Self {
+            code,
+            ast,
+            spans,
+            options,
+        }
+    }
+}
+
+impl TraceContext for ErrorContextRef<'_> {
+    fn code(&self) -> &str {
+        self.code
+    }
+
+    fn ast(&self) -> &AST {
+        self.ast
+    }
+
+    fn spans(&self) -> &[Span] {
+        self.spans
+    }
+
+    fn options(&self) -> ImproveOptions {
+        self.options
+    }
+}
@@ -0,0 +1,218 @@
+//! Collections of diagnostics for one script, and grouping of cascading failures.
+//!
+//! A single root mistake, such as a typo in a `let`, often shows up as several
+//! diagnostics, e.g. when a retrying pipeline reruns the script after each fix.
+//! [`Diagnostics::correlate`] links such follow-on diagnostics to the one most
+//! likely to have caused them, so they can be indented under it or hidden.
+
+use crate::error::{BetterError, Severity, identifier_at};
+use crate::error_code::ErrorCode;
+use crate::lexer;
+use crate::span::Span;
+use rhai::Engine;
+
+/// Identifies a [`Diagnostic`] within its [`Diagnostics`].
+///
+/// Ids are handed out in the order diagnostics are pushed, starting at 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct DiagnosticId(usize);
+
+impl DiagnosticId {
+    /// Returns the position of the diagnostic in its [`Diagnostics`].
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+/// A [`BetterError`] along with the diagnostic it most likely follows from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub error: BetterError,
+    /// The diagnostic this one most likely follows from, as found by
+    /// [`Diagnostics::correlate`].
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub caused_by: Option<DiagnosticId>,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic that is not correlated to any other.
+    pub fn new(error: BetterError) -> Self {
+        Self {
+            error,
+            caused_by: None,
+        }
+    }
+}
+
+/// The diagnostics reported for one script, in the order they were found.
+///
+/// # Example
+///
+/// ```rust
+/// use rhai_trace::{BetterError, Diagnostic, Diagnostics, ErrorCode, Severity, Span};
+///
+/// let code = "let coutn = 0;\ncount += 1;\nprint(count);";
+/// let missing = |start| BetterError {
+///     message: "Variable not found: count".into(),
+///     help: None,
+///     hint: None,
+///     note: None,
+///     span: Span::from_range(code, start..start + 5),
+///     code: Some(ErrorCode::VariableNotFound),
+///     labels: Vec::new(),
+///     severity: Severity::Error,
+/// };
+///
+/// let mut diagnostics = Diagnostics::new(code);
+/// let root = diagnostics.push(Diagnostic::new(missing(15)));
+/// let follow_on = diagnostics.push(Diagnostic::new(missing(33)));
+///
+/// diagnostics.correlate();
+/// assert_eq!(diagnostics[follow_on].caused_by, Some(root));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    source: String,
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    /// Creates an empty collection for diagnostics about `source`.
+    pub fn new(source: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+            items: Vec::new(),
+        }
+    }
+
+    /// Returns the script the diagnostics are about.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Adds a diagnostic and returns its id.
+    pub fn push(&mut self, diagnostic: Diagnostic) -> DiagnosticId {
+        self.items.push(diagnostic);
+        DiagnosticId(self.items.len() - 1)
+    }
+
+    /// Returns the diagnostic with the given id, if it belongs to this collection.
+    pub fn get(&self, id: DiagnosticId) -> Option<&Diagnostic> {
+        self.items.get(id.0)
+    }
+
+    /// Returns the number of diagnostics.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns whether there are no diagnostics.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Iterates over the diagnostics and their ids, in the order they were pushed.
+    pub fn iter(&self) -> impl Iterator<Item = (DiagnosticId, &Diagnostic)> {
+        self.items
+            .iter()
+            .enumerate()
+            .map(|(i, diagnostic)| (DiagnosticId(i), diagnostic))
+    }
+
+    /// Iterates over the diagnostics [correlated](Diagnostics::correlate) to `root`.
+    pub fn caused_by(&self, root: DiagnosticId) -> impl Iterator<Item = (DiagnosticId, &Diagnostic)> {
+        self.iter()
+            .filter(move |(_, diagnostic)| diagnostic.caused_by == Some(root))
+    }
+
+    /// Returns the worst severity among the diagnostics.
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.items.iter().map(|diagnostic| diagnostic.error.severity).max()
+    }
+
+    /// Links diagnostics that most likely follow from an earlier one by setting
+    /// their [`caused_by`](Diagnostic::caused_by), replacing any earlier links.
+    ///
+    /// The heuristics are deliberately conservative. Only errors can be a root
+    /// cause, and a diagnostic is linked to the first earlier error that:
+    ///
+    /// 1. is a [`VariableNotFound`](ErrorCode::VariableNotFound) for the same
+    ///    name that the later diagnostic's span starts with, or
+    /// 2. lies inside the body of a function `f`, where the later diagnostic
+    ///    also lies inside `f` or its span starts with a call to `f`.
+    ///
+    /// Links always point at a root, never at another linked diagnostic, so
+    /// cascades are one level deep.
+    pub fn correlate(&mut self) {
+        let functions = lexer::function_bodies(&Engine::new_raw(), &self.source);
+        let enclosing = |span: &Span| {
+            functions
+                .iter()
+                .find(|function| !span.is_none() && function.body.contains(&span.start()))
+                .map(|function| function.name.as_str())
+        };
+
+        let facts: Vec<_> = self
+            .items
+            .iter()
+            .map(|diagnostic| {
+                let span = &diagnostic.error.span;
+                (identifier_at(&self.source, span), enclosing(span))
+            })
+            .collect();
+
+        let mut caused_by = vec![None; self.items.len()];
+
+        for later in 0..self.items.len() {
+            let (name, function) = facts[later];
+
+            caused_by[later] = (0..later)
+                .filter(|&root| {
+                    caused_by[root].is_none() && self.items[root].error.severity == Severity::Error
+                })
+                .find(|&root| {
+                    let (root_name, root_function) = facts[root];
+                    let same_missing_variable = self.items[root].error.code
+                        == Some(ErrorCode::VariableNotFound)
+                        && root_name.is_some()
+                        && root_name == name;
+                    let same_broken_function = root_function
+                        .is_some_and(|f| function == Some(f) || name == Some(f));
+
+                    same_missing_variable || same_broken_function
+                })
+                .map(DiagnosticId);
+        }
+
+        for (diagnostic, caused_by) in self.items.iter_mut().zip(caused_by) {
+            diagnostic.caused_by = caused_by;
+        }
+    }
+}
+
+impl std::ops::Index<DiagnosticId> for Diagnostics {
+    type Output = Diagnostic;
+
+    fn index(&self, id: DiagnosticId) -> &Diagnostic {
+        &self.items[id.0]
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
@@ -1,7 +1,404 @@
-use crate::span::Span;
-use crate::tracer::SpanTracer;
-use rhai::{Engine, EvalAltResult, ParseError};
+use crate::cache::{ExtractSettings, SpanCache};
+use crate::calls::CallGraph;
+use crate::error_code::ErrorCode;
+use crate::lexer;
+use crate::line_index;
+use crate::messages::{self, DiagnosticMessages};
+use crate::span::{Span, SpanKind, pos_offset};
+use crate::tracer::{BinaryOperation, SpanTracer, WalkOptions};
+use rhai::{AST, ASTNode, Engine, EvalAltResult, ParseError, Position, Scope, Stmt, Token};
+use std::borrow::Cow;
+use std::cmp::Reverse;
 use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// The largest script, in bytes, accepted by default: 256 MiB.
+pub const DEFAULT_MAX_SOURCE_BYTES: usize = 256 * 1024 * 1024;
+
+/// How deeply the statements and expressions of a script may nest by default
+/// before extracting its spans fails.
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 256;
+
+/// Errors raised by `rhai_trace` itself, as opposed to errors from the script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TraceError {
+    /// The script is larger than the configured maximum.
+    SourceTooLarge { size: usize, max: usize },
+    /// A span would start after it ends.
+    InvalidSpan { start: usize, end: usize },
+    /// A string passed to [`Span::parse_compact`](crate::Span::parse_compact)
+    /// is not in the compact span format.
+    MalformedSpan { input: String },
+    /// The statements and expressions of a script nest deeper than the
+    /// configured maximum.
+    NestingTooDeep { max: usize },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::SourceTooLarge { size, max } => write!(
+                f,
+                "Script is {} bytes long, which exceeds the maximum of {} bytes.",
+                size, max
+            ),
+            TraceError::InvalidSpan { start, end } => {
+                write!(f, "Invalid span: start {} is past end {}.", start, end)
+            }
+            TraceError::MalformedSpan { input } => write!(
+                f,
+                "Malformed span '{}': expected `start..end@line:column..end_line:end_column`.",
+                input
+            ),
+            TraceError::NestingTooDeep { max } => write!(
+                f,
+                "Script nests statements and expressions deeper than the maximum of {} levels.",
+                max
+            ),
+        }
+    }
+}
+
+impl Error for TraceError {}
+
+/// Rejects scripts longer than `max` bytes.
+pub(crate) fn check_source_size(code: &str, max: usize) -> Result<(), TraceError> {
+    if code.len() > max {
+        return Err(TraceError::SourceTooLarge {
+            size: code.len(),
+            max,
+        });
+    }
+    Ok(())
+}
+
+/// How much work improving an error spends on suggestions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Verbosity {
+    /// Only describe the error itself. Nothing is looked up in the script or the
+    /// engine: no suggestions for unknown variables or functions, no failed
+    /// operation, recursion cycle or index, and no declaration of a mismatched
+    /// parameter. The `AST` is never needed, and improving an error in a
+    /// [`TraceContext`](crate::TraceContext) allocates nothing besides the returned
+    /// [`BetterError`].
+    Minimal,
+    /// Everything `rhai_trace` can find out about the error.
+    #[default]
+    Full,
+}
+
+/// Settings for improving errors: the largest script accepted and how much work
+/// is spent on suggestions.
+///
+/// Every `BetterError::improve_*` function is also a method here, to improve
+/// errors with these settings instead of the defaults.
+///
+/// # Example
+///
+/// ```rust
+/// use rhai_trace::{
+///     ErrorContextRef, ImproveOptions, SpanTracer, TraceContext, TraceError, Verbosity,
+/// };
+///
+/// let code = "let total = prce * 2;";
+/// let engine = rhai::Engine::new();
+/// let ast = engine.compile(code).unwrap();
+/// let spans = SpanTracer::new().extract_from(code).unwrap();
+///
+/// let options = ImproveOptions::new().verbosity(Verbosity::Minimal);
+/// let context = ErrorContextRef::with_options(code, &ast, &spans, options);
+///
+/// let err = engine.eval_ast::<rhai::Dynamic>(&ast).unwrap_err();
+/// let better = context.improve(&err);
+/// assert_eq!(better.help.as_deref(), Some("Unknown variable 'prce'."));
+///
+/// // Reject large scripts before spending any work on them.
+/// let small = ImproveOptions::new().max_source_bytes(8);
+/// let too_large = small.improve_eval_error(&err, code, &engine, None, None).unwrap_err();
+/// assert_eq!(
+///     too_large.downcast_ref::<TraceError>(),
+///     Some(&TraceError::SourceTooLarge { size: code.len(), max: 8 })
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImproveOptions {
+    pub(crate) max_source_bytes: usize,
+    verbosity: Verbosity,
+}
+
+impl Default for ImproveOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ImproveOptions {
+    /// Creates the default options, accepting scripts of up to
+    /// [`DEFAULT_MAX_SOURCE_BYTES`] and improving errors with [`Verbosity::Full`].
+    pub fn new() -> Self {
+        Self {
+            max_source_bytes: DEFAULT_MAX_SOURCE_BYTES,
+            verbosity: Verbosity::Full,
+        }
+    }
+
+    /// Sets the largest script, in bytes, that errors are improved for. Larger
+    /// scripts are rejected with [`TraceError::SourceTooLarge`].
+    /// Defaults to [`DEFAULT_MAX_SOURCE_BYTES`].
+    pub fn max_source_bytes(mut self, max_source_bytes: usize) -> Self {
+        self.max_source_bytes = max_source_bytes;
+        self
+    }
+
+    /// Sets how much work improving an error spends on suggestions.
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Like [`BetterError::improve_eval_error`], with these options.
+    pub fn improve_eval_error(
+        &self,
+        error: &EvalAltResult,
+        code: &str,
+        engine: &Engine,
+        tracked_spans: Option<Vec<Span>>,
+        scope: Option<&Scope>,
+    ) -> Result<BetterError, Box<dyn Error>> {
+        self.improve_eval_error_with_messages(
+            error,
+            code,
+            engine,
+            tracked_spans,
+            scope,
+            &DiagnosticMessages::new(),
+        )
+    }
+
+    /// Like [`BetterError::improve_eval_error_with_messages`], with these options.
+    pub fn improve_eval_error_with_messages(
+        &self,
+        error: &EvalAltResult,
+        code: &str,
+        engine: &Engine,
+        tracked_spans: Option<Vec<Span>>,
+        scope: Option<&Scope>,
+        messages: &DiagnosticMessages,
+    ) -> Result<BetterError, Box<dyn Error>> {
+        check_source_size(code, self.max_source_bytes)?;
+
+        // Compile at most once, sharing the `AST` between span extraction and
+        // function suggestions.
+        match tracked_spans {
+            Some(spans) => Ok(BetterError::improve_with(
+                error,
+                code,
+                || engine.compile(&*lexer::compilable(code)).map(Cow::Owned),
+                &spans,
+                Some(engine),
+                scope,
+                messages,
+                *self,
+            )),
+            None => {
+                let ast = engine.compile(&*lexer::compilable(code))?;
+                let spans = SpanTracer::spans_from_ast(&ast, code, engine, WalkOptions::default())?;
+
+                Ok(BetterError::improve_with(
+                    error,
+                    code,
+                    || Ok(Cow::Borrowed(&ast)),
+                    &spans,
+                    Some(engine),
+                    scope,
+                    messages,
+                    *self,
+                ))
+            }
+        }
+    }
+
+    /// Like [`BetterError::improve_eval_error_cached`], with these options. Spans
+    /// cached for another size limit are not shared.
+    pub fn improve_eval_error_cached(
+        &self,
+        error: &EvalAltResult,
+        code: &str,
+        engine: &Engine,
+        cache: &SpanCache,
+        scope: Option<&Scope>,
+    ) -> Result<BetterError, Box<dyn Error>> {
+        check_source_size(code, self.max_source_bytes)?;
+
+        let settings = ExtractSettings {
+            level: engine.optimization_level(),
+            walk: WalkOptions::default(),
+            max_source_bytes: self.max_source_bytes,
+        };
+        let spans = cache.get_or_extract(code, settings, || {
+            let ast = engine.compile(&*lexer::compilable(code))?;
+            SpanTracer::spans_from_ast(&ast, code, engine, settings.walk)
+        })?;
+
+        Ok(BetterError::improve_with(
+            error,
+            code,
+            || engine.compile(&*lexer::compilable(code)).map(Cow::Owned),
+            &spans,
+            Some(engine),
+            scope,
+            &DiagnosticMessages::new(),
+            *self,
+        ))
+    }
+
+    /// Like [`BetterError::improve_eval_error_with_ast`], with these options.
+    pub fn improve_eval_error_with_ast(
+        &self,
+        error: &EvalAltResult,
+        code: &str,
+        engine: &Engine,
+        ast: &AST,
+    ) -> Result<BetterError, Box<dyn Error>> {
+        check_source_size(code, self.max_source_bytes)?;
+
+        let spans = SpanTracer::spans_from_ast(ast, code, engine, WalkOptions::default())?;
+
+        Ok(BetterError::improve_with(
+            error,
+            code,
+            || Ok(Cow::Borrowed(ast)),
+            &spans,
+            Some(engine),
+            None,
+            &DiagnosticMessages::new(),
+            *self,
+        ))
+    }
+
+    /// Like [`BetterError::improve_parse_error`], with these options.
+    pub fn improve_parse_error(
+        &self,
+        error: &ParseError,
+        code: &str,
+    ) -> Result<BetterError, Box<dyn Error>> {
+        check_source_size(code, self.max_source_bytes)?;
+
+        let pos = error.position();
+        let span = Span::from_pos(code, &pos).trim(code);
+
+        Ok(BetterError::builder()
+            .message(error.to_string())
+            .help("Syntax error detected.")
+            .hint("Check for missing tokens, unmatched parentheses, or invalid constructs.")
+            .span(span)
+            .code(ErrorCode::SyntaxError)
+            .build())
+    }
+
+    /// Like [`BetterError::improve_compile_error`], with these options. Also returns
+    /// `None` for a script larger than the size limit.
+    pub fn improve_compile_error(
+        &self,
+        err: &(dyn Error + 'static),
+        code: &str,
+    ) -> Option<BetterError> {
+        if let Some(error) = err.downcast_ref::<ParseError>() {
+            return self.improve_parse_error(error, code).ok();
+        }
+        match err.downcast_ref::<EvalAltResult>() {
+            Some(EvalAltResult::ErrorParsing(kind, pos)) => self
+                .improve_parse_error(&ParseError(Box::new(kind.clone()), *pos), code)
+                .ok(),
+            _ => None,
+        }
+    }
+
+    /// Like [`BetterError::improve_all_parse_errors`], with these options.
+    pub fn improve_all_parse_errors(
+        &self,
+        code: &str,
+        engine: &Engine,
+    ) -> Result<Vec<BetterError>, Box<dyn Error>> {
+        check_source_size(code, self.max_source_bytes)?;
+
+        let mut errors = Vec::new();
+        let mut masked = lexer::compilable(code).into_owned();
+        let mut last_line = 0;
+
+        while let Err(error) = engine.compile(&masked) {
+            let pos = error.position();
+            let line = pos.line().unwrap_or(0);
+            // No progress: anything further is most likely a cascade.
+            if line <= last_line && !errors.is_empty() {
+                break;
+            }
+            errors.push(self.improve_parse_error(&error, code)?);
+            if line == 0 {
+                break;
+            }
+
+            last_line = line;
+            masked = blank_line(&masked, line);
+        }
+
+        Ok(errors)
+    }
+}
+
+/// Formats like `format!`, but allocates the string once, at its final length,
+/// where `format!` may have to grow it several times.
+macro_rules! format_exact {
+    ($($arg:tt)*) => {
+        $crate::error::format_exact_args(format_args!($($arg)*))
+    };
+}
+
+/// Writes `args` into a string allocated once, see `format_exact!`.
+pub(crate) fn format_exact_args(args: fmt::Arguments<'_>) -> String {
+    /// Counts the bytes written to it.
+    struct Length(usize);
+
+    impl fmt::Write for Length {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0 += s.len();
+            Ok(())
+        }
+    }
+
+    let mut length = Length(0);
+    // A failing `Display` implementation fails again below.
+    let _ = fmt::write(&mut length, args);
+    let mut text = String::with_capacity(length.0);
+    fmt::write(&mut text, args).expect("a Display implementation returned an error unexpectedly");
+    text
+}
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Severity {
+    /// Informational, nothing is wrong.
+    Note,
+    /// Valid code that is most likely a mistake, such as a [`Lint`](crate::Lint).
+    Warning,
+    /// The script cannot be compiled or failed to run.
+    #[default]
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
 
 /// A structure containing all the information that you would need
 /// to print pretty errors. 
@@ -9,135 +406,856 @@ use std::error::Error;
 /// It can easily be plugged into crates like [`ariadne`][ariadne].
 ///
 /// [ariadne]: https://docs.rs/ariadne/latest/ariadne/
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BetterError {
     pub message: String,
     pub help: Option<String>,
     pub hint: Option<String>,
     pub note: Option<String>,
     pub span: Span,
+    /// What kind of problem this is, e.g. [`ErrorCode::VariableNotFound`].
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub code: Option<ErrorCode>,
+    /// Secondary locations related to the error, each with a message, e.g. the
+    /// declaration of a parameter that was passed a value of the wrong type.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub labels: Vec<(Span, String)>,
+    /// How serious the error is. Control flow that escaped the script, such as a
+    /// `return` or `break`, is only a [`Note`](Severity::Note).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub severity: Severity,
+}
+
+impl fmt::Display for BetterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for BetterError {}
+
+/// Builds a [`BetterError`] field by field, e.g. to report problems found by the
+/// host in the same form as improved Rhai errors, or to adjust an improved error
+/// before rendering it.
+///
+/// Unset fields are left empty: no help, hint, note or labels, [`Span::NONE`],
+/// no [`ErrorCode`] and a severity of [`Severity::Error`].
+///
+/// # Example
+///
+/// ```rust
+/// use rhai_trace::{BetterError, Span};
+///
+/// let code = "let port = \"80\";";
+/// let error = BetterError::builder()
+///     .message("`port` must be a number")
+///     .span(Span::from_range(code, 11..15))
+///     .help("Remove the quotes.")
+///     .build();
+/// assert_eq!(error.help.as_deref(), Some("Remove the quotes."));
+///
+/// // Errors can be changed again, e.g. to attach a note to an improved error.
+/// let error = error.into_builder().note("Ports are numbers.").build();
+/// assert_eq!(error.note.as_deref(), Some("Ports are numbers."));
+/// assert_eq!(error.code, None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct BetterErrorBuilder {
+    error: BetterError,
+}
+
+impl BetterErrorBuilder {
+    /// Sets the main message of the error.
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.error.message = message.into();
+        self
+    }
+    /// Sets the help text, replacing any help set before.
+    pub fn help(mut self, help: impl Into<String>) -> Self {
+        self.error.help = Some(help.into());
+        self
+    }
+    /// Sets the hint, replacing any hint set before.
+    pub fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.error.hint = Some(hint.into());
+        self
+    }
+    /// Sets the note, replacing any note set before.
+    pub fn note(mut self, note: impl Into<String>) -> Self {
+        self.error.note = Some(note.into());
+        self
+    }
+    /// Sets the span the error points at.
+    pub fn span(mut self, span: Span) -> Self {
+        self.error.span = span;
+        self
+    }
+    /// Sets what kind of problem the error is.
+    pub fn code(mut self, code: ErrorCode) -> Self {
+        self.error.code = Some(code);
+        self
+    }
+    /// Sets how serious the error is.
+    pub fn severity(mut self, severity: Severity) -> Self {
+        self.error.severity = severity;
+        self
+    }
+    /// Adds a secondary location with a message, after the labels added before.
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.error.labels.push((span, message.into()));
+        self
+    }
+    /// Returns the built error.
+    pub fn build(self) -> BetterError {
+        self.error
+    }
 }
 
 impl BetterError {
+    /// Returns a builder for an error with every field empty.
+    pub fn builder() -> BetterErrorBuilder {
+        BetterErrorBuilder {
+            error: BetterError {
+                message: String::new(),
+                help: None,
+                hint: None,
+                note: None,
+                span: Span::NONE,
+                code: None,
+                labels: Vec::new(),
+                severity: Severity::Error,
+            },
+        }
+    }
+
+    /// Returns a builder starting from this error, to change some of its fields.
+    pub fn into_builder(self) -> BetterErrorBuilder {
+        BetterErrorBuilder { error: self }
+    }
+
     /// Return a more informative Rhai evaluation error.
+    ///
+    /// Without `tracked_spans`, the script is compiled with `engine`, so custom syntax
+    /// and operators registered on it are understood.
+    ///
+    /// Pass the `scope` the script ran with so that suggestions for unknown variables
+    /// also consider the variables it holds, e.g. ones pushed by the host. The hint
+    /// then also lists the variables in the scope.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai::{Engine, Scope};
+    /// use rhai_trace::BetterError;
+    ///
+    /// let engine = Engine::new();
+    /// let mut scope = Scope::new();
+    /// scope.push("player_name", "Ada");
+    ///
+    /// let code = "print(player_nme);";
+    /// let err = engine.run_with_scope(&mut scope, code).unwrap_err();
+    /// let better = BetterError::improve_eval_error(&err, code, &engine, None, Some(&scope)).unwrap();
+    ///
+    /// assert_eq!(
+    ///     better.hint.as_deref(),
+    ///     Some("Did you mean `player_name`?\nVariables in scope: `player_name`.")
+    /// );
+    /// ```
     pub fn improve_eval_error(
         error: &EvalAltResult,
         code: &str,
         engine: &Engine,
-        tracked_spans: Option<Vec<Span>>
+        tracked_spans: Option<Vec<Span>>,
+        scope: Option<&Scope>,
     ) -> Result<Self, Box<dyn Error>> {
-        let pos = get_root_cause(error).position();
-        let line = pos.line().unwrap_or(0);
-        let column = pos.position().unwrap_or(1);
-        let help_hint = get_error_info(get_root_cause(error), error, engine, code);
+        ImproveOptions::new().improve_eval_error(error, code, engine, tracked_spans, scope)
+    }
 
-        let spans = match tracked_spans {
-            Some(s) => s,
-            None => {
-                let span_tracer = SpanTracer::new();
-                span_tracer.extract_from(code)?
+    /// Return a more informative Rhai evaluation error like
+    /// [`improve_eval_error`](BetterError::improve_eval_error), with the help and
+    /// hint taken from `messages` wherever they have a template for the error's code.
+    pub fn improve_eval_error_with_messages(
+        error: &EvalAltResult,
+        code: &str,
+        engine: &Engine,
+        tracked_spans: Option<Vec<Span>>,
+        scope: Option<&Scope>,
+        messages: &DiagnosticMessages,
+    ) -> Result<Self, Box<dyn Error>> {
+        ImproveOptions::new().improve_eval_error_with_messages(
+            error,
+            code,
+            engine,
+            tracked_spans,
+            scope,
+            messages,
+        )
+    }
+
+    /// Return a more informative Rhai evaluation error like
+    /// [`improve_eval_error`](BetterError::improve_eval_error) without tracked spans,
+    /// extracting the spans of `code` only once for all calls sharing `cache`.
+    ///
+    /// The script is only compiled again when a suggestion needs its functions.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai::Engine;
+    /// use rhai_trace::{BetterError, SpanCache};
+    ///
+    /// let cache = SpanCache::new(128);
+    /// let engine = Engine::new();
+    /// let code = "let a = 1;\nlet b = a + c;";
+    ///
+    /// for _ in 0..3 {
+    ///     let err = engine.run(code).unwrap_err();
+    ///     let better = BetterError::improve_eval_error_cached(&err, code, &engine, &cache, None).unwrap();
+    ///     assert_eq!(better.span.line(), 2);
+    /// }
+    /// assert_eq!(cache.len(), 1);
+    /// ```
+    pub fn improve_eval_error_cached(
+        error: &EvalAltResult,
+        code: &str,
+        engine: &Engine,
+        cache: &SpanCache,
+        scope: Option<&Scope>,
+    ) -> Result<Self, Box<dyn Error>> {
+        ImproveOptions::new().improve_eval_error_cached(error, code, engine, cache, scope)
+    }
+
+    /// Return a more informative Rhai evaluation error, reusing the `AST` the script
+    /// was compiled to instead of compiling it again.
+    ///
+    /// The result is the same as that of [`improve_eval_error`](BetterError::improve_eval_error)
+    /// without tracked spans or a scope, but neither span extraction nor suggestions compile the script.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::BetterError;
+    ///
+    /// let code = "let a = 1;\nlet b = a + c;";
+    /// let engine = rhai::Engine::new();
+    /// let ast = engine.compile(code).unwrap();
+    ///
+    /// let err = engine.eval_ast::<rhai::Dynamic>(&ast).unwrap_err();
+    /// let better = BetterError::improve_eval_error_with_ast(&err, code, &engine, &ast).unwrap();
+    /// assert_eq!(better.span.line(), 2);
+    /// ```
+    pub fn improve_eval_error_with_ast(
+        error: &EvalAltResult,
+        code: &str,
+        engine: &Engine,
+        ast: &AST,
+    ) -> Result<Self, Box<dyn Error>> {
+        ImproveOptions::new().improve_eval_error_with_ast(error, code, engine, ast)
+    }
+
+    /// Builds the improved error from already extracted spans.
+    ///
+    /// `ast` is only called when a suggestion needs to look at the script's
+    /// function definitions, so callers holding a compiled [`AST`] can hand
+    /// it out without compiling or allocating.
+    ///
+    /// Without an `engine`, custom operators cannot be told apart from functions.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn improve_with<'a>(
+        error: &EvalAltResult,
+        code: &str,
+        ast: impl FnOnce() -> Result<Cow<'a, AST>, ParseError>,
+        spans: &[Span],
+        engine: Option<&Engine>,
+        scope: Option<&Scope>,
+        messages: &DiagnosticMessages,
+        options: ImproveOptions,
+    ) -> Self {
+        let (pos, module) = script_position(error);
+        let verbosity = options.verbosity;
+        let full = verbosity == Verbosity::Full;
+        // Arithmetic errors need the `AST` to find the operation that failed, and
+        // stack overflows to find the recursion, but neither for their help.
+        let mut operation = None;
+        let mut cycle = None;
+        let mut help_hint = match root_cause(error) {
+            EvalAltResult::ErrorArithmetic(message, _) if module.is_none() && full => {
+                let ast = ast();
+                if let Ok(ast) = &ast {
+                    let operations = match engine {
+                        Some(engine) => SpanTracer::binary_operations(ast, code, engine),
+                        None => SpanTracer::binary_operations(ast, code, &Engine::new_raw()),
+                    };
+                    operation = arithmetic_operation(&operations, code, message, &pos);
+                }
+                get_error_info(root_cause(error), move || ast, engine, scope, verbosity)
             }
+            EvalAltResult::ErrorStackOverflow(_) if module.is_none() && full => {
+                let ast = ast();
+                if let Ok(ast) = &ast {
+                    let graph = match engine {
+                        Some(engine) => CallGraph::new(ast, code, engine),
+                        None => CallGraph::new(ast, code, &Engine::new_raw()),
+                    };
+                    cycle = recursion_cycle(&graph, code, error, &pos);
+                }
+                get_error_info(root_cause(error), move || ast, engine, scope, verbosity)
+            }
+            root => get_error_info(root, ast, engine, scope, verbosity),
         };
-        let span = Self::find_span_for_position(&spans, line, column)
-            .unwrap_or(Span::new(0, 0, line, column));
+        if let Some((functions, _)) = &cycle {
+            help_hint.hint = format!(
+                "Recursion cycle: {}. Make sure one of these calls stops at a base case.",
+                functions
+            );
+        }
 
-        Ok(BetterError {
-            message: error.to_string(),
-            help: if help_hint.help.is_empty() {
-                None
-            } else {
-                Some(help_hint.help)
-            },
-            hint: if help_hint.hint.is_empty() {
-                None
-            } else {
-                Some(help_hint.hint)
-            },
-            note: if help_hint.note.is_empty() {
-                None
-            } else {
-                Some(help_hint.note)
-            },
-            span,
-        })
+        let root = root_cause(error);
+        if let Some(template) = error_code(root).and_then(|code| messages.template(code)) {
+            if let Some(help) = &template.help {
+                help_hint.help = messages::render(help, root);
+            }
+            if let Some(hint) = &template.hint {
+                help_hint.hint = messages::render(hint, root);
+            }
+        }
+        if let Some(mapped) = help_hint.generic.then(|| messages.map(root)).flatten() {
+            if let Some(help) = mapped.help {
+                help_hint.help = help;
+            }
+            if let Some(hint) = mapped.hint {
+                help_hint.hint = hint;
+            }
+        }
+
+        // Underline only the module path, the function name, the failed operation
+        // or the index out of bounds, not the whole statement.
+        let bounds = match root_cause(error) {
+            EvalAltResult::ErrorArrayBounds(..) | EvalAltResult::ErrorStringBounds(..)
+                if module.is_none() && full =>
+            {
+                let lexemes = match engine {
+                    Some(engine) => lexer::tokenize(engine, code),
+                    None => lexer::tokenize(&Engine::new_raw(), code),
+                };
+                index_operand(spans, &lexemes, code, &pos)
+            }
+            _ => None,
+        };
+        let precise = match root_cause(error) {
+            EvalAltResult::ErrorModuleNotFound(..) => Some(SpanKind::ImportPath),
+            EvalAltResult::ErrorFunctionNotFound(..) => Some(SpanKind::FnName),
+            _ => None,
+        };
+        // Without a tracked span at the position, fall back to the rest of its line,
+        // or to `Span::NONE` if the error has no position at all.
+        let source_id = spans.iter().find_map(Span::source_id);
+        // A missing variable is a single identifier, where spans of the tracked
+        // expression would run on to the end of the statement.
+        let variable = match root_cause(error) {
+            EvalAltResult::ErrorVariableNotFound(..) if module.is_none() => {
+                let token = Span::from_pos_token(code, &pos);
+                (!token.is_none()).then(|| token.start()..token.end())
+            }
+            _ => None,
+        };
+        let span = operation
+            .map(|range| (range, SpanKind::Operator))
+            .or_else(|| bounds.map(|range| (range, SpanKind::Index)))
+            .or_else(|| variable.map(|range| (range, SpanKind::Variable)))
+            .map(|(range, kind)| {
+                Span::from_range(code, range)
+                    .with_kind(kind)
+                    .with_source_id(source_id)
+            })
+            .or_else(|| precise.and_then(|kind| Self::find_span_of_kind(spans, code, &pos, kind)))
+            .or_else(|| Self::find_span_for_position(spans, code, &pos))
+            .unwrap_or_else(|| Span::from_pos(code, &pos).with_source_id(source_id))
+            .trim(code);
+
+        let note = error_note(error, module);
+
+        let mut builder = BetterError::builder()
+            .message(format_exact!("{}", error))
+            .severity(match root_cause(error) {
+                EvalAltResult::Return(..) | EvalAltResult::LoopBreak(..) => Severity::Note,
+                _ => Severity::Error,
+            });
+        if !help_hint.help.is_empty() {
+            builder = builder.help(help_hint.help);
+        }
+        if !help_hint.hint.is_empty() {
+            builder = builder.hint(help_hint.hint);
+        }
+        if !note.is_empty() {
+            builder = builder.note(note);
+        }
+        if let Some(code) = error_code(root_cause(error)) {
+            builder = builder.code(code);
+        }
+        if let Some((label_span, label)) = secondary_label(error, code, &span, spans, verbosity) {
+            builder = builder.with_label(label_span, label);
+        }
+        for (label_span, label) in cycle.map(|(_, calls)| calls).unwrap_or_default() {
+            builder = builder.with_label(label_span.with_source_id(source_id), label);
+        }
+
+        builder.span(span).build()
     }
 
     /// Return a more informative Rhai parse error.
     pub fn improve_parse_error(error: &ParseError, code: &str) -> Result<Self, Box<dyn Error>> {
-        let pos = error.position();
-        let span = Span::from_pos(code, &pos);
+        ImproveOptions::new().improve_parse_error(error, code)
+    }
 
-        Ok(BetterError {
-            message: error.to_string(),
-            help: Some("Syntax error detected.".into()),
-            hint: Some(
-                "Check for missing tokens, unmatched parentheses, or invalid constructs.".into(),
-            ),
-            note: None,
-            span,
+    /// Return a more informative error for a failure to compile `code`, e.g. one
+    /// returned by [`SpanTracer`] methods, `engine.compile` or `engine.eval`. Pass
+    /// boxed errors as `&*err`.
+    ///
+    /// Both a [`ParseError`] and an `EvalAltResult::ErrorParsing` are improved like
+    /// with [`improve_parse_error`](BetterError::improve_parse_error). Returns `None`
+    /// for any other error, e.g. a [`TraceError`] or an error raised while running.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::{BetterError, SpanTracer};
+    ///
+    /// let code = "let a = ;";
+    /// let err = SpanTracer::new().extract_from(code).unwrap_err();
+    ///
+    /// let better = BetterError::improve_compile_error(&*err, code).unwrap();
+    /// assert_eq!(better.span.line(), 1);
+    /// ```
+    pub fn improve_compile_error(err: &(dyn Error + 'static), code: &str) -> Option<Self> {
+        ImproveOptions::new().improve_compile_error(err, code)
+    }
+
+    /// Returns an improved error for every independent syntax error in `code`, in
+    /// source order. Returns an empty `Vec` if the script compiles.
+    ///
+    /// Rhai stops at the first parse error, so recovery is best-effort: after each
+    /// error, the line it points at is blanked out and the script is compiled again.
+    /// Blanking keeps every other line and column where it was, so the errors point
+    /// into the original `code`. Collection stops once an error no longer lies past
+    /// the previous one, or has no position, as later errors would likely only be
+    /// caused by the earlier ones. An error reported on the line after the actual
+    /// mistake, such as a missing `;`, can hide an error on that line.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai::Engine;
+    /// use rhai_trace::BetterError;
+    ///
+    /// let code = "let a = 1 +;\nlet b = 2;\nlet c = (b;";
+    /// let errors = BetterError::improve_all_parse_errors(code, &Engine::new()).unwrap();
+    ///
+    /// let lines: Vec<usize> = errors.iter().map(|error| error.span.line()).collect();
+    /// assert_eq!(lines, [1, 3]);
+    /// ```
+    pub fn improve_all_parse_errors(
+        code: &str,
+        engine: &Engine,
+    ) -> Result<Vec<Self>, Box<dyn Error>> {
+        ImproveOptions::new().improve_all_parse_errors(code, engine)
+    }
+
+    /// Returns the span at `pos` as chosen by [`SpanTracer::span_at`], so that the
+    /// underline covers the failing sub-expression rather than the statement around it.
+    fn find_span_for_position(spans: &[Span], code: &str, pos: &Position) -> Option<Span> {
+        let line = pos.line()?;
+        let column = pos.position().unwrap_or(1);
+
+        SpanTracer::find_span(spans, code, line_index::scan_offset(code, line, column))
+    }
+
+    /// Returns the span of `kind` covering `pos`, if any.
+    fn find_span_of_kind(spans: &[Span], code: &str, pos: &Position, kind: SpanKind) -> Option<Span> {
+        let line = pos.line()?;
+        let offset = line_index::scan_offset(code, line, pos.position().unwrap_or(1));
+
+        spans
+            .iter()
+            .find(|span| span.kind() == kind && span.contains_offset(offset))
+            .copied()
+    }
+}
+
+/// Replaces every character of the 1-based `line` of `code` with a space, keeping
+/// the lines and columns of everything else unchanged.
+pub(crate) fn blank_line(code: &str, line: usize) -> String {
+    code.split_inclusive('\n')
+        .enumerate()
+        .map(|(i, text)| {
+            if i + 1 != line {
+                return text.to_string();
+            }
+            let content = text.trim_end_matches(['\r', '\n']);
+            " ".repeat(content.chars().count()) + &text[content.len()..]
         })
+        .collect()
+}
+
+/// Returns the position in the script that best locates `err`: the position of
+/// the failing expression inside the called functions, rather than that of the
+/// outermost call.
+///
+/// Positions inside a module are relative to the module's own source, so for an
+/// error raised in a module, the position of the call or `import` in the script
+/// is returned instead.
+///
+/// # Example
+///
+/// ```rust
+/// use rhai::Engine;
+/// use rhai_trace::error::deepest_position;
+///
+/// let code = "fn inner(x) { x + missing }\nfn outer(x) { inner(x) }\nouter(1);";
+/// let err = Engine::new().run(code).unwrap_err();
+///
+/// // The call on line 3 failed, because of `missing` on line 1.
+/// assert_eq!(err.position().line(), Some(3));
+/// assert_eq!(deepest_position(&err).line(), Some(1));
+/// assert_eq!(deepest_position(&err).position(), Some(19));
+/// ```
+pub fn deepest_position(err: &EvalAltResult) -> Position {
+    script_position(err).0
+}
+
+/// Returns the deepest usable position of the error in the script itself, and
+/// the module the root cause was raised in if it lies elsewhere.
+///
+/// Inside a function body, the failing expression is preferred over the call,
+/// unless the inner error carries no position. Positions inside a module are
+/// relative to the module's own source, so for those the position of the call
+/// or `import` in the script is used instead.
+fn script_position(err: &EvalAltResult) -> (Position, Option<&str>) {
+    match err {
+        EvalAltResult::ErrorInFunctionCall(_, source, _, pos) if !source.is_empty() => {
+            (*pos, Some(source))
+        }
+        EvalAltResult::ErrorInFunctionCall(_, _, inner, pos) => match script_position(inner) {
+            (inner_pos, module) if inner_pos.is_none() => (*pos, module),
+            deepest => deepest,
+        },
+        EvalAltResult::ErrorInModule(name, _, pos) => (*pos, Some(name)),
+        _ => (err.position(), None),
     }
+}
 
-    fn find_span_for_position(spans: &[Span], line: usize, column: usize) -> Option<Span> {
-        if let Some(span) = spans.iter().find(|span| {
-            span.line() == line
-                && span.column() <= column
-                && column <= span.column() + (span.end() - span.start())
-        }) {
-            return Some(span.clone());
+/// Returns the byte range of the operation among `operations` that raised an
+/// arithmetic error with `message`, e.g. `Division by zero: 1 / 0`.
+///
+/// Rhai's built-in operators raise these errors without a position, so unless
+/// `pos` is at an operator, the operation is the only one with the operator in
+/// `message`, or the only one whose operands are written like the values there.
+fn arithmetic_operation(
+    operations: &[BinaryOperation],
+    code: &str,
+    message: &str,
+    pos: &Position,
+) -> Option<Range<usize>> {
+    if !pos.is_none() {
+        let offset = pos_offset(code, pos);
+        if let Some(operation) = operations.iter().find(|op| op.operator == offset) {
+            return Some(operation.range.clone());
         }
+    }
+
+    let (_, values) = message.rsplit_once(": ")?;
+    let mut candidates: Vec<(usize, &BinaryOperation)> = operations
+        .iter()
+        .filter_map(|op| {
+            let (lhs, rhs) = values.split_once(&format!(" {} ", op.name))?;
+            let matches = usize::from(code[op.lhs.clone()].trim() == lhs)
+                + usize::from(code[op.rhs.clone()].trim() == rhs);
+            Some((matches, op))
+        })
+        .collect();
+    candidates.sort_by_key(|(matches, _)| Reverse(*matches));
+
+    match candidates.as_slice() {
+        [(_, only)] => Some(only.range.clone()),
+        [(best, op), (next, _), ..] if best > next => Some(op.range.clone()),
+        _ => None,
+    }
+}
+
+/// Returns the byte range of the index that a bounds error at `pos` was raised
+/// for, e.g. `5` in `a[5]`, found inside the brackets of the innermost tracked
+/// [`SpanKind::Index`] span around `pos`.
+fn index_operand(
+    spans: &[Span],
+    lexemes: &[lexer::Lexeme],
+    code: &str,
+    pos: &Position,
+) -> Option<Range<usize>> {
+    if pos.is_none() {
+        return None;
+    }
+    let offset = pos_offset(code, pos);
 
-        spans.iter().find(|span| span.line() == line).cloned()
+    // Indexing spans start at their `[`, after the indexed value.
+    let open = spans
+        .iter()
+        .filter(|span| span.kind() == SpanKind::Index && span.start() < offset)
+        .filter(|span| span.contains_offset(offset))
+        .map(Span::start)
+        .max()?;
+    let open = lexer::lexeme_at(lexemes, open);
+    if lexemes.get(open)?.token != Token::LeftBracket {
+        return None;
     }
+    lexer::bracket_contents(lexemes, open).filter(|contents| contents.contains(&offset))
 }
 
-fn get_root_cause<'a>(err: &'a EvalAltResult) -> &'a EvalAltResult {
+/// Returns the recursion in `graph` that overflowed the call stack with `err`,
+/// as the functions along it, e.g. `render -> layout -> render`, along with a
+/// label on every call in it.
+///
+/// The recursion starts at the function called at `pos`, or else at the first
+/// recursive function `err` was raised in.
+fn recursion_cycle(
+    graph: &CallGraph,
+    code: &str,
+    err: &EvalAltResult,
+    pos: &Position,
+) -> Option<(String, Vec<(Span, String)>)> {
+    let called = match pos.is_none() {
+        true => None,
+        false => graph
+            .call_at(pos_offset(code, pos))
+            .and_then(|call| call.callee),
+    };
+    let mut raised_in = Vec::new();
+    let mut current = err;
+    while let EvalAltResult::ErrorInFunctionCall(name, _, inner, _) = current {
+        raised_in.extend(graph.functions.iter().position(|f| f.name == *name));
+        current = inner;
+    }
+
+    let calls = called
+        .into_iter()
+        .chain(raised_in)
+        .find_map(|function| graph.cycle_from(function))?;
+    let name = |function: usize| graph.functions[function].name.as_str();
+
+    let mut functions = vec![name(calls.first()?.caller?)];
+    let mut labels = Vec::new();
+    for call in calls {
+        let (caller, callee) = (call.caller?, call.callee?);
+        functions.push(name(callee));
+        labels.push((
+            call.span,
+            format!("`{}` calls `{}` here", name(caller), name(callee)),
+        ));
+    }
+    Some((functions.join(" -> "), labels))
+}
+
+/// Returns the error that caused `err`, unwrapping the errors Rhai wraps
+/// around it while it propagates out of function calls and modules.
+///
+/// # Example
+///
+/// ```rust
+/// use rhai::{Engine, EvalAltResult};
+/// use rhai_trace::error::root_cause;
+///
+/// let code = "fn inner(x) { x + missing }\nfn outer(x) { inner(x) }\nouter(1);";
+/// let err = Engine::new().run(code).unwrap_err();
+///
+/// assert!(matches!(*err, EvalAltResult::ErrorInFunctionCall(..)));
+/// assert!(matches!(root_cause(&err), EvalAltResult::ErrorVariableNotFound(name, _) if name == "missing"));
+/// ```
+pub fn root_cause(err: &EvalAltResult) -> &EvalAltResult {
     match err {
-        EvalAltResult::ErrorInFunctionCall(_, _, inner, _) => get_root_cause(inner),
-        EvalAltResult::ErrorInModule(_, inner, _) => get_root_cause(inner),
+        EvalAltResult::ErrorInFunctionCall(_, _, inner, _) => root_cause(inner),
+        EvalAltResult::ErrorInModule(_, inner, _) => root_cause(inner),
         _ => err,
     }
 }
 
-fn get_error_info(
-    root_err: &EvalAltResult,
-    outer_err: &EvalAltResult,
-    engine: &Engine,
+/// Returns a secondary label for an error whose primary span is `span`, if any.
+///
+/// When a value of the wrong type fails inside a script function and the failing
+/// expression is one of its parameters, the parameter's declaration is labeled,
+/// unless `verbosity` is [`Verbosity::Minimal`]. When a `throw` inside a `catch`
+/// block raised the error, the block is labeled.
+fn secondary_label(
+    err: &EvalAltResult,
     code: &str,
+    span: &Span,
+    spans: &[Span],
+    verbosity: Verbosity,
+) -> Option<(Span, String)> {
+    match root_cause(err) {
+        EvalAltResult::ErrorMismatchDataType(..) if verbosity == Verbosity::Full => {
+            let function = innermost_function(err)?;
+            let name = identifier_at(code, span)?;
+            parameter_span(code, function, name)
+                .map(|param| (param, format!("parameter `{}` is declared here", name)))
+        }
+        EvalAltResult::ErrorRuntime(..) if !span.is_none() => spans
+            .iter()
+            .filter(|block| block.kind() == SpanKind::CatchBlock)
+            .filter(|block| block.contains_offset(span.start()))
+            .min_by_key(|block| block.len())
+            .map(|block| (*block, "the error was re-thrown from this catch block".into())),
+        _ => None,
+    }
+}
+
+/// Returns the name of the script function the root cause was raised in, if any.
+fn innermost_function(err: &EvalAltResult) -> Option<&str> {
+    match err {
+        EvalAltResult::ErrorInFunctionCall(name, source, inner, _) if source.is_empty() => {
+            innermost_function(inner).or(Some(name))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the identifier that `span` starts with, if any.
+pub(crate) fn identifier_at<'a>(code: &'a str, span: &Span) -> Option<&'a str> {
+    if span.is_none() {
+        return None;
+    }
+
+    let rest = code.get(span.start()..)?;
+    let len = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    let ident = &rest[..len];
+
+    match ident.chars().next() {
+        Some(c) if c.is_alphabetic() || c == '_' => Some(ident),
+        _ => None,
+    }
+}
+
+/// Finds the declaration of the parameter `param` in the signature of `function`.
+/// The script is tokenized, so comments and strings never match.
+fn parameter_span(code: &str, function: &str, param: &str) -> Option<Span> {
+    let lexemes = lexer::tokenize(&Engine::new_raw(), code);
+
+    lexemes.iter().enumerate().find_map(|(i, lexeme)| {
+        let (Token::Fn, Some(Token::Identifier(name))) =
+            (&lexeme.token, lexemes.get(i + 1).map(|next| &next.token))
+        else {
+            return None;
+        };
+        if name.as_str() != function {
+            return None;
+        }
+
+        lexemes[i + 2..]
+            .iter()
+            .take_while(|lexeme| lexeme.token != Token::LeftBrace)
+            .find(|lexeme| matches!(&lexeme.token, Token::Identifier(p) if p.as_str() == param))
+            .map(|lexeme| lexeme.span)
+    })
+}
+
+/// Returns the [`ErrorCode`] of an error raised in the script, if it has one.
+fn error_code(err: &EvalAltResult) -> Option<ErrorCode> {
+    match err {
+        EvalAltResult::ErrorParsing(..) => Some(ErrorCode::SyntaxError),
+        EvalAltResult::ErrorVariableNotFound(..) => Some(ErrorCode::VariableNotFound),
+        EvalAltResult::ErrorFunctionNotFound(..) => Some(ErrorCode::FunctionNotFound),
+        EvalAltResult::ErrorPropertyNotFound(..) => Some(ErrorCode::PropertyNotFound),
+        EvalAltResult::ErrorMismatchDataType(..) | EvalAltResult::ErrorMismatchOutputType(..) => {
+            Some(ErrorCode::DataTypeMismatch)
+        }
+        EvalAltResult::ErrorArrayBounds(..)
+        | EvalAltResult::ErrorStringBounds(..)
+        | EvalAltResult::ErrorBitFieldBounds(..) => Some(ErrorCode::IndexOutOfBounds),
+        EvalAltResult::ErrorArithmetic(..) => Some(ErrorCode::ArithmeticError),
+        EvalAltResult::ErrorAssignmentToConstant(..) => Some(ErrorCode::AssignmentToConstant),
+        EvalAltResult::ErrorModuleNotFound(..) => Some(ErrorCode::ModuleNotFound),
+        EvalAltResult::ErrorStackOverflow(..) => Some(ErrorCode::StackOverflow),
+        EvalAltResult::ErrorTooManyOperations(..) => Some(ErrorCode::TooManyOperations),
+        EvalAltResult::ErrorDataTooLarge(..) => Some(ErrorCode::DataTooLarge),
+        EvalAltResult::ErrorRuntime(..) => Some(ErrorCode::RuntimeError),
+        _ => None,
+    }
+}
+
+fn get_error_info<'a>(
+    root_err: &EvalAltResult,
+    ast: impl FnOnce() -> Result<Cow<'a, AST>, ParseError>,
+    engine: Option<&Engine>,
+    scope: Option<&Scope>,
+    verbosity: Verbosity,
 ) -> ErrorHelp {
+    let suggestions = verbosity == Verbosity::Full;
+    // Looking up custom operators and their overloads in the engine allocates.
+    let operator_engine = engine.filter(|_| suggestions);
+    // Whether the error has no texts of its own, leaving it to a `CustomErrorMapper`.
+    let mut generic = matches!(root_err, EvalAltResult::ErrorRuntime(..));
     let (help, hint) = match root_err {
         EvalAltResult::ErrorParsing(..) => (
             "Syntax error encountered while parsing.".into(),
             "Check for unmatched tokens, invalid constructs, or misplaced punctuation.".into(),
         ),
         EvalAltResult::ErrorVariableExists(name, ..) => (
-            format!("Variable '{}' is already defined.", name),
+            format_exact!("Variable '{}' is already defined.", name),
             "Remove or rename the duplicate declaration.".into(),
         ),
         EvalAltResult::ErrorForbiddenVariable(name, ..) => (
-            format!("Usage of forbidden variable '{}'.", name),
+            format_exact!("Usage of forbidden variable '{}'.", name),
             "Avoid using reserved or protected variable names.".into(),
         ),
-        EvalAltResult::ErrorVariableNotFound(name, ..) => (
-            format!("Unknown variable '{}'.", name),
+        EvalAltResult::ErrorVariableNotFound(name, ..) if !suggestions => (
+            format_exact!("Unknown variable '{}'.", name),
             "Check for typos or ensure the variable is initialized before use.".into(),
         ),
+        EvalAltResult::ErrorVariableNotFound(name, ..) => {
+            let hint = match closest_variable(ast().ok().as_deref(), scope, name) {
+                Some(suggestion) => format!("Did you mean `{}`?", suggestion),
+                None => "Check for typos or ensure the variable is initialized before use.".into(),
+            };
+            (
+                format!("Unknown variable '{}'.", name),
+                match scope.and_then(scope_variables) {
+                    Some(variables) => format!("{}\n{}", hint, variables),
+                    None => hint,
+                },
+            )
+        }
         EvalAltResult::ErrorPropertyNotFound(name, ..) => (
-            format!("Property '{}' not found on this object.", name),
+            format_exact!("Property '{}' not found on this object.", name),
             "Verify the property name and the object’s available fields.".into(),
         ),
+        EvalAltResult::ErrorFunctionNotFound(fn_sig, ..)
+            if is_operator(fn_sig.split('(').next().unwrap_or(fn_sig).trim(), operator_engine) =>
+        {
+            operator_help(fn_sig, operator_engine)
+        }
+        EvalAltResult::ErrorFunctionNotFound(fn_sig, ..) if !suggestions => (
+            format_exact!("Function '{}' not found.", fn_sig),
+            "Check spelling, module path, or argument count.".into(),
+        ),
         EvalAltResult::ErrorFunctionNotFound(fn_sig, ..) => {
             let base = fn_sig.split('(').next().unwrap_or(fn_sig).trim();
 
             // Might be a bit less performant but I gotta pay the price of
             // having "kinda good" errors with Rhai.
-            let ast = match engine.compile(code) {
+            let ast = match ast() {
                 Ok(ast) => ast,
                 Err(err) => {
                     return ErrorHelp {
                         help: format!("Failed to compile code for suggestions: {}", err),
                         hint: String::new(),
-                        note: String::new(),
+                        generic: false,
                     };
                 }
             };
@@ -151,7 +1269,27 @@ fn get_error_info(
                 })
                 .collect();
 
-            if !candidates.is_empty() {
+            // A same-named function that only differs in arity is the most common
+            // mistake, so say so directly instead of just listing the overloads.
+            let provided = argument_count(fn_sig);
+            let mut arities: Vec<usize> = ast
+                .iter_functions()
+                .filter(|f| f.name == base)
+                .map(|f| f.params.len())
+                .collect();
+            arities.sort();
+            arities.dedup();
+
+            if !candidates.is_empty() && !arities.contains(&provided) {
+                (
+                    format!("Function '{}' not found with this argument list.", fn_sig),
+                    format!(
+                        "{}\nIt is defined as:\n  {}",
+                        arity_mismatch(base, &arities, provided),
+                        candidates.join("\n  ")
+                    ),
+                )
+            } else if !candidates.is_empty() {
                 (
                     format!("Function '{}' not found with this argument list.", fn_sig),
                     format!("Did you mean one of:\n  {}", candidates.join("\n    ")),
@@ -164,15 +1302,15 @@ fn get_error_info(
             }
         }
         EvalAltResult::ErrorModuleNotFound(name, ..) => (
-            format!("Module '{}' could not be located.", name),
+            format_exact!("Module '{}' could not be located.", name),
             "Check that the path is correct, the module is imported, and its code is valid.".into(),
         ),
         EvalAltResult::ErrorInFunctionCall(fn_name, msg, ..) => (
-            format!("Error inside function '{}': {}", fn_name, msg),
+            format_exact!("Error inside function '{}': {}", fn_name, msg),
             "Inspect the function implementation and arguments passed.".into(),
         ),
         EvalAltResult::ErrorInModule(name, ..) => (
-            format!("Error while loading module '{}'.", name),
+            format_exact!("Error while loading module '{}'.", name),
             "Check the module code for syntax or runtime errors.".into(),
         ),
         EvalAltResult::ErrorUnboundThis(..) => (
@@ -180,33 +1318,33 @@ fn get_error_info(
             "Only use `this` inside methods or bound closures.".into(),
         ),
         EvalAltResult::ErrorMismatchDataType(found, expected, ..) => (
-            format!(
+            format_exact!(
                 "Data type mismatch: found '{}', expected '{}'.",
                 found, expected
             ),
             "Convert or cast values to the required type.".into(),
         ),
         EvalAltResult::ErrorMismatchOutputType(found, expected, ..) => (
-            format!(
+            format_exact!(
                 "Return type mismatch: found '{}', expected '{}'.",
                 found, expected
             ),
             "Ensure your function returns the correct type.".into(),
         ),
         EvalAltResult::ErrorIndexingType(typ, ..) => (
-            format!("Cannot index into value of type '{}'.", typ),
+            format_exact!("Cannot index into value of type '{}'.", typ),
             "Only arrays, maps, bitfields, or strings support indexing.".into(),
         ),
         EvalAltResult::ErrorArrayBounds(len, idx, ..) => (
-            format!("Array index {} out of bounds (0..{}).", idx, len),
+            format_exact!("Array index {} out of bounds (0..{}).", idx, len),
             "Use a valid index within the array’s range.".into(),
         ),
         EvalAltResult::ErrorStringBounds(len, idx, ..) => (
-            format!("String index {} out of bounds (0..{}).", idx, len),
+            format_exact!("String index {} out of bounds (0..{}).", idx, len),
             "Ensure you index only valid character positions.".into(),
         ),
         EvalAltResult::ErrorBitFieldBounds(len, idx, ..) => (
-            format!("Bitfield index {} out of bounds (0..{}).", idx, len),
+            format_exact!("Bitfield index {} out of bounds (0..{}).", idx, len),
             "Use a valid bit position within the bitfield’s size.".into(),
         ),
         EvalAltResult::ErrorFor(..) => (
@@ -214,15 +1352,15 @@ fn get_error_info(
             "Iterate only over arrays, strings, ranges, or iterators.".into(),
         ),
         EvalAltResult::ErrorDataRace(name, ..) => (
-            format!("Data race detected on '{}'.", name),
+            format_exact!("Data race detected on '{}'.", name),
             "Avoid shared mutable data or use synchronization primitives.".into(),
         ),
         EvalAltResult::ErrorAssignmentToConstant(name, ..) => (
-            format!("Cannot assign to constant '{}'.", name),
+            format_exact!("Cannot assign to constant '{}'.", name),
             "Constants cannot be reassigned after declaration.".into(),
         ),
         EvalAltResult::ErrorDotExpr(field, ..) => (
-            format!("Invalid member access '{}'.", field),
+            format_exact!("Invalid member access '{}'.", field),
             "Verify the object has this member or method.".into(),
         ),
         EvalAltResult::ErrorArithmetic(msg, ..) => {
@@ -240,18 +1378,26 @@ fn get_error_info(
             "Call stack overflow detected.".into(),
             "Check for infinite recursion or deeply nested calls.".into(),
         ),
-        EvalAltResult::ErrorDataTooLarge(name, ..) => (
-            format!("Data '{}' is too large to handle.", name),
-            "Use smaller data sizes or adjust engine limits.".into(),
-        ),
+        EvalAltResult::ErrorDataTooLarge(name, ..) => data_too_large_help(name, engine),
         EvalAltResult::ErrorTerminated(..) => (
             "Script execution was terminated.".into(),
             "This occurs when a `stop` or external termination is triggered.".into(),
         ),
         EvalAltResult::ErrorCustomSyntax(msg, options, ..) => (
-            format!("Custom syntax error: {}.", msg),
-            format!("Expected one of: {}.", options.join(", ")),
+            format_exact!("Custom syntax error: {}.", msg),
+            format_exact!("Expected one of: {}.", options.join(", ")),
         ),
+        // A thrown string is the script's own description of the error.
+        EvalAltResult::ErrorRuntime(value, ..)
+            if value
+                .read_lock::<rhai::ImmutableString>()
+                .is_some_and(|s| !s.is_empty()) =>
+        {
+            (
+                format_exact!("{}", value),
+                "Inspect the error message and script logic for issues.".into(),
+            )
+        }
         EvalAltResult::ErrorRuntime(..) => (
             "Runtime error encountered.".into(),
             "Inspect the error message and script logic for issues.".into(),
@@ -264,35 +1410,403 @@ fn get_error_info(
             "`return` statement encountered.".into(),
             "Script terminated with an explicit return value.".into(),
         ),
-        _ => (
-            "Unknown error".into(),
-            "No additional information available for this error.".into(),
-        ),
+        _ => {
+            generic = true;
+            (
+                "Unknown error".into(),
+                "No additional information available for this error.".into(),
+            )
+        }
     };
 
-    let note = match outer_err {
-        EvalAltResult::ErrorInFunctionCall(fn_name, ..) => format!(
-            "This error occurred during a call to '{}'. Inspecting the function implementation and arguments passed may help solve this error.",
-            fn_name
-        ),
+    ErrorHelp {
+        help,
+        hint,
+        generic,
+    }
+}
 
-        EvalAltResult::ErrorInModule(mod_name, ..) => format!(
-            "This happened while loading the module '{}'. Tip: Check the module code for syntax or runtime errors",
-            mod_name
-        ),
+/// Returns the note of `err`: what it was raised in, the fields of an object map
+/// thrown by the script, and where in `module` the root cause was raised when it
+/// lies in another file, which the span cannot point into. Each is on its own line.
+fn error_note(err: &EvalAltResult, module: Option<&str>) -> String {
+    let raised_in = match err {
+        EvalAltResult::ErrorInFunctionCall(fn_name, ..) => Some(NoteLine::Call(fn_name)),
+        EvalAltResult::ErrorInModule(mod_name, ..) => Some(NoteLine::Module(mod_name)),
+        EvalAltResult::ErrorRuntime(..) => Some(NoteLine::Runtime),
+        _ => None,
+    };
+    let map = match root_cause(err) {
+        EvalAltResult::ErrorRuntime(value, _) => value.read_lock::<rhai::Map>(),
+        _ => None,
+    };
+    let fields = map
+        .as_deref()
+        .filter(|map| !map.is_empty())
+        .map(NoteLine::ThrownMap);
+    let location = module.map(|module| NoteLine::Location(module, root_cause(err).position()));
+
+    // Written in one go, so that the note is the only string allocated.
+    format_exact!("{}", Note([raised_in, fields, location]))
+}
+
+/// A line of the note of an error, see [`error_note`].
+enum NoteLine<'a> {
+    /// The error was raised during a call to this function.
+    Call(&'a str),
+    /// The error was raised while loading this module.
+    Module(&'a str),
+    /// The script raised the error.
+    Runtime,
+    /// The fields of an object map thrown by the script, e.g.
+    /// `Thrown map: code = 42, msg = "out of stock".` for
+    /// `throw #{code: 42, msg: "out of stock"}`.
+    ThrownMap(&'a rhai::Map),
+    /// The position in a module that the root cause was raised at.
+    Location(&'a str, Position),
+}
 
-        EvalAltResult::ErrorRuntime(..) => {
-            "A runtime error bubbled up from a lower-level operation.".into()
+impl fmt::Display for NoteLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoteLine::Call(fn_name) => write!(
+                f,
+                "This error occurred during a call to '{}'. Inspecting the function implementation and arguments passed may help solve this error.",
+                fn_name
+            ),
+            NoteLine::Module(mod_name) => write!(
+                f,
+                "This happened while loading the module '{}'. Tip: Check the module code for syntax or runtime errors",
+                mod_name
+            ),
+            NoteLine::Runtime => {
+                f.write_str("A runtime error bubbled up from a lower-level operation.")
+            }
+            NoteLine::ThrownMap(map) => {
+                f.write_str("Thrown map: ")?;
+                for (i, (name, value)) in map.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{} = {:?}", name, value)?;
+                }
+                f.write_str(".")
+            }
+            NoteLine::Location(module, pos) => match (pos.line(), pos.position()) {
+                (Some(line), Some(column)) => write!(
+                    f,
+                    "Raised in `{}` at line {}, column {}.",
+                    module, line, column
+                ),
+                _ => write!(f, "Raised in `{}`.", module),
+            },
         }
+    }
+}
 
-        _ => "".into(),
-    };
+/// The lines of a note that are present, separated by line breaks.
+struct Note<'a>([Option<NoteLine<'a>>; 3]);
 
-    ErrorHelp { help, hint, note }
+impl fmt::Display for Note<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, line) in self.0.iter().flatten().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{}", line)?;
+        }
+        Ok(())
+    }
 }
 
 struct ErrorHelp {
     help: String,
     hint: String,
-    note: String,
+    generic: bool,
+}
+
+/// Returns the number of arguments in a call signature such as `add (i64, string)`.
+fn argument_count(fn_sig: &str) -> usize {
+    let args = fn_sig.split_once('(').map_or("", |(_, args)| args);
+    args.trim_end()
+        .trim_end_matches(')')
+        .split(", ")
+        .filter(|arg| !arg.trim().is_empty())
+        .count()
+}
+
+/// Describes a call to `name` with the wrong number of arguments, e.g.
+/// `add expects 2 arguments but 1 was provided.`
+fn arity_mismatch(name: &str, expected: &[usize], provided: usize) -> String {
+    let (last, others) = expected.split_last().unwrap_or((&0, &[]));
+    let expected_text = match others {
+        [] => last.to_string(),
+        others => {
+            let others: Vec<String> = others.iter().map(usize::to_string).collect();
+            format!("{} or {}", others.join(", "), last)
+        }
+    };
+
+    format!(
+        "{} expects {} argument{} but {} {} provided.",
+        name,
+        expected_text,
+        if *last == 1 { "" } else { "s" },
+        provided,
+        if provided == 1 { "was" } else { "were" }
+    )
+}
+
+/// Returns whether `name` is a binary operator, either built in or a custom
+/// operator registered with `engine`.
+fn is_operator(name: &str, engine: Option<&Engine>) -> bool {
+    is_builtin_operator(name) || engine.is_some_and(|engine| lexer::is_custom_operator(engine, name))
+}
+
+fn is_builtin_operator(name: &str) -> bool {
+    Token::lookup_symbol_from_syntax(name).is_some_and(|token| token.precedence().is_some())
+}
+
+/// Explains a call to an operator that is not defined for its operand types,
+/// listing the operand types a custom operator is defined for when `engine` is known
+/// and the `metadata` feature is enabled.
+fn operator_help(fn_sig: &str, engine: Option<&Engine>) -> (String, String) {
+    let (name, operands) = fn_sig.split_once('(').unwrap_or((fn_sig, ""));
+    let name = name.trim();
+
+    let custom = !is_builtin_operator(name);
+    let help = format_exact!(
+        "The {} `{}` is not defined for `({})`.",
+        if custom { "custom operator" } else { "operator" },
+        name,
+        OperandTypes(operands)
+    );
+
+    let mut supported = match engine {
+        Some(engine) if custom => operator_signatures(engine, name),
+        _ => Vec::new(),
+    };
+    supported.sort();
+    supported.dedup();
+
+    let hint = if supported.is_empty() {
+        "Convert the operands to types the operator supports.".into()
+    } else {
+        format!("It is defined for:\n  {}", supported.join("\n  "))
+    };
+
+    (help, hint)
+}
+
+/// The operand types of a call signature such as `string, i64)`, written shortened
+/// by [`display_type_name`] and separated by commas.
+struct OperandTypes<'a>(&'a str);
+
+impl fmt::Display for OperandTypes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operands = self.0.trim_end_matches(')').split(", ");
+        for (i, operand) in operands.filter(|operand| !operand.is_empty()).enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            f.write_str(display_type_name(operand))?;
+        }
+        Ok(())
+    }
+}
+
+/// Shortens the type names Rhai puts in function signatures, e.g.
+/// `&str | ImmutableString | String` becomes `string`.
+fn display_type_name(name: &str) -> &str {
+    let name = name.trim().trim_start_matches("&mut ");
+    if name.contains("ImmutableString") {
+        "string"
+    } else {
+        name
+    }
+}
+
+/// Lists the operand types of every two-operand overload of `name` registered with `engine`,
+/// e.g. `(bool, bool)`.
+#[cfg(feature = "metadata")]
+fn operator_signatures(engine: &Engine, name: &str) -> Vec<String> {
+    let prefix = format!("{}(", name);
+
+    engine
+        .gen_fn_signatures(false)
+        .iter()
+        .filter_map(|signature| {
+            // Signatures look like `implies(_: bool, _: bool) -> bool`.
+            let params = signature.strip_prefix(&prefix)?.split(')').next()?;
+            let types: Vec<&str> = params
+                .split(", ")
+                .map(|param| param.rsplit(": ").next().unwrap_or(param))
+                .collect();
+            (types.len() == 2).then(|| format!("({})", types.join(", ")))
+        })
+        .collect()
+}
+
+/// Registered signatures are only available with the `metadata` feature.
+#[cfg(not(feature = "metadata"))]
+fn operator_signatures(_engine: &Engine, _name: &str) -> Vec<String> {
+    Vec::new()
+}
+
+/// Explains which size limit of the engine a value outgrew.
+///
+/// Rhai names the limit in the error (e.g. `Size of array/BLOB`) but does not
+/// report the size of the value, which is only known to be past the maximum.
+fn data_too_large_help(name: &str, engine: Option<&Engine>) -> (String, String) {
+    let names = |word: &str| {
+        name.as_bytes()
+            .windows(word.len())
+            .any(|window| window.eq_ignore_ascii_case(word.as_bytes()))
+    };
+    let (value, unit, setting, max) = if names("string") {
+        ("string", "bytes", "max_string_size", engine.map(Engine::max_string_size))
+    } else if names("array") || names("blob") {
+        ("array", "elements", "max_array_size", engine.map(Engine::max_array_size))
+    } else if names("map") {
+        ("object map", "entries", "max_map_size", engine.map(Engine::max_map_size))
+    } else {
+        return (
+            format_exact!("Data '{}' is too large to handle.", name),
+            "Use smaller data sizes or adjust engine limits.".into(),
+        );
+    };
+
+    let help = match max {
+        Some(max) if max > 0 => format_exact!(
+            "The {} grew to at least {} {}; the engine's {} is {}.",
+            value,
+            WithThousandsSeparators(max.saturating_add(1)),
+            unit,
+            setting,
+            WithThousandsSeparators(max)
+        ),
+        _ => format_exact!("The {} grew past the engine's {}.", value, setting),
+    };
+    let hint = format_exact!(
+        "Raise the limit with `Engine::set_{}`, or build the {} in smaller chunks \
+         or process it as a stream instead of holding all of it at once.",
+        setting, value
+    );
+
+    (help, hint)
+}
+
+/// Returns the name declared in `ast` or held by `scope` that is closest to the
+/// unknown variable `name`, if any is within an edit distance of 2.
+///
+/// Candidates are the variables declared with `let`, `const` and `for`, the
+/// names and parameters of script functions, and the variables in `scope`.
+/// A candidate must also differ in fewer characters than `name` has, so that
+/// e.g. `x` does not suggest `y`.
+fn closest_variable(ast: Option<&AST>, scope: Option<&Scope>, name: &str) -> Option<String> {
+    let mut candidates: Vec<String> = scope
+        .into_iter()
+        .flat_map(|scope| scope.iter_raw().map(|(name, ..)| name.to_string()))
+        .collect();
+
+    if let Some(ast) = ast {
+        collect_declared_names(ast, &mut candidates);
+    }
+
+    let max_distance = 2.min(name.chars().count().saturating_sub(1));
+    candidates
+        .into_iter()
+        .filter(|candidate| !candidate.is_empty() && candidate != name)
+        .map(|candidate| (edit_distance(name, &candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// The most variables [`scope_variables`] lists before summarizing the rest.
+const MAX_LISTED_VARIABLES: usize = 10;
+
+/// Lists the variables in `scope`, e.g. "Variables in scope: `foo`, `bar`.",
+/// or returns `None` if it is empty.
+///
+/// The most recently bound variables come first, and shadowed variables are
+/// listed once.
+fn scope_variables(scope: &Scope) -> Option<String> {
+    let mut names: Vec<&str> = Vec::new();
+    for (name, ..) in scope.iter_raw() {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    if names.is_empty() {
+        return None;
+    }
+
+    let listed: Vec<String> = names
+        .iter()
+        .take(MAX_LISTED_VARIABLES)
+        .map(|name| format!("`{}`", name))
+        .collect();
+    let more = match names.len().saturating_sub(MAX_LISTED_VARIABLES) {
+        0 => String::new(),
+        n => format!(" and {} more", n),
+    };
+
+    Some(format!("Variables in scope: {}{}.", listed.join(", "), more))
+}
+
+/// Pushes the names of the variables, functions and parameters declared in `ast`.
+fn collect_declared_names(ast: &AST, candidates: &mut Vec<String>) {
+    ast.walk(&mut |nodes: &[ASTNode]| {
+        match nodes.last() {
+            Some(ASTNode::Stmt(Stmt::Var(boxed, ..))) => candidates.push(boxed.0.name.to_string()),
+            Some(ASTNode::Stmt(Stmt::For(boxed, ..))) => {
+                let (var, counter, _) = &**boxed;
+                candidates.push(var.name.to_string());
+                candidates.extend(counter.iter().map(|counter| counter.name.to_string()));
+            }
+            _ => {}
+        }
+        true
+    });
+    for f in ast.iter_functions() {
+        candidates.push(f.name.to_string());
+        candidates.extend(f.params.iter().map(|param| param.to_string()));
+    }
+}
+
+/// Returns the Levenshtein distance between `a` and `b`, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Formats a number with a `,` between each group of three digits, e.g. `10,000`.
+struct WithThousandsSeparators(usize);
+
+impl fmt::Display for WithThousandsSeparators {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.0.checked_ilog10().unwrap_or(0) + 1;
+
+        // Digits are written from the most significant one, at place `digits - 1`.
+        for place in (0..digits).rev() {
+            if place + 1 < digits && (place + 1).is_multiple_of(3) {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", self.0 / 10usize.pow(place) % 10)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,239 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A stable, machine-readable identifier for a kind of diagnostic, e.g. `RT0101`.
+///
+/// Codes starting with `RT00` are syntax errors, `RT01` runtime errors and `RT02`
+/// lints. Codes are never reused, so they can be linked to from documentation
+/// or used to filter diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// `RT0001`: the script could not be parsed.
+    SyntaxError,
+    /// `RT0101`: a variable is used but never defined.
+    VariableNotFound,
+    /// `RT0102`: a function is called but not defined for the given arguments.
+    FunctionNotFound,
+    /// `RT0103`: a property does not exist on an object.
+    PropertyNotFound,
+    /// `RT0104`: a value has a different type than expected.
+    DataTypeMismatch,
+    /// `RT0105`: an index is outside the bounds of an array, string or bit-field.
+    IndexOutOfBounds,
+    /// `RT0106`: an arithmetic operation failed, e.g. a division by zero.
+    ArithmeticError,
+    /// `RT0107`: a constant is assigned to.
+    AssignmentToConstant,
+    /// `RT0108`: an imported module cannot be found.
+    ModuleNotFound,
+    /// `RT0109`: the call stack grew too deep.
+    StackOverflow,
+    /// `RT0110`: the script ran more operations than the engine allows.
+    TooManyOperations,
+    /// `RT0111`: a value grew larger than the engine allows.
+    DataTooLarge,
+    /// `RT0112`: the script threw an error.
+    RuntimeError,
+    /// `RT0201`: a `while true` loop never terminates.
+    InfiniteLoop,
+    /// `RT0202`: a variable is declared but its value is never read.
+    UnusedVariable,
+    /// `RT0203`: a `let` shadows an earlier declaration of the same name.
+    ShadowedVariable,
+    /// `RT0204`: statements follow a `return`, `throw`, `break` or `continue`.
+    UnreachableCode,
+}
+
+impl ErrorCode {
+    /// Every error code, in order.
+    pub const ALL: &'static [ErrorCode] = &[
+        ErrorCode::SyntaxError,
+        ErrorCode::VariableNotFound,
+        ErrorCode::FunctionNotFound,
+        ErrorCode::PropertyNotFound,
+        ErrorCode::DataTypeMismatch,
+        ErrorCode::IndexOutOfBounds,
+        ErrorCode::ArithmeticError,
+        ErrorCode::AssignmentToConstant,
+        ErrorCode::ModuleNotFound,
+        ErrorCode::StackOverflow,
+        ErrorCode::TooManyOperations,
+        ErrorCode::DataTooLarge,
+        ErrorCode::RuntimeError,
+        ErrorCode::InfiniteLoop,
+        ErrorCode::UnusedVariable,
+        ErrorCode::ShadowedVariable,
+        ErrorCode::UnreachableCode,
+    ];
+
+    /// Returns the code as written in diagnostics, e.g. `RT0101`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::SyntaxError => "RT0001",
+            ErrorCode::VariableNotFound => "RT0101",
+            ErrorCode::FunctionNotFound => "RT0102",
+            ErrorCode::PropertyNotFound => "RT0103",
+            ErrorCode::DataTypeMismatch => "RT0104",
+            ErrorCode::IndexOutOfBounds => "RT0105",
+            ErrorCode::ArithmeticError => "RT0106",
+            ErrorCode::AssignmentToConstant => "RT0107",
+            ErrorCode::ModuleNotFound => "RT0108",
+            ErrorCode::StackOverflow => "RT0109",
+            ErrorCode::TooManyOperations => "RT0110",
+            ErrorCode::DataTooLarge => "RT0111",
+            ErrorCode::RuntimeError => "RT0112",
+            ErrorCode::InfiniteLoop => "RT0201",
+            ErrorCode::UnusedVariable => "RT0202",
+            ErrorCode::ShadowedVariable => "RT0203",
+            ErrorCode::UnreachableCode => "RT0204",
+        }
+    }
+
+    /// Returns a one-line summary of the diagnostic.
+    pub fn title(&self) -> &'static str {
+        match self {
+            ErrorCode::SyntaxError => "syntax error",
+            ErrorCode::VariableNotFound => "variable not found",
+            ErrorCode::FunctionNotFound => "function not found",
+            ErrorCode::PropertyNotFound => "property not found",
+            ErrorCode::DataTypeMismatch => "data type mismatch",
+            ErrorCode::IndexOutOfBounds => "index out of bounds",
+            ErrorCode::ArithmeticError => "arithmetic error",
+            ErrorCode::AssignmentToConstant => "assignment to a constant",
+            ErrorCode::ModuleNotFound => "module not found",
+            ErrorCode::StackOverflow => "stack overflow",
+            ErrorCode::TooManyOperations => "too many operations",
+            ErrorCode::DataTooLarge => "data too large",
+            ErrorCode::RuntimeError => "runtime error",
+            ErrorCode::InfiniteLoop => "infinite loop",
+            ErrorCode::UnusedVariable => "unused variable",
+            ErrorCode::ShadowedVariable => "shadowed variable",
+            ErrorCode::UnreachableCode => "unreachable code",
+        }
+    }
+
+    /// Returns a longer explanation of what causes the diagnostic and how to fix it.
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            ErrorCode::SyntaxError => {
+                "The script is not valid Rhai and could not be parsed, so nothing in it was run.\n\
+                 Common causes are a missing `;` between statements, unbalanced brackets, and\n\
+                 keywords used as names. The reported position is where the parser gave up,\n\
+                 which can be after the actual mistake."
+            }
+            ErrorCode::VariableNotFound => {
+                "A variable was read before any `let` or `const` declared it in scope.\n\
+                 Check the name for typos, and make sure it is declared before it is used.\n\
+                 Functions cannot see the variables of the script that calls them; pass\n\
+                 values in as parameters instead."
+            }
+            ErrorCode::FunctionNotFound => {
+                "No function with this name accepts the given number and types of arguments.\n\
+                 Rhai picks functions by name and by the types of all arguments, so a function\n\
+                 that exists for `(i64, i64)` is not found when called with `(i64, string)`.\n\
+                 Operators are functions too, so using an operator on unsupported types also\n\
+                 reports this error."
+            }
+            ErrorCode::PropertyNotFound => {
+                "The object does not have the property being accessed. Object maps only have\n\
+                 the keys that were set on them, and custom types only have the getters that\n\
+                 were registered with the engine."
+            }
+            ErrorCode::DataTypeMismatch => {
+                "A value has a different type than the operation expects, for example a string\n\
+                 where a number is required. Convert the value first, e.g. with `parse_int`,\n\
+                 `to_string` or `to_float`."
+            }
+            ErrorCode::IndexOutOfBounds => {
+                "An index is outside the array, string or bit-field being indexed. Valid\n\
+                 indices run from 0 to one less than the length; negative indices count\n\
+                 from the end."
+            }
+            ErrorCode::ArithmeticError => {
+                "An arithmetic operation has no valid result, such as a division by zero or an\n\
+                 integer overflow. Check the operands before the operation."
+            }
+            ErrorCode::AssignmentToConstant => {
+                "A value declared with `const` cannot be changed after it is declared. Declare it\n\
+                 with `let` instead if it needs to change."
+            }
+            ErrorCode::ModuleNotFound => {
+                "The module resolver could not find the module being imported. Check the path\n\
+                 in the `import` statement and the module resolver configured on the engine."
+            }
+            ErrorCode::StackOverflow => {
+                "Function calls were nested deeper than the engine allows, which usually means\n\
+                 a recursion that never reaches its base case."
+            }
+            ErrorCode::TooManyOperations => {
+                "The script ran more operations than the limit set with\n\
+                 `Engine::set_max_operations`, which usually means a loop that never ends."
+            }
+            ErrorCode::DataTooLarge => {
+                "A string, array, object map or other value grew past a size limit configured\n\
+                 on the engine."
+            }
+            ErrorCode::RuntimeError => {
+                "The script raised an error with `throw`, or a registered function returned an\n\
+                 error. The message is the value that was thrown."
+            }
+            ErrorCode::InfiniteLoop => {
+                "A `while true` loop contains no `break`, `return` or `throw`, so it can never\n\
+                 finish. Add a way out of the loop, or use `loop` to make the intent explicit."
+            }
+            ErrorCode::UnusedVariable => {
+                "A `let`, loop or `catch` variable is never read, or is only ever assigned to,\n\
+                 which often means a typo or leftover code. Remove the variable, or start its\n\
+                 name with `_` to mark it as intentionally unused."
+            }
+            ErrorCode::ShadowedVariable => {
+                "A `let` declares a name that is already bound, so the earlier variable,\n\
+                 constant or parameter can no longer be reached by its name. In the same\n\
+                 block this is often a `let` that was meant to be an assignment; in a nested\n\
+                 block, changes to the new variable do not reach the outer one. Rename one of\n\
+                 them, or start the name with `_` if the shadowing is intended."
+            }
+            ErrorCode::UnreachableCode => {
+                "Statements follow a `return`, `throw`, `break` or `continue` in the same\n\
+                 block, or an `if` whose every branch ends in one, so they can never run.\n\
+                 Rhai silently ignores them. Remove them, or move them before the statement\n\
+                 that leaves the block."
+            }
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ErrorCode {
+    type Err = String;
+
+    /// Parses a code such as `RT0101`, ignoring ASCII case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ErrorCode::ALL
+            .iter()
+            .find(|code| code.as_str().eq_ignore_ascii_case(s.trim()))
+            .copied()
+            .ok_or_else(|| format!("Unknown error code '{}'.", s))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for ErrorCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ErrorCode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,347 @@
+use crate::error::blank_line;
+use crate::line_index::LineIndex;
+use crate::span::{Span, pos_to_byte};
+use rhai::{Engine, Token};
+use std::borrow::Cow;
+use std::ops::Range;
+
+/// A token of a script along with the exact bytes it covers.
+#[derive(Debug, Clone)]
+pub(crate) struct Lexeme {
+    pub token: Token,
+    pub span: Span,
+}
+
+/// Returns `script` as Rhai accepts it: without a leading UTF-8 byte order mark,
+/// and with a `#!` shebang line blanked out, as editors and Unix scripts add them.
+///
+/// Every line keeps its number and columns, so the positions Rhai reports for the
+/// result point at the same code in `script` through a [`LineIndex`] of it, which
+/// skips the byte order mark too.
+pub(crate) fn compilable(script: &str) -> Cow<'_, str> {
+    let script = script.strip_prefix('\u{FEFF}').unwrap_or(script);
+    match script.starts_with("#!") {
+        true => Cow::Owned(blank_line(script, 1)),
+        false => Cow::Borrowed(script),
+    }
+}
+
+/// Tokenizes `script` with `engine`, skipping comments.
+///
+/// Tokenizing stops at the end of the script. Invalid input shows up as
+/// `Token::LexError` lexemes rather than stopping the scan. A byte order mark or
+/// shebang line at the start is skipped, as in [`compilable`].
+pub(crate) fn tokenize(engine: &Engine, script: &str) -> Vec<Lexeme> {
+    let inputs = [compilable(script)];
+    let (mut tokens, control) = engine.lex(&inputs);
+    let index = LineIndex::new(script);
+    let mut lexemes = Vec::new();
+
+    // Brace depths at which an interpolated string resumes, innermost last.
+    let mut interpolations: Vec<usize> = Vec::new();
+    let mut depth = 0usize;
+
+    while let Some((token, pos)) = tokens.next() {
+        if token == Token::EOF {
+            break;
+        }
+
+        // `tokens.pos` points at the last character of the token just read.
+        let start = pos_to_byte(&index, &pos).min(script.len());
+        let last = pos_to_byte(&index, &tokens.pos).min(script.len());
+        let last_len = script[last..].chars().next().map_or(0, char::len_utf8);
+        let end = (last + last_len).max(start);
+
+        match token {
+            Token::InterpolatedString(..) => interpolations.push(depth),
+            Token::LeftBrace | Token::MapStart => depth += 1,
+            Token::RightBrace => {
+                depth = depth.saturating_sub(1);
+                // Closing `}` of a `${ ... }` block switches back to text.
+                if interpolations.last() == Some(&depth) {
+                    interpolations.pop();
+                    control.borrow_mut().is_within_text = true;
+                }
+            }
+            _ => {}
+        }
+
+        if matches!(token, Token::Comment(..)) {
+            continue;
+        }
+
+        lexemes.push(Lexeme {
+            token,
+            span: Span::from_range_in(&index, start..end),
+        });
+    }
+
+    lexemes
+}
+
+/// Returns whether `name` is a custom operator registered with `engine`.
+///
+/// The engine does not expose its custom operators directly, but its tokenizer
+/// reads them as [`Token::Custom`] rather than as identifiers.
+pub(crate) fn is_custom_operator(engine: &Engine, name: &str) -> bool {
+    let inputs = [name];
+    let (mut tokens, _) = engine.lex(&inputs);
+
+    matches!(tokens.next(), Some((Token::Custom(custom), _)) if custom.as_str() == name)
+}
+
+/// A `fn name(...) { ... }` found by [`function_bodies`].
+#[derive(Debug, Clone)]
+pub(crate) struct FunctionBody {
+    pub name: String,
+    pub name_span: Span,
+    /// Where the definition starts, at `fn` or at `private` before it.
+    pub start: usize,
+    /// The names of the parameters.
+    pub params: Vec<Span>,
+    /// The byte range of the body, braces included.
+    pub body: Range<usize>,
+}
+
+/// Finds every `fn name(...) { ... }` in `source`, along with the byte range of its body.
+pub(crate) fn function_bodies(engine: &Engine, source: &str) -> Vec<FunctionBody> {
+    let lexemes = tokenize(engine, source);
+    let mut functions = Vec::new();
+
+    for (i, lexeme) in lexemes.iter().enumerate() {
+        let (Token::Fn, Some(Token::Identifier(name))) =
+            (&lexeme.token, lexemes.get(i + 1).map(|next| &next.token))
+        else {
+            continue;
+        };
+
+        let Some(open) = lexemes[i..]
+            .iter()
+            .position(|lexeme| lexeme.token == Token::LeftBrace)
+        else {
+            continue;
+        };
+
+        // Parameters are plain names, with nothing else between them but commas.
+        let mut params: Vec<Span> = lexemes[i..i + open]
+            .iter()
+            .skip(2)
+            .filter(|lexeme| matches!(lexeme.token, Token::Identifier(_)))
+            .map(|lexeme| lexeme.span)
+            .collect();
+
+        let mut depth = 0usize;
+        for lexeme in &lexemes[i + open..] {
+            match lexeme.token {
+                Token::LeftBrace | Token::MapStart => depth += 1,
+                Token::RightBrace => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                let start = match i.checked_sub(1).map(|prev| &lexemes[prev]) {
+                    Some(prev) if prev.token == Token::Private => prev.span.start(),
+                    _ => lexemes[i].span.start(),
+                };
+                functions.push(FunctionBody {
+                    name: name.to_string(),
+                    name_span: lexemes[i + 1].span,
+                    start,
+                    params: std::mem::take(&mut params),
+                    body: lexemes[i + open].span.start()..lexeme.span.end(),
+                });
+                break;
+            }
+        }
+    }
+
+    functions
+}
+
+/// Returns the length in bytes of the token that `text` starts with, found by
+/// scanning its characters rather than by running Rhai's tokenizer.
+///
+/// Identifiers and numbers, e.g. `count` or `42.5`, run while they are followed
+/// by word characters, operators while they are followed by operator characters,
+/// and strings and characters up to their closing quote, skipping escapes. Any
+/// other punctuation is a token of its own. Returns `None` for whitespace, or for
+/// a string that is not closed within `text`.
+pub(crate) fn token_len(text: &str) -> Option<usize> {
+    const OPERATOR: &[char] = &[
+        '+', '-', '*', '/', '%', '=', '!', '<', '>', '&', '|', '^', '.', '?', ':', '~',
+    ];
+    let word = |c: char| c.is_alphanumeric() || c == '_';
+    let first = text.chars().next()?;
+
+    let len = match first {
+        '"' | '\'' | '`' => {
+            let mut escaped = false;
+            let (close, _) = text.char_indices().skip(1).find(|&(_, c)| {
+                let closes = c == first && !escaped;
+                escaped = c == '\\' && first != '`' && !escaped;
+                closes
+            })?;
+            close + 1
+        }
+        // A `.` continues a number only before a digit, not a method call.
+        c if c.is_ascii_digit() => text
+            .char_indices()
+            .find(|&(i, c)| {
+                let fraction = c == '.' && text[i + 1..].starts_with(|c: char| c.is_ascii_digit());
+                !(word(c) || fraction)
+            })
+            .map_or(text.len(), |(i, _)| i),
+        c if word(c) => text.find(|c: char| !word(c)).unwrap_or(text.len()),
+        c if OPERATOR.contains(&c) => text
+            .find(|c: char| !OPERATOR.contains(&c))
+            .unwrap_or(text.len()),
+        c if c.is_whitespace() => return None,
+        c => c.len_utf8(),
+    };
+    Some(len)
+}
+
+/// Returns the index of the first lexeme starting at or after `offset`.
+pub(crate) fn lexeme_at(lexemes: &[Lexeme], offset: usize) -> usize {
+    lexemes.partition_point(|lexeme| lexeme.span.start() < offset)
+}
+
+pub(crate) fn is_open(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::LeftParen | Token::LeftBracket | Token::LeftBrace | Token::MapStart
+    )
+}
+
+pub(crate) fn is_close(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::RightParen | Token::RightBracket | Token::RightBrace
+    )
+}
+
+/// Returns the byte range from the first to the last lexeme inside the brackets
+/// opened by the lexeme at `open`, or `None` if they are empty or never closed.
+pub(crate) fn bracket_contents(lexemes: &[Lexeme], open: usize) -> Option<Range<usize>> {
+    let mut depth = 0usize;
+    for (i, lexeme) in lexemes.iter().enumerate().skip(open) {
+        match lexeme.token {
+            ref token if is_open(token) => depth += 1,
+            ref token if is_close(token) => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return (i > open + 1)
+                .then(|| lexemes[open + 1].span.start()..lexemes[i - 1].span.end());
+        }
+    }
+    None
+}
+
+/// Returns the byte range of every `{ ... }` block, braces included.
+pub(crate) fn block_ranges(lexemes: &[Lexeme]) -> Vec<Range<usize>> {
+    let mut open: Vec<Option<usize>> = Vec::new();
+    let mut blocks = Vec::new();
+
+    for lexeme in lexemes {
+        match lexeme.token {
+            Token::LeftBrace => open.push(Some(lexeme.span.start())),
+            ref token if is_open(token) => open.push(None),
+            ref token if is_close(token) => {
+                if let Some(Some(start)) = open.pop() {
+                    blocks.push(start..lexeme.span.end());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    blocks
+}
+
+/// Returns the innermost block strictly containing the byte `offset`.
+pub(crate) fn enclosing_block(blocks: &[Range<usize>], offset: usize) -> Option<Range<usize>> {
+    blocks
+        .iter()
+        .filter(|block| block.start < offset && offset < block.end)
+        .min_by_key(|block| block.end - block.start)
+        .cloned()
+}
+
+/// Returns where the statement containing the lexeme at `at` ends: after its `;`,
+/// or before the `}` closing its block.
+pub(crate) fn statement_end(lexemes: &[Lexeme], at: usize, len: usize) -> usize {
+    let mut depth = 0usize;
+    for lexeme in &lexemes[at.min(lexemes.len())..] {
+        match lexeme.token {
+            ref token if is_open(token) => depth += 1,
+            ref token if is_close(token) => match depth {
+                0 => return lexeme.span.start(),
+                _ => depth -= 1,
+            },
+            Token::SemiColon if depth == 0 => return lexeme.span.end(),
+            _ => {}
+        }
+    }
+    len
+}
+
+/// Widens the byte `range` so that every bracket opened or closed by a lexeme
+/// inside it is also closed or opened inside it, e.g. from `a + b) / 2` to
+/// `(a + b) / 2`.
+pub(crate) fn balance(lexemes: &[Lexeme], range: Range<usize>) -> Range<usize> {
+    let first = lexeme_at(lexemes, range.start);
+    let last = lexeme_at(lexemes, range.end);
+
+    // Brackets closed but not opened in the range, and opened but not closed.
+    let (mut unopened, mut unclosed) = (0usize, 0usize);
+    for lexeme in &lexemes[first..last.max(first)] {
+        match lexeme.token {
+            ref token if is_open(token) => unclosed += 1,
+            ref token if is_close(token) => match unclosed {
+                0 => unopened += 1,
+                _ => unclosed -= 1,
+            },
+            _ => {}
+        }
+    }
+
+    let mut start = range.start;
+    let mut depth = 0usize;
+    for lexeme in lexemes[..first].iter().rev() {
+        if unopened == 0 {
+            break;
+        }
+        match lexeme.token {
+            ref token if is_close(token) => depth += 1,
+            ref token if is_open(token) => match depth {
+                0 => {
+                    unopened -= 1;
+                    start = lexeme.span.start();
+                }
+                _ => depth -= 1,
+            },
+            _ => {}
+        }
+    }
+
+    let mut end = range.end;
+    let mut depth = 0usize;
+    for lexeme in &lexemes[last.max(first)..] {
+        if unclosed == 0 {
+            break;
+        }
+        match lexeme.token {
+            ref token if is_open(token) => depth += 1,
+            ref token if is_close(token) => match depth {
+                0 => {
+                    unclosed -= 1;
+                    end = lexeme.span.end();
+                }
+                _ => depth -= 1,
+            },
+            _ => {}
+        }
+    }
+
+    start..end
+}
@@ -39,7 +39,7 @@
 //!         Ok(result) => println!("Execution result: {:?}", result),
 //!         Err(e) => {
 //!             // Improve the error using our library
-//!             if let Ok(better) = BetterError::improve_eval_error(&e, code, &engine, None) {
+//!             if let Ok(better) = BetterError::improve_eval_error(&e, code, &engine, None, None) {
 //!                 // This returns a [`BetterError`] structure.
 //!                 //
 //!                 // It includes all the information you need to print a
@@ -64,19 +64,57 @@
 //! For a complete working example that integrates `rhai_trace` with the [`ariadne`](https://docs.rs/ariadne) crate for pretty error reporting, check out the example folder:
 //! [GitHub Example](https://github.com/Byson94/rhai_trace/tree/main/example)
 
+pub mod analysis;
+pub mod cache;
+pub mod context;
+pub mod diagnostics;
 pub mod error;
+pub mod error_code;
+mod calls;
+mod lexer;
+pub mod line_index;
+pub mod lint;
+pub mod messages;
+pub mod outline;
+pub mod render;
+pub mod semantic;
 pub mod span;
+pub mod symbols;
 pub mod tracer;
 
+#[cfg(feature = "ariadne")]
+pub mod ariadne;
+
+#[cfg(feature = "miette")]
+pub mod miette;
+
+#[cfg(feature = "codespan")]
+pub mod codespan;
+
+#[cfg(feature = "lsp")]
+pub mod lsp;
+
 // == Rexporting ==//
-pub use error::BetterError;
-pub use span::Span;
-pub use tracer::SpanTracer;
+pub use cache::SpanCache;
+pub use context::{ErrorContext, ErrorContextRef, TraceContext};
+pub use diagnostics::{Diagnostic, DiagnosticId, Diagnostics};
+pub use error::{BetterError, BetterErrorBuilder, ImproveOptions, Severity, TraceError, Verbosity};
+pub use error_code::ErrorCode;
+pub use line_index::LineIndex;
+pub use lint::Lint;
+pub use messages::{CustomErrorMapper, DiagnosticMessages, MessageTemplate};
+pub use outline::{DocumentSymbol, DocumentSymbolKind};
+pub use semantic::{SemanticToken, TokenModifiers, TokenType};
+pub use span::{SourceId, SourceNames, Span, SpanKind};
+pub use symbols::{Symbol, SymbolKind, SymbolTable};
+pub use tracer::{
+    FunctionSpans, SpanNode, SpanStats, SpanTracer, SpanTree, StatementKind, StatementRange,
+};
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use rhai::{Engine, Dynamic};
+    use rhai::{Engine, Dynamic, Position};
 
     #[test]
     fn test_span_extraction() {
@@ -94,17 +132,11 @@ mod test {
         match spans_result {
             Ok(_) => {}
             Err(ref err) => {
-                if let Some(parse_err) = err.downcast_ref::<rhai::ParseError>() {
-                    match BetterError::improve_parse_error(&parse_err, code) {
-                        Ok(better_error) => println!("Better error: {:?}", better_error),
-                        Err(e) => eprintln!("Failed to improve parse error: {:?}", e),
-                    }
-
-                    return;
-                } else {
-                    eprintln!("Other error: {:?}", err);
-                    return;
+                match BetterError::improve_compile_error(&**err, code) {
+                    Some(better_error) => println!("Better error: {:?}", better_error),
+                    None => eprintln!("Other error: {:?}", err),
                 }
+                return;
             }
         }
 
@@ -148,8 +180,2643 @@ return "test complete"
         let _ = engine.eval_with_scope::<Dynamic>(&mut scope, code).map_err(|e| {
             eprintln!(
                 "Better Error: {:#?}",
-                BetterError::improve_eval_error(&e, code, &engine, None)
+                BetterError::improve_eval_error(&e, code, &engine, None, Some(&scope))
             );
         });
     }
+
+    #[test]
+    fn test_crlf_line_endings() {
+        let code = "let a = 1;\r\nlet b = 2;\r\nlet c = a + b;\r\n";
+
+        let tracer = SpanTracer::new();
+        let spans = tracer.extract_from(code).unwrap();
+        assert!(!spans.is_empty(), "There should be some spans extracted");
+
+        for span in &spans {
+            assert!(span.end() <= code.len(), "Span end should not exceed code length");
+        }
+
+        let third = spans
+            .iter()
+            .find(|span| span.line() == 3 && span.column() == 1)
+            .expect("There should be a span for the third statement");
+        assert_eq!(&code[third.start()..third.end()], "let c = a + b;");
+
+        let pos = rhai::Position::new(2, 5);
+        let span = Span::from_pos(code, &pos);
+        assert_eq!(&code[span.start()..span.end()], "b = 2;");
+    }
+
+    #[test]
+    fn test_span_snippet() {
+        let code = "let name = \"héllo\";";
+
+        let span = Span::new(4, 8, 1, 5);
+        assert_eq!(span.snippet(code), Some("name"));
+
+        // Out of bounds
+        let span = Span::new(4, code.len() + 1, 1, 5);
+        assert_eq!(span.snippet(code), None);
+
+        // `é` is two bytes long, so offset 14 lands in the middle of it
+        let span = Span::new(12, 14, 1, 13);
+        assert_eq!(span.snippet(code), None);
+    }
+
+    #[test]
+    fn test_span_range_conversion() {
+        let code = "let a = 1;\nlet b = a + 2;";
+
+        let span = Span::from_range(code, 19..24);
+        assert_eq!(span.line(), 2);
+        assert_eq!(span.column(), 9);
+
+        let range: std::ops::Range<usize> = span.into();
+        assert_eq!(range, 19..24);
+        assert_eq!(&code[range], "a + 2");
+    }
+
+    #[test]
+    fn test_infinite_loop_lint() {
+        let code = r#"
+let x = 0;
+while true { x += 1; }
+while true { if x > 10 { break; } x += 1; }
+while x < 10 { x += 1; }
+        "#;
+
+        let tracer = SpanTracer::new();
+        let lints = tracer.lint(code).unwrap();
+
+        assert_eq!(lints.len(), 1, "Only the first loop should be flagged");
+        assert_eq!(lints[0].name, "infinite_loop");
+        assert_eq!(lints[0].span.line(), 3);
+        assert_eq!(lints[0].span.snippet(code), Some("while true"));
+    }
+
+    #[test]
+    fn test_highlight_ranges() {
+        let code = "let total = add(1, 2);";
+
+        let tracer = SpanTracer::new();
+        let ranges = tracer.highlight_ranges(code).unwrap();
+        assert!(!ranges.is_empty(), "There should be some highlighted ranges");
+
+        for pair in ranges.windows(2) {
+            assert!(pair[0].0.end <= pair[1].0.start, "Ranges should not overlap");
+        }
+
+        let literal = code.find('1').unwrap();
+        let (_, kind) = ranges
+            .iter()
+            .find(|(range, _)| range.contains(&literal))
+            .expect("The literal should be highlighted");
+        assert_eq!(*kind, SpanKind::Literal);
+    }
+
+    #[test]
+    fn test_span_eq_hash_ord() {
+        use std::collections::HashSet;
+
+        let outer = Span::new(0, 20, 1, 1);
+        let inner = Span::new(0, 5, 1, 1);
+        let later = Span::new(8, 12, 1, 9);
+
+        let set: HashSet<Span> = [inner, outer, inner].into();
+        assert_eq!(set.len(), 2, "Equal spans should hash the same");
+
+        // Enclosing spans sort before the spans nested inside them.
+        let mut spans = vec![later, inner, outer];
+        spans.sort();
+        assert_eq!(spans, vec![outer, inner, later]);
+
+        // Sorting a shuffled vector restores ascending order by start.
+        let code = "let a = 1;\nif a > 0 {\n    print(a + 2);\n}\nlet b = [a, 3];";
+        let sorted = SpanTracer::new().extract_from(code).unwrap();
+        let mut shuffled = sorted.clone();
+        shuffled.reverse();
+        shuffled.rotate_left(sorted.len() / 3);
+        assert_ne!(shuffled, sorted);
+        shuffled.sort();
+        assert_eq!(shuffled, sorted);
+        assert!(shuffled.windows(2).all(|pair| pair[0].start() <= pair[1].start()));
+    }
+
+    #[test]
+    fn test_statement_ranges() {
+        let code = r#"
+// leading comment
+let a = 1; // trailing comment
+fn add(x, y) { x + y }
+/* between */
+if a > 0 { print(a); } else { print(0); }
+let f = |x| { x + 1 };
+do { a -= 1; } while a > 0;
+let s = `value: ${a}`;
+add(a, 2)
+        "#;
+
+        let tracer = SpanTracer::new();
+        let ranges = tracer.statement_ranges(code).unwrap();
+
+        let snippets: Vec<_> = ranges.iter().map(|r| r.span.snippet(code).unwrap()).collect();
+        assert_eq!(
+            snippets,
+            vec![
+                "let a = 1;",
+                "fn add(x, y) { x + y }",
+                "if a > 0 { print(a); } else { print(0); }",
+                "let f = |x| { x + 1 };",
+                "do { a -= 1; } while a > 0;",
+                "let s = `value: ${a}`;",
+                "add(a, 2)",
+            ]
+        );
+
+        let kinds: Vec<_> = ranges.iter().map(|r| r.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                StatementKind::Declaration,
+                StatementKind::FunctionDefinition,
+                StatementKind::ControlFlow,
+                StatementKind::Declaration,
+                StatementKind::ControlFlow,
+                StatementKind::Declaration,
+                StatementKind::Expression,
+            ]
+        );
+
+        for (index, range) in ranges.iter().enumerate() {
+            assert_eq!(range.index, index);
+        }
+    }
+
+    #[test]
+    fn test_span_end_position() {
+        let code = r#"try {
+    let a = 1;
+    throw a;
+} catch (err) {
+    print(err);
+}"#;
+
+        let engine = Engine::new();
+        let ast = engine.compile(code).unwrap();
+        let Some(rhai::Stmt::TryCatch(flow, pos)) = ast.statements().first() else {
+            panic!("Expected a try/catch statement");
+        };
+
+        let body = Span::from_rhai_span(code, flow.body.span(), &flow.body.position());
+        assert_eq!((body.line(), body.column()), (1, 5));
+        assert_eq!((body.end_line(), body.end_column()), (4, 1));
+
+        let whole = Span::from_rhai_start_end_pos(code, pos, &flow.branch.span().end());
+        assert_eq!((whole.line(), whole.column()), (1, 1));
+        assert_eq!((whole.end_line(), whole.end_column()), (6, 1));
+        assert!(whole.snippet(code).unwrap().starts_with("try {"));
+
+        let single = Span::from_pos(code, pos);
+        assert_eq!((single.end_line(), single.end_column()), (1, 1));
+    }
+
+    #[test]
+    fn test_source_size_guard() {
+        let code = "let a = 1;\nlet b = 2;\n";
+
+        let tracer = SpanTracer::new().max_source_bytes(8);
+        let err = tracer.extract_from(code).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<TraceError>(),
+            Some(&TraceError::SourceTooLarge {
+                size: code.len(),
+                max: 8
+            })
+        );
+
+        let tracer = SpanTracer::new().max_source_bytes(code.len());
+        assert!(tracer.extract_from(code).is_ok());
+    }
+
+    #[test]
+    fn test_improve_source_size_limit() {
+        let code = "let a = 1;\nlet b = a + c;\n";
+        let engine = Engine::new();
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let too_large = Some(&TraceError::SourceTooLarge {
+            size: code.len(),
+            max: 8,
+        });
+
+        let options = ImproveOptions::new().max_source_bytes(8);
+        let result = options.improve_eval_error(&err, code, &engine, None, None);
+        assert_eq!(result.unwrap_err().downcast_ref::<TraceError>(), too_large);
+        let result = options.improve_all_parse_errors(code, &engine);
+        assert_eq!(result.unwrap_err().downcast_ref::<TraceError>(), too_large);
+        let result = ErrorContext::with_options(code, &engine, options);
+        assert_eq!(result.err().unwrap().downcast_ref::<TraceError>(), too_large);
+
+        let options = ImproveOptions::new().max_source_bytes(code.len());
+        let better = options.improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.span.line(), 2);
+    }
+
+    #[test]
+    fn test_checked_span_constructor() {
+        let span = Span::try_new(usize::MAX - 1, usize::MAX, 1, 1).unwrap();
+        assert_eq!(span.end() - span.start(), 1);
+
+        assert_eq!(
+            Span::try_new(usize::MAX, usize::MAX - 1, 1, 1),
+            Err(TraceError::InvalidSpan {
+                start: usize::MAX,
+                end: usize::MAX - 1
+            })
+        );
+
+        // Positions far past the end of the script must not overflow; they are
+        // clamped to its end.
+        let span = Span::from_pos("x", &rhai::Position::new(u16::MAX, u16::MAX));
+        assert_eq!((span.start(), span.snippet("x")), (1, Some("")));
+    }
+
+    #[test]
+    fn test_span_from_byte_range() {
+        let code = "let a = 1;\nlet b = 2;";
+
+        // Start of the second line
+        let span = Span::from_byte_range(code, 11, 14);
+        assert_eq!((span.line(), span.column()), (2, 1));
+        assert_eq!(span.snippet(code), Some("let"));
+
+        // The newline itself still belongs to the first line
+        let span = Span::from_byte_range(code, 10, 11);
+        assert_eq!((span.line(), span.column()), (1, 11));
+
+        // The final byte
+        let span = Span::from_byte_range(code, code.len() - 1, code.len());
+        assert_eq!((span.line(), span.column()), (2, 10));
+        assert_eq!(span.snippet(code), Some(";"));
+
+        // Offsets past the end are clamped
+        let span = Span::from_byte_range(code, 100, 200);
+        assert_eq!((span.start(), span.end()), (code.len(), code.len()));
+        assert_eq!((span.line(), span.column()), (2, 11));
+    }
+
+    #[test]
+    fn test_span_merge_and_contains() {
+        let code = "if a > 0 {\n    print(a);\n} else {\n    print(b);\n}";
+
+        let head = Span::from_byte_range(code, 0, 10);
+        let tail = Span::from_byte_range(code, 35, 44);
+        let merged = tail.merge(&head);
+
+        assert_eq!((merged.start(), merged.end()), (0, 44));
+        assert_eq!((merged.line(), merged.column()), (1, 1));
+        assert_eq!((merged.end_line(), merged.end_column()), (tail.end_line(), tail.end_column()));
+        assert!(merged.contains(&head));
+        assert!(merged.contains(&tail));
+        assert!(!head.contains(&merged));
+
+        // Overlapping spans on different lines keep the earlier line/column
+        let first = Span::from_byte_range(code, 5, 20);
+        let second = Span::from_byte_range(code, 15, 30);
+        let merged = second.merge(&first);
+        assert_eq!((merged.start(), merged.end()), (5, 30));
+        assert_eq!((merged.line(), merged.column()), (1, 6));
+
+        assert!(merged.contains_offset(5));
+        assert!(merged.contains_offset(29));
+        assert!(!merged.contains_offset(30));
+
+        // Two spans on one line, with a gap between them
+        let line = "let total = add(a, b);";
+        let name = Span::from_byte_range(line, 4, 9);
+        let call = Span::from_byte_range(line, 12, 21);
+        let merged = call.merge(&name);
+        assert_eq!(merged.snippet(line), Some("total = add(a, b)"));
+        assert_eq!((merged.line(), merged.column()), (1, 5));
+        assert_eq!((merged.end_line(), merged.end_column()), (1, call.end_column()));
+        assert_eq!(merged, name.merge(&call));
+
+        let none = Span::from_pos(code, &Position::NONE);
+        assert_eq!(none.merge(&tail), tail);
+        assert_eq!(tail.merge(&none), tail);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let code = "let a = 1;\nlet b = a + c;";
+
+        let engine = Engine::new();
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+
+        let json = serde_json::to_value(&better).unwrap();
+        for field in ["message", "help", "hint", "note", "span"] {
+            assert!(json.get(field).is_some(), "Missing field `{}`", field);
+        }
+        for field in ["start", "end", "line", "column"] {
+            assert!(json["span"].get(field).is_some(), "Missing span field `{}`", field);
+        }
+
+        let back: BetterError = serde_json::from_value(json).unwrap();
+        assert_eq!(back, better);
+    }
+
+    #[test]
+    fn test_structural_scan_unclosed_brace() {
+        let mut code = String::new();
+        for i in 1..=100 {
+            if i == 37 {
+                code.push_str("if x > 1 {\n");
+            } else if i % 10 == 0 {
+                code.push_str(&format!("print(v{});\n", i - 1));
+            } else {
+                code.push_str(&format!("let v{} = [{}, ({} + 1)];\n", i, i, i));
+            }
+        }
+
+        let scan = analysis::structural_scan(&code).expect("The brace should be reported");
+        assert_eq!(scan.error.span.line(), 37);
+        assert_eq!(scan.error.span.snippet(&code), Some("{"));
+        assert_eq!(scan.unclosed, vec![scan.error.span]);
+        assert!(scan.error.hint.unwrap().contains("1 unclosed `{`"));
+
+        let engine = Engine::new();
+        let err = analysis::validate_full(&code, &engine).unwrap_err();
+        assert_eq!(err.span.line(), 37, "validate_full should fall back to the scan");
+    }
+
+    #[test]
+    fn test_structural_scan_random_bytes() {
+        let prefix = "let ok = [1, (2 + 3)];\n";
+        let bytes: Vec<u8> = (0u32..512).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let code = format!("{}{}", prefix, String::from_utf8_lossy(&bytes));
+
+        let scan = analysis::structural_scan(&code).expect("Garbage should be reported");
+        assert!(scan.error.message.starts_with("Invalid input"));
+        assert_eq!(scan.error.span.start(), prefix.len());
+        assert_eq!((scan.error.span.line(), scan.error.span.column()), (2, 1));
+
+        assert!(analysis::structural_scan(prefix).is_none());
+    }
+
+    #[test]
+    fn test_span_overlaps_and_intersection() {
+        let code = "let a = 1;\nlet b = a + 2;\nlet c = b * 3;";
+
+        let statement = Span::from_byte_range(code, 11, 25);
+        let viewport = Span::from_byte_range(code, 0, 15);
+
+        assert!(statement.overlaps(&viewport));
+        let clipped = statement.intersection(&viewport).unwrap();
+        assert_eq!((clipped.start(), clipped.end()), (11, 15));
+        assert_eq!((clipped.line(), clipped.column()), (2, 1));
+        assert_eq!((clipped.end_line(), clipped.end_column()), (2, 5));
+        assert_eq!(clipped.snippet(code), Some("let "));
+
+        let third = Span::from_byte_range(code, 26, 40);
+        assert!(!statement.overlaps(&third));
+        assert_eq!(statement.intersection(&third), None);
+
+        // Zero-length spans are points
+        let point = Span::from_byte_range(code, 13, 13);
+        assert!(point.overlaps(&statement));
+        assert!(statement.overlaps(&point));
+        assert_eq!(statement.intersection(&point), Some(point));
+        assert!(!point.overlaps(&viewport.intersection(&Span::from_byte_range(code, 0, 5)).unwrap()));
+    }
+
+    #[test]
+    fn test_extract_from_ast() {
+        let code = "fn add(x, y) { x + y }\nlet a = add(1, 2);\nlet b = a * 3;";
+        let tracer = SpanTracer::new();
+        let ast = tracer.engine().compile(code).unwrap();
+
+        let from_ast = tracer.extract_from_ast(&ast, code).unwrap();
+        assert_eq!(from_ast, tracer.extract_from(code).unwrap());
+        assert!(!from_ast.is_empty());
+
+        let small = SpanTracer::new().max_source_bytes(8);
+        let err = small.extract_from_ast(&ast, code).unwrap_err();
+        assert!(err.downcast_ref::<TraceError>().is_some());
+    }
+
+    #[test]
+    fn test_operator_hints() {
+        let mut engine = Engine::new();
+        engine.register_custom_operator("implies", 160).unwrap();
+        engine.register_fn("implies", |a: bool, b: bool| !a || b);
+
+        let code = "let x = true;\nlet y = x implies \"no\";";
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(
+            better.help.as_deref(),
+            Some("The custom operator `implies` is not defined for `(bool, string)`.")
+        );
+        #[cfg(feature = "metadata")]
+        assert_eq!(better.hint.as_deref(), Some("It is defined for:\n  (bool, bool)"));
+        assert_eq!(better.span.line(), 2);
+
+        let code = "let z = \"a\" * 2;";
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(
+            better.help.as_deref(),
+            Some("The operator `*` is not defined for `(string, i64)`.")
+        );
+
+        let code = "let total = a + 1;";
+        let ranges = SpanTracer::new().highlight_ranges(code).unwrap();
+        let plus = code.find('+').unwrap();
+        let (_, kind) = ranges.iter().find(|(range, _)| range.contains(&plus)).unwrap();
+        assert_eq!(*kind, SpanKind::Operator);
+    }
+
+    #[test]
+    fn test_line_index() {
+        let code = "let a = 1;\r\nlet é = \"ü\";\nprint(é)";
+        let index = LineIndex::new(code);
+
+        assert_eq!(index.line_count(), 3);
+        assert_eq!(index.offset(2, 5), 16);
+        assert_eq!(index.position(16), (2, 5));
+        // Columns count characters, not bytes.
+        assert_eq!(index.position(code.find('ü').unwrap()), (2, 10));
+        assert_eq!(index.offset(2, 10), code.find('ü').unwrap());
+
+        assert_eq!(&code[index.line_range(1)], "let a = 1;");
+        assert_eq!(&code[index.line_range(3)], "print(é)");
+        assert_eq!(index.line_range(4), code.len()..code.len());
+        assert_eq!(index.position(code.len() + 10), (3, 9));
+
+        // Columns past the end of a line stay on it.
+        assert_eq!(index.offset(1, 20), code.find('\r').unwrap());
+        assert_eq!(index.offset(3, 20), code.len());
+        assert_eq!(LineIndex::new("ab\ncd\n").offset(1, 10), 2);
+
+        // Single lookups scan the source instead, with the same results.
+        let code = "\u{FEFF}let a = 1;\r\nlet é = \"ü\";\n\nprint(é)\r";
+        let index = LineIndex::new(code);
+        for line in 0..=6 {
+            assert_eq!(line_index::scan_line_range(code, line), index.line_range(line));
+            for column in 0..=12 {
+                let offset = index.offset(line, column);
+                assert_eq!(line_index::scan_offset(code, line, column), offset);
+                assert_eq!(line_index::scan_position(code, offset), index.position(offset));
+            }
+        }
+        assert_eq!(line_index::scan_position(code, 0), index.position(0));
+        assert_eq!(line_index::scan_position(code, 100), index.position(100));
+    }
+
+    #[test]
+    fn test_large_script_extraction() {
+        // Every span used to rescan the script from the start, which made
+        // extraction quadratic in the number of lines.
+        let mut code = String::from("let total = 0;\n");
+        for i in 0..10_000 {
+            code.push_str(&format!("total += {};\n", i));
+        }
+
+        let engine = Engine::new();
+        let ast = engine.compile(&code).unwrap();
+        let spans = SpanTracer::new().extract_from_ast(&ast, &code).unwrap();
+
+        let last = spans.iter().max_by_key(|span| span.start()).unwrap();
+        assert_eq!(last.line(), 10_001);
+        assert_eq!(last.snippet(&code), Some("9999;"));
+
+        let index = LineIndex::new(&code);
+        assert_eq!(index.position(last.start()), (last.line(), last.column()));
+        assert_eq!(index.offset(last.line(), last.column()), last.start());
+    }
+
+    #[test]
+    fn test_tracer_with_engine() {
+        let mut engine = Engine::new();
+        engine
+            .register_custom_syntax(["unless", "$expr$", "$block$"], false, |context, inputs| {
+                if context.eval_expression_tree(&inputs[0])?.as_bool().unwrap_or(false) {
+                    return Ok(Dynamic::UNIT);
+                }
+                context.eval_expression_tree(&inputs[1])
+            })
+            .unwrap();
+        engine.register_custom_operator("implies", 160).unwrap();
+        engine.register_fn("implies", |a: bool, b: bool| !a || b);
+
+        let code = "let ok = false implies true;\nunless ok { print(\"no\"); }";
+        assert!(SpanTracer::new().extract_from(code).is_err());
+
+        let tracer = SpanTracer::with_engine(engine);
+        let spans = tracer.extract_from(code).unwrap();
+        assert!(spans.iter().any(|span| span.line() == 2));
+
+        let ranges = tracer.highlight_ranges(code).unwrap();
+        let operator = code.find("implies").unwrap();
+        let (_, kind) = ranges.iter().find(|(range, _)| range.contains(&operator)).unwrap();
+        assert_eq!(*kind, SpanKind::Operator);
+
+        tracer.engine().run(code).unwrap();
+    }
+
+    #[test]
+    fn test_error_codes() {
+        for code in ErrorCode::ALL {
+            assert_eq!(code.as_str().parse::<ErrorCode>(), Ok(*code));
+            assert!(!code.explanation().is_empty());
+        }
+        assert_eq!("rt0201".parse::<ErrorCode>(), Ok(ErrorCode::InfiniteLoop));
+        assert!("RT0000".parse::<ErrorCode>().is_err());
+
+        let mut codes: Vec<&str> = ErrorCode::ALL.iter().map(ErrorCode::as_str).collect();
+        codes.dedup();
+        assert_eq!(codes.len(), ErrorCode::ALL.len(), "Codes must be unique");
+    }
+
+    #[test]
+    fn test_span_none() {
+        assert!(Span::NONE.is_none());
+        assert!(Span::from_pos("let a = 1;", &Position::NONE).is_none());
+        assert!(!Span::new(0, 0, 1, 1).is_none());
+
+        let span = Span::new(4, 5, 1, 5);
+        assert_eq!(span.merge(&Span::NONE), span);
+        assert_eq!(Span::NONE.merge(&span), span);
+
+        // An error without a position has nothing to point at.
+        let code = "let a = 42;\nlet z = a / 0;";
+        let engine = Engine::new();
+        let err = rhai::EvalAltResult::ErrorRuntime("failed".into(), Position::NONE);
+
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert!(better.span.is_none());
+    }
+
+    #[test]
+    fn test_data_too_large_limits() {
+        let cases = [
+            (
+                "let s = \"abc\";\nfor i in 0..10 { s += \"abcdef\"; }",
+                "The string grew to at least 11 bytes; the engine's max_string_size is 10.",
+                "Engine::set_max_string_size",
+            ),
+            (
+                "let a = [];\nfor i in 0..20000 { a.push(i); }",
+                "The array grew to at least 10,001 elements; the engine's max_array_size is 10,000.",
+                "Engine::set_max_array_size",
+            ),
+            (
+                "let m = #{a: 1};\nm.b = 2; m.c = 3;",
+                "The object map grew to at least 3 entries; the engine's max_map_size is 2.",
+                "Engine::set_max_map_size",
+            ),
+        ];
+
+        let mut engine = Engine::new();
+        engine
+            .set_max_string_size(10)
+            .set_max_array_size(10_000)
+            .set_max_map_size(2);
+
+        for (code, help, setter) in cases {
+            let err = engine.eval::<Dynamic>(code).unwrap_err();
+            let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+
+            assert_eq!(better.help.as_deref(), Some(help));
+            assert!(better.hint.unwrap().contains(setter));
+            assert_eq!(better.span.line(), 2, "The span should point at the growing statement");
+        }
+
+        // The largest possible limit must not overflow.
+        engine.set_max_array_size(usize::MAX);
+        let code = "let a = [];";
+        let err = rhai::EvalAltResult::ErrorDataTooLarge("Size of array".into(), Position::new(1, 9));
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        let help = better.help.unwrap();
+        let (grew, max) = help.split_once(" elements; the engine's max_array_size is ").unwrap();
+        assert_eq!(grew.strip_prefix("The array grew to at least "), max.strip_suffix('.'));
+    }
+
+    #[test]
+    fn test_switch_arm_spans() {
+        let code = "let x = 7;\nswitch x {\n    1 => print(\"one\"),\n    7 => { throw \"bad\"; }\n    2..5 => 2,\n    _ => { throw \"default\"; }\n}";
+        let engine = Engine::new();
+
+        let spans = SpanTracer::new().highlight_ranges(code).unwrap();
+        let switch = code.find("switch").unwrap();
+        assert!(spans
+            .iter()
+            .any(|(range, kind)| range.start == switch && *kind == SpanKind::Switch));
+
+        // Conditions and bodies of value, range and default arms all get spans.
+        let guarded = code.replace("2..5 => 2,", "2..5 if x > 9 => x + 1,");
+        let spans = SpanTracer::new().extract_from(&guarded).unwrap();
+        for needle in ["x {", "print", "throw \"bad\"", "x > 9", "> 9", "+ 1", "throw \"default\""] {
+            let start = guarded.find(needle).unwrap();
+            assert!(
+                spans.iter().any(|span| span.start() == start),
+                "no span at `{}`",
+                needle
+            );
+        }
+
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!((better.span.line(), better.span.column()), (4, 12));
+        assert!(code[better.span.start()..].starts_with("throw \"bad\""));
+
+        let code = code.replace("7 =>", "8 =>");
+        let err = engine.eval::<Dynamic>(&code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, &code, &engine, None, None).unwrap();
+        assert_eq!((better.span.line(), better.span.column()), (6, 12));
+    }
+
+    #[test]
+    fn test_span_expand_to_line() {
+        let code = "let a = 1;\nlet b = a + 2;\nlet c = b * 3;";
+
+        // A span on the last line, which has no terminating newline.
+        let span = Span::from_range(code, 34..35);
+        let line = span.expand_to_line(code);
+        assert_eq!(line.snippet(code), Some("let c = b * 3;"));
+        assert_eq!((line.line(), line.column()), (3, 1));
+        assert_eq!((line.end_line(), line.end_column()), (3, 15));
+
+        let span = Span::from_range(code, 8..19);
+        let lines = span.expand_to_line(code);
+        assert_eq!(lines.snippet(code), Some("let a = 1;\nlet b = a + 2;"));
+        assert_eq!((lines.line(), lines.end_line()), (1, 2));
+
+        // Ending right after a newline does not pull in the next line.
+        let span = Span::from_range(code, 0..11);
+        assert_eq!(span.expand_to_line(code).end_line(), 1);
+
+        let middle = Span::from_range(code, 19..20);
+        assert_eq!(middle.context(code, 1, 1).snippet(code), Some(code));
+        assert_eq!(middle.context(code, 5, 0).snippet(code), Some("let a = 1;\nlet b = a + 2;"));
+        assert_eq!(middle.context(code, 0, 5).line(), 2);
+        assert_eq!(middle.context(code, 0, 5).end_line(), 3);
+
+        assert!(Span::NONE.expand_to_line(code).is_none());
+    }
+
+    #[test]
+    fn test_closure_spans() {
+        let code = "let xs = [1, 2, 3];\nlet ys = xs.map(|x| x + missing);";
+        let body = code.find("x + missing").unwrap();
+        let closure_end = code.rfind(')').unwrap();
+
+        let spans = SpanTracer::new().extract_from(code).unwrap();
+        assert!(spans.iter().any(|span| span.start() == body));
+
+        let engine = Engine::new();
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert!(better.span.start() > code.find('|').unwrap());
+        assert!(better.span.start() < closure_end);
+        assert_eq!(better.span.snippet(code).map(|s| s.starts_with("missing")), Some(true));
+
+        // Closures nested two levels deep, with block bodies, and statement expressions.
+        let code = "let xs = [1, 2];\nlet ys = xs.map(|x| {\n    let inner = [x].map(|y| {\n        let z = y * 2;\n        z + missing\n    });\n    inner\n});\nlet v = { let a = 1; a + 2 };";
+        let spans = SpanTracer::new().extract_from(code).unwrap();
+        for needle in ["let inner", "[x]", "let z", "y * 2", "z + missing", "let a", "a + 2"] {
+            let start = code.find(needle).unwrap();
+            assert!(spans.iter().any(|span| span.start() == start), "no span at `{}`", needle);
+        }
+
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.span.line(), 5);
+        assert_eq!(better.span.snippet(code), Some("missing"));
+    }
+
+    #[test]
+    fn test_correlate_diagnostics() {
+        let code = "let coutn = 0;\ncount += 1;\nprint(count);\nlet doubled = count * 2;\nlet half = count / 2;\nlet x = y + 1;\nfn area(w) { w * height }\nlet a = area(2);\nfn other() { nope }";
+        let error = |needle: &str, nth: usize| {
+            let start = code.match_indices(needle).nth(nth).unwrap().0;
+            Diagnostic::new(
+                BetterError {
+                    message: format!("Variable not found: {}", needle),
+                    help: None,
+                    hint: None,
+                    note: None,
+                    span: Span::from_range(code, start..start + needle.len()),
+                    code: Some(ErrorCode::VariableNotFound),
+                    labels: Vec::new(),
+                    severity: Severity::Error,
+                },
+            )
+        };
+
+        let mut diagnostics = Diagnostics::new(code);
+        let root = diagnostics.push(error("count", 0));
+        let cascade = [
+            diagnostics.push(error("count", 1)),
+            diagnostics.push(error("count", 2)),
+            diagnostics.push(error("count", 3)),
+        ];
+        let unrelated = diagnostics.push(error("y", 0));
+        let in_area = diagnostics.push(error("height", 0));
+        let call_to_area = diagnostics.push(error("area", 1));
+        let in_other = diagnostics.push(error("nope", 0));
+
+        diagnostics.correlate();
+
+        assert_eq!(diagnostics[root].caused_by, None);
+        for id in cascade {
+            assert_eq!(diagnostics[id].caused_by, Some(root));
+        }
+        assert_eq!(diagnostics[unrelated].caused_by, None);
+        assert_eq!(diagnostics[in_area].caused_by, None);
+        assert_eq!(diagnostics[call_to_area].caused_by, Some(in_area));
+        assert_eq!(diagnostics[in_other].caused_by, None);
+        assert_eq!(diagnostics.caused_by(root).count(), 3);
+
+        let shown = render::render_diagnostics(&diagnostics, "script.rhai", render::Cascade::Show);
+        let suppressed =
+            render::render_diagnostics(&diagnostics, "script.rhai", render::Cascade::Suppress);
+        let indented =
+            render::render_diagnostics(&diagnostics, "script.rhai", render::Cascade::Indent);
+        assert_eq!(shown.matches("error[RT0101]").count(), 8);
+        assert_eq!(suppressed.matches("error[RT0101]").count(), 4);
+        assert!(suppressed.contains("3 more diagnostics likely caused by this one were hidden."));
+        assert_eq!(indented.matches("\n    error[RT0101]").count(), 4);
+    }
+
+    #[test]
+    fn test_extracted_spans_sorted_and_unique() {
+        let code = "fn double(x) { x * 2 }\nlet a = double(21);\nlet b = [a, a + 1].map(|v| v - 1);\nif a > 1 { print(b); }";
+        let tracer = SpanTracer::new();
+        let spans = tracer.extract_from(code).unwrap();
+
+        assert!(!spans.is_empty());
+        for pair in spans.windows(2) {
+            assert_ne!(pair[0], pair[1]);
+            assert!(pair[0].start() <= pair[1].start());
+            // No two spans cover the same bytes, whatever their kind.
+            assert_ne!((pair[0].start(), pair[0].end()), (pair[1].start(), pair[1].end()));
+            assert!(pair[0] < pair[1]);
+        }
+
+        // Every other extraction method keeps the same order.
+        let ast = tracer.engine().compile(code).unwrap();
+        assert_eq!(tracer.extract_from_ast(&ast, code).unwrap(), spans);
+        let named = tracer
+            .extract_from_named(&mut SourceNames::new(), "double.rhai", code)
+            .unwrap();
+        assert!(named.windows(2).all(|pair| pair[0] < pair[1]));
+        let host = format!("script: |\n{}", code);
+        let embedded = tracer
+            .extract_from_embedded(code, Span::from_byte_range(&host, 10, 10))
+            .unwrap();
+        assert!(embedded.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(embedded.len(), spans.len());
+    }
+
+    #[test]
+    fn test_span_utf16_range() {
+        // "😀" is 4 bytes and 2 UTF-16 units, "漢字" is 6 bytes and 2 units.
+        let code = "let a = 1;\nlet s = \"😀漢字\" + name;";
+        let start = code.find("name").unwrap();
+        let span = Span::from_range(code, start..start + 4);
+
+        assert_eq!(span.to_utf16_range(code), ((1, 17), (1, 21)));
+
+        let index = LineIndex::new(code);
+        assert_eq!(index.utf16_position(0), (0, 0));
+        assert_eq!(index.utf16_position(code.find('漢').unwrap()), (1, 11));
+        // Inside the emoji, the position moves back to its start.
+        assert_eq!(index.utf16_position(code.find('😀').unwrap() + 1), (1, 9));
+        assert_eq!(index.utf16_position(usize::MAX), (1, 22));
+    }
+
+    #[test]
+    fn test_span_kinds() {
+        let code = "fn add(x, y) { x + y }\nlet a = 1;\nlet b = 2;\nlet c = add(a,b);";
+        let spans = SpanTracer::new().extract_from(code).unwrap();
+
+        let call = code.find("add(a,b)").unwrap();
+        let span = spans.iter().find(|span| span.start() == call).unwrap();
+        assert_eq!(span.kind(), SpanKind::FnCall);
+
+        let decl = spans.iter().find(|span| span.start() == code.find("let a").unwrap());
+        assert_eq!(decl.map(Span::kind), Some(SpanKind::VariableDecl));
+
+        assert_eq!(Span::new(0, 1, 1, 1).kind(), SpanKind::Other);
+        assert_eq!(span.merge(&Span::new(0, 1, 1, 1)).kind(), SpanKind::Other);
+        assert_eq!(span.merge(span).kind(), SpanKind::FnCall);
+    }
+
+    #[test]
+    fn test_span_display_and_compact_form() {
+        let span = Span::new(12, 27, 3, 12).with_end_position(3, 27);
+        assert_eq!(span.to_string(), "3:12..3:27");
+        assert_eq!(format!("{:#}", span), "line 3, col 12 (bytes 12..27)");
+        assert_eq!(format!("{:#}", Span::new(24, 24, 3, 5)), "line 3, col 5 (bytes 24..24)");
+        assert_eq!(span.to_compact_string(), "12..27@3:12..3:27");
+
+        let code = "let a = 1;\nlet b = a +\n    2;";
+        for span in SpanTracer::new().extract_from(code).unwrap() {
+            let parsed = Span::parse_compact(&span.to_compact_string()).unwrap();
+            assert_eq!(parsed, span.with_kind(SpanKind::Other));
+        }
+
+        for malformed in ["", "12..27", "12..27@3:12", "a..27@3:12..3:27", "12..27@3:12..3"] {
+            assert!(matches!(
+                Span::parse_compact(malformed),
+                Err(TraceError::MalformedSpan { .. })
+            ));
+        }
+        assert_eq!(
+            Span::parse_compact("27..12@3:12..3:27"),
+            Err(TraceError::InvalidSpan { start: 27, end: 12 })
+        );
+    }
+
+    #[test]
+    fn test_narrowest_span_for_position() {
+        let code = "let a = parse_int(\"42\");\nlet z = a/0;";
+        let engine = Engine::new();
+        let division = code.find('/').unwrap();
+
+        // An arithmetic error at the `/` covers the operation, not the statement.
+        let err =
+            rhai::EvalAltResult::ErrorArithmetic("Division by zero".into(), Position::new(2, 10));
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+
+        assert_eq!(better.span.start(), division - 1);
+        assert_eq!(better.span.kind(), SpanKind::Operator);
+        assert_eq!(better.span.snippet(code), Some("a/0"));
+    }
+
+    #[test]
+    fn test_extract_from_embedded() {
+        let host = "steps:\n  - run: let a = 1;\n    let b = a + 2;\n";
+        let start = host.find("let a").unwrap();
+        let script = &host[start..];
+
+        let host_offset = Span::from_byte_range(host, start, start);
+        let spans = SpanTracer::new()
+            .extract_from_embedded(script, host_offset)
+            .unwrap();
+        let index = LineIndex::new(host);
+
+        assert!(!spans.is_empty());
+        for span in &spans {
+            assert_eq!(index.position(span.start()), (span.line(), span.column()));
+        }
+        let decl = spans.iter().find(|span| span.kind() == SpanKind::VariableDecl).unwrap();
+        assert_eq!((decl.line(), decl.column()), (2, 10));
+        assert!(host[decl.start()..].starts_with("let a"));
+
+        assert!(Span::NONE.with_offset(10, 1, 1).is_none());
+    }
+
+    #[test]
+    fn test_source_ids() {
+        use rhai::module_resolvers::StaticModuleResolver;
+        use rhai::{Module, Scope};
+
+        let mut engine = Engine::new();
+        let mut module_ast = engine.compile("fn half(x) {\n    x / missing\n}").unwrap();
+        module_ast.set_source("utils.rhai");
+        let module = Module::eval_ast_as_new(Scope::new(), &module_ast, &engine).unwrap();
+        let mut resolver = StaticModuleResolver::new();
+        resolver.insert("utils", module);
+        engine.set_module_resolver(resolver);
+
+        let code = "import \"utils\" as u;\nlet h = u::half(4);";
+        let mut names = SourceNames::new();
+        let spans = SpanTracer::new().extract_from_named(&mut names, "main.rhai", code).unwrap();
+        let main = names.get("main.rhai").unwrap();
+        assert!(spans.iter().all(|span| span.source_id() == Some(main)));
+        assert_eq!(spans[0].source_name(&names), Some("main.rhai"));
+
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, Some(spans), None).unwrap();
+
+        // The span points at the call in the script, the note into the module.
+        assert_eq!(better.span.source_id(), Some(main));
+        assert_eq!(better.span.line(), 2);
+        assert!(code[better.span.start()..].starts_with("half(4)"));
+        let text = render::render_text_named(&better, &names, code);
+        assert!(text.contains(" --> main.rhai:2:"));
+        assert!(render::render_github_named(&better, &names).contains("file=main.rhai,line=2"));
+        let note = better.note.unwrap();
+        assert!(note.ends_with("\nRaised in `utils.rhai` at line 2, column 9."));
+
+        assert_eq!(Span::new(0, 1, 1, 1).source_id(), None);
+        assert_eq!(Span::new(0, 1, 1, 1).source_name(&names), None);
+        let utils = names.intern("utils.rhai");
+        assert_ne!(utils, main);
+        assert_eq!(names.intern("main.rhai"), main);
+        assert_eq!(names.name(utils), Some("utils.rhai"));
+        assert_eq!(SourceNames::new().name(utils), None);
+    }
+
+    #[test]
+    fn test_function_call_error_position() {
+        let engine = Engine::new();
+        let code = "fn multiply(x, y) {\n    x * y\n}\nlet c = multiply(\"a\", 2);";
+        let body = code.find('{').unwrap()..code.find('}').unwrap();
+
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert!(body.contains(&better.span.start()));
+        assert_eq!(better.span.snippet(code).map(|s| s.starts_with('*')), Some(true));
+
+        // Division by zero carries no position, but the only division is found.
+        let code = "fn halve(x) {\n    x / 0\n}\nlet c = halve(4);";
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.span.line(), 2);
+        assert_eq!(better.span.snippet(code), Some("x / 0"));
+    }
+
+    #[test]
+    fn test_variable_suggestions() {
+        let engine = Engine::new();
+        let suggest = |code: &str| {
+            let err = engine.eval::<Dynamic>(code).unwrap_err();
+            BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap().hint
+        };
+
+        assert_eq!(suggest("let count = 1; cont + 1").as_deref(), Some("Did you mean `count`?"));
+        assert_eq!(
+            suggest("fn area(width) { widht * 2 }\narea(3)").as_deref(),
+            Some("Did you mean `width`?")
+        );
+        assert_eq!(
+            suggest("for item in [1, 2] { print(itme); }").as_deref(),
+            Some("Did you mean `item`?")
+        );
+        assert_eq!(
+            suggest("let total = 1; banana + 1").as_deref(),
+            Some("Check for typos or ensure the variable is initialized before use.")
+        );
+        // A single character is too short to be a typo of another.
+        assert_eq!(
+            suggest("let y = 1; x + 1").as_deref(),
+            Some("Check for typos or ensure the variable is initialized before use.")
+        );
+    }
+
+    #[test]
+    fn test_span_trim() {
+        let code = "let a = 1;   // one\n  \n  let b = a +   \n    2;";
+
+        let tight = Span::from_range(code, 0..10);
+        assert_eq!(tight.trim(code), tight);
+
+        let span = Span::from_range(code, 8..13).trim(code);
+        assert_eq!(span.snippet(code), Some("1;"));
+        assert_eq!((span.line(), span.column()), (1, 9));
+        assert_eq!((span.end_line(), span.end_column()), (1, 11));
+
+        // Leading whitespace across lines moves the start to a later line.
+        let start = code.find("\n  \n").unwrap();
+        let end = code.find("+").unwrap() + 4;
+        let span = Span::from_range(code, start..end).trim(code);
+        assert_eq!(span.snippet(code), Some("let b = a +"));
+        assert_eq!((span.line(), span.column()), (3, 3));
+        assert_eq!((span.end_line(), span.end_column()), (3, 14));
+        assert_eq!(span, Span::from_range(code, span.start()..span.end()));
+
+        let blank = Span::from_range(code, start..start + 4).trim(code);
+        assert_eq!(blank.start(), start);
+        assert_eq!(blank.end(), start);
+
+        assert!(Span::NONE.trim(code).is_none());
+        assert_eq!(Span::new(100, 200, 1, 1).trim(code), Span::new(100, 200, 1, 1));
+
+        // Errors are reported with trimmed spans.
+        let code = "let x = 1;\nlet y = x + missing;   ";
+        let engine = Engine::new();
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.span.snippet(code), Some("missing"));
+    }
+
+    #[test]
+    fn test_function_arity_mismatch() {
+        let engine = Engine::new();
+        let improve = |code: &str| {
+            let err = engine.eval::<Dynamic>(code).unwrap_err();
+            BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap()
+        };
+
+        let better = improve("fn add(x, y) { x + y }\nadd(1)");
+        let hint = better.hint.unwrap();
+        assert!(hint.starts_with("add expects 2 arguments but 1 was provided."));
+        assert!(hint.contains("add(x, y)"));
+
+        let better = improve("fn one(x) { x }\nfn one(x, y, z) { x }\none(1, 2)");
+        let hint = better.hint.unwrap();
+        assert!(hint.starts_with("one expects 1 or 3 arguments but 2 were provided."));
+
+        let better = improve("fn none() { 0 }\nnone(1)");
+        assert!(better.hint.unwrap().starts_with("none expects 0 arguments but 1 was provided."));
+
+        let better = improve("fn add(x, y) { x + y }\nsub(1)");
+        assert_eq!(better.help.unwrap(), "Function 'sub (i64)' is not defined.");
+    }
+
+    #[test]
+    fn test_extract_nodes() {
+        let code = r#"import "util" as util;
+const LIMIT = 3;
+let total = 0;
+total = total * LIMIT - 1;
+print(total);
+if total > 1 { total -= 1; } else { total += 1; }
+while total < LIMIT { total += 1; }
+loop { break; }
+do { total -= 1; } until total <= 0;
+for i in [1, total] { if i == 1 { continue; } }
+switch total { 0 => print("none"), _ => () }
+try { throw "oops"; } catch (err) { print(err); }
+{ let inner = #{ a: total }; inner.a }
+fn twice(x) { if x == 0 { return 0; } x.abs() * 2 }
+"#;
+        let nodes = SpanTracer::new().extract_nodes(code).unwrap();
+        let has = |needle: &str, kind: SpanKind| {
+            let start = code.find(needle).unwrap_or_else(|| panic!("`{}` not found", needle));
+            nodes
+                .iter()
+                .any(|node| node.span.start() == start && node.kind == kind)
+        };
+
+        for (needle, kind) in [
+            ("import", SpanKind::Import),
+            ("\"util\"", SpanKind::Literal),
+            ("const", SpanKind::VariableDecl),
+            ("let total", SpanKind::VariableDecl),
+            ("total = total", SpanKind::Assignment),
+            ("total = total", SpanKind::Variable),
+            ("* LIMIT", SpanKind::Operator),
+            ("print(total)", SpanKind::FnCall),
+            ("if total", SpanKind::If),
+            ("total -= 1; }", SpanKind::Assignment),
+            ("while", SpanKind::While),
+            ("loop", SpanKind::While),
+            ("break", SpanKind::Break),
+            ("do", SpanKind::Do),
+            ("for", SpanKind::For),
+            ("[1, total]", SpanKind::Array),
+            ("continue", SpanKind::Continue),
+            ("switch", SpanKind::Switch),
+            ("try", SpanKind::TryCatch),
+            ("throw", SpanKind::Throw),
+            ("let inner", SpanKind::VariableDecl),
+            ("#{", SpanKind::Map),
+            ("inner.a", SpanKind::Variable),
+            ("return", SpanKind::Return),
+            ("abs", SpanKind::MethodCall),
+        ] {
+            assert!(has(needle, kind), "expected {:?} at `{}`", kind, needle);
+        }
+
+        for node in &nodes {
+            assert_eq!(node.span.kind(), node.kind);
+        }
+        let position = |node: &SpanNode| node.span.with_kind(SpanKind::Other);
+        assert!(nodes.windows(2).all(|pair| position(&pair[0]) <= position(&pair[1])));
+
+        // `extract_from` keeps one span per position, with the innermost kind.
+        let spans = SpanTracer::new().extract_from(code).unwrap();
+        let mut positions: Vec<Span> = nodes.iter().map(position).collect();
+        positions.dedup();
+        assert_eq!(spans.len(), positions.len());
+        let assignment = code.find("total = total").unwrap();
+        let span = spans.iter().find(|span| span.start() == assignment).unwrap();
+        assert_eq!(span.kind(), SpanKind::Variable);
+    }
+
+    #[test]
+    fn test_better_error_codes() {
+        let engine = Engine::new();
+        let improve = |code: &str| {
+            let err = engine.eval::<Dynamic>(code).unwrap_err();
+            BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap()
+        };
+
+        let better = improve("let x = 1;\nlet y = x + z;");
+        assert_eq!(better.code, Some(ErrorCode::VariableNotFound));
+        assert_eq!(better.code.unwrap().as_str(), "RT0101");
+
+        assert_eq!(improve("missing(1)").code, Some(ErrorCode::FunctionNotFound));
+        assert_eq!(improve("let a = [1];\na[5]").code, Some(ErrorCode::IndexOutOfBounds));
+        assert_eq!(improve("throw \"oops\";").code, Some(ErrorCode::RuntimeError));
+        // The code is that of the root cause, not of the function call around it.
+        assert_eq!(
+            improve("fn f() { nope }\nf()").code,
+            Some(ErrorCode::VariableNotFound)
+        );
+
+        let err = engine.compile("let x = ;").unwrap_err();
+        let better = BetterError::improve_parse_error(&err, "let x = ;").unwrap();
+        assert_eq!(better.code, Some(ErrorCode::SyntaxError));
+    }
+
+    #[test]
+    fn test_improve_eval_error_with_ast() {
+        let engine = Engine::new();
+
+        for code in [
+            "let x = 1;\nlet y = x + z;",
+            "fn add(x, y) { x + y }\nadd(1)",
+            "fn f(a) { a / 0 }\nlet r = f(1);",
+            "let list = [1, 2];\nlist[7]",
+        ] {
+            let ast = engine.compile(code).unwrap();
+            let err = engine.eval_ast::<Dynamic>(&ast).unwrap_err();
+
+            let from_code = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+            let from_ast =
+                BetterError::improve_eval_error_with_ast(&err, code, &engine, &ast).unwrap();
+            assert_eq!(from_ast, from_code, "for script {:?}", code);
+        }
+    }
+
+    #[test]
+    fn test_custom_syntax_spans() {
+        let mut engine = Engine::new();
+        engine
+            .register_custom_syntax(
+                ["do_thing", "$expr$", "times", "$block$"],
+                false,
+                |context, inputs| {
+                    let times = context.eval_expression_tree(&inputs[0])?.as_int()?;
+                    for _ in 0..times {
+                        let _ = context.eval_expression_tree(&inputs[1])?;
+                    }
+                    Ok(Dynamic::UNIT)
+                },
+            )
+            .unwrap();
+
+        let code = "let n = 2;\ndo_thing n times {\n    let x = n + missing;\n}";
+        assert!(SpanTracer::new().extract_from(code).is_err());
+
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.span.line(), 3);
+        assert_eq!(better.span.snippet(code), Some("missing"));
+
+        let tracer = SpanTracer::with_engine(engine);
+        let spans = tracer.extract_from(code).unwrap();
+        let custom = code.find("do_thing").unwrap();
+        assert!(spans.iter().any(|span| span.start() == custom && span.kind() == SpanKind::Custom));
+        let inner = code.find("let x").unwrap();
+        assert!(spans.iter().any(|span| span.start() == inner));
+    }
+
+    #[test]
+    fn test_function_definition_spans() {
+        let code = "fn multiply(x, y) {\n    x * y\n}\nprivate fn helper() { 1 }\nlet c = multiply(\"a\", 7);";
+        let spans = SpanTracer::new().extract_from(code).unwrap();
+
+        let signatures: Vec<&str> = spans
+            .iter()
+            .filter(|span| span.kind() == SpanKind::FnDef)
+            .filter_map(|span| span.snippet(code))
+            .collect();
+        assert_eq!(signatures, ["fn multiply(x, y)", "private fn helper()"]);
+
+        let body = code.find("x * y").unwrap();
+        assert!(spans.iter().any(|span| span.start() == body));
+
+        let engine = Engine::new();
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, Some(spans), None).unwrap();
+        assert_eq!(better.span.line(), 2);
+        assert!(better.span.start() > body && better.span.start() < code.find('}').unwrap());
+    }
+
+    #[test]
+    fn test_secondary_labels() {
+        let code = "fn check(count, flag) {\n    if flag { count } else { 0 }\n}\ncheck(1, 5)";
+        let engine = Engine::new();
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+
+        assert_eq!(better.span.line(), 2);
+        assert_eq!(better.labels.len(), 1);
+        let (span, message) = &better.labels[0];
+        assert_eq!(span.snippet(code), Some("flag"));
+        assert_eq!((span.line(), span.column()), (1, 17));
+        assert_eq!(message, "parameter `flag` is declared here");
+
+        // Comments in the signature are skipped, even when they mention the parameter.
+        let code = "fn check(/* flag */ count, // flag\n flag) {\n    if flag { count } else { 0 }\n}\ncheck(1, 5)";
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        let (span, _) = &better.labels[0];
+        assert_eq!(span.snippet(code), Some("flag"));
+        assert_eq!((span.line(), span.column()), (2, 2));
+
+        // Other errors, and mismatches outside functions, have no secondary labels.
+        let code = "let x = 5;\nif x { 1 }";
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert!(better.labels.is_empty());
+    }
+
+    #[test]
+    fn test_better_error_severity() {
+        let code = "let x = 1;\nreturn x;";
+        let engine = Engine::new();
+
+        let err = rhai::EvalAltResult::Return(Dynamic::from(1_i64), Position::new(2, 1));
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.severity, Severity::Note);
+
+        let err = rhai::EvalAltResult::LoopBreak(true, Dynamic::UNIT, Position::new(2, 1));
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.severity, Severity::Note);
+
+        let err = engine.eval::<Dynamic>("let y = z;").unwrap_err();
+        let better = BetterError::improve_eval_error(&err, "let y = z;", &engine, None, None).unwrap();
+        assert_eq!(better.severity, Severity::Error);
+        assert_eq!(Severity::default(), Severity::Error);
+    }
+
+    #[test]
+    fn test_composite_expression_spans() {
+        let code = "fn default_port() { 80 }\nlet config = #{ servers: [#{ port: () }] };\nlet idx = 0;\nlet ok = idx >= 0 && idx < 1 || idx == 9;\nlet port = config.servers[idx].port ?? default_port();";
+        let spans = SpanTracer::new().extract_from(code).unwrap();
+        let span_at = |needle: &str| {
+            let start = code.rfind(needle).unwrap();
+            spans.iter().find(|span| span.start() == start).copied()
+        };
+
+        assert_eq!(span_at("idx]").map(|span| span.kind()), Some(SpanKind::Variable));
+        assert_eq!(span_at("default_port()").map(|span| span.kind()), Some(SpanKind::FnCall));
+        assert_eq!(span_at("< 1").map(|span| span.kind()), Some(SpanKind::Operator));
+        assert_eq!(span_at("== 9").map(|span| span.kind()), Some(SpanKind::Operator));
+
+        let engine = Engine::new();
+        let code = code.replace("let idx = 0;", "let idx = 0;\nlet y = true && missing;");
+        let err = engine.eval::<Dynamic>(&code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, &code, &engine, None, None).unwrap();
+        assert_eq!(better.span.snippet(&code), Some("missing"));
+    }
+
+    #[test]
+    fn test_extract_tree() {
+        let script = "fn twice(n) { n * 2 }\nlet total = 0;\nfor i in 0..3 {\n    total += twice(i);\n}";
+        let tree = SpanTracer::new().extract_tree(script).unwrap();
+
+        assert_eq!(tree.kind, SpanKind::Script);
+        assert_eq!(tree.span.start(), 0);
+        assert_eq!(tree.span.end(), script.len());
+
+        // Operands are children of their operator, even the left one, which starts
+        // before the operator's position.
+        let product = tree.innermost_at(script.find('*').unwrap()).unwrap();
+        assert_eq!(product.kind, SpanKind::Operator);
+        let operand = script.find("n *").unwrap();
+        assert!(product.children.iter().any(|node| node.span.start() == operand));
+
+        // Pre-order: the root first, then each child before its own children.
+        let kinds: Vec<_> = tree.iter().map(|node| node.kind).collect();
+        assert_eq!(kinds[0], SpanKind::Script);
+        let for_at = kinds.iter().position(|kind| *kind == SpanKind::For).unwrap();
+        let assign_at = kinds.iter().position(|kind| *kind == SpanKind::Assignment).unwrap();
+        assert!(for_at < assign_at);
+        assert_eq!(tree.iter().count(), SpanTracer::new().extract_nodes(script).unwrap().len() + 1);
+
+        // The call to `twice` is nested in the assignment, which is nested in the loop.
+        let offset = script.find("twice(i)").unwrap();
+        let name = tree.innermost_at(offset).unwrap();
+        assert_eq!(name.kind, SpanKind::FnName);
+        let call = tree
+            .iter()
+            .find(|node| node.kind == SpanKind::FnCall && node.children.contains(name))
+            .unwrap();
+        let for_node = tree.children.iter().find(|node| node.kind == SpanKind::For).unwrap();
+        let assignment = for_node
+            .iter()
+            .find(|node| node.kind == SpanKind::Assignment)
+            .unwrap();
+        assert!(assignment.iter().any(|node| node == call));
+
+        // The signature is a top-level node.
+        assert!(tree.children.iter().any(|node| node.kind == SpanKind::FnDef));
+
+        // Offsets past the end are outside the tree.
+        assert!(tree.innermost_at(script.len()).is_none());
+    }
+
+    #[test]
+    fn test_span_at() {
+        let script = "let a = 1;\nlet b = a + 2;\n\n  print(b);\n";
+        let tracer = SpanTracer::new();
+
+        // The smallest enclosing span wins over the statement around it.
+        let span = tracer.span_at(script, 2, 9).unwrap().unwrap();
+        assert_eq!(span.start(), script.find("a +").unwrap());
+        assert_eq!(span.kind(), SpanKind::Variable);
+        let offset = script.find("2;").unwrap();
+        assert_eq!(tracer.span_at_offset(script, offset).unwrap().unwrap().start(), offset);
+
+        // Columns past the end of a line stay on that line.
+        let span = tracer.span_at(script, 1, 40).unwrap().unwrap();
+        assert_eq!(span.line(), 1);
+
+        // Indentation falls back to the nearest span on the line.
+        let span = tracer.span_at(script, 4, 1).unwrap().unwrap();
+        assert_eq!(span.start(), script.find("print").unwrap());
+
+        // Blank lines, the empty last line and offsets past the end have no span.
+        assert_eq!(tracer.span_at(script, 3, 1).unwrap(), None);
+        assert_eq!(tracer.span_at(script, 5, 1).unwrap(), None);
+        assert_eq!(tracer.span_at_offset(script, script.len() + 1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_improve_all_parse_errors() {
+        let engine = Engine::new();
+        let code = "let total = 1 +;\nfn double(x) {\n    x * 2\n}\nlet items = [1, 2;\nprint(double(total));";
+
+        let errors = BetterError::improve_all_parse_errors(code, &engine).unwrap();
+        assert_eq!(errors.len(), 2, "{:#?}", errors);
+        assert_eq!(errors[0].span.line(), 1);
+        assert_eq!(errors[1].span.line(), 5);
+        // Spans point into the original code.
+        assert!(code[errors[1].span.start()..].starts_with(';'));
+        assert!(errors.iter().all(|error| error.code == Some(ErrorCode::SyntaxError)));
+
+        // The first error matches the one reported on its own.
+        let first = engine.compile(code).unwrap_err();
+        let single = BetterError::improve_parse_error(&first, code).unwrap();
+        assert_eq!(errors[0], single);
+
+        assert!(BetterError::improve_all_parse_errors("let a = 1;", &engine).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_improve_eval_error_with_scope() {
+        let engine = Engine::new();
+        let mut scope = rhai::Scope::new();
+        scope.push("max_health", 100_i64);
+        scope.push_constant("difficulty", "hard");
+
+        let code = "let damage = 10;\nlet left = max_helth - damage;";
+        let err = engine.run_with_scope(&mut scope, code).unwrap_err();
+
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, Some(&scope)).unwrap();
+        assert!(better.hint.unwrap().starts_with("Did you mean `max_health`?\n"));
+        assert_eq!(better.span.line(), 2);
+
+        // Without the scope, the variable is nowhere to be found.
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_ne!(better.hint.as_deref(), Some("Did you mean `max_health`?"));
+
+        // Names declared in the script are still suggested alongside the scope.
+        let code = "let damage = 10;\nlet left = max_health - damag;";
+        let err = engine.run_with_scope(&mut scope, code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, Some(&scope)).unwrap();
+        assert!(better.hint.unwrap().starts_with("Did you mean `damage`?\n"));
+    }
+
+    #[test]
+    fn test_visit_spans() {
+        use std::ops::ControlFlow;
+
+        let script = "fn double(x) { x * 2 }\nlet a = double(21);\nif a > 40 {\n    print(a);\n}";
+        let tracer = SpanTracer::new();
+
+        // Visiting everything sees the same nodes `extract_nodes` collects.
+        let mut visited = Vec::new();
+        tracer
+            .visit(script, |node| {
+                visited.push(*node);
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        let mut nodes = tracer.extract_nodes(script).unwrap();
+        assert_eq!(visited.len(), nodes.len());
+        visited.sort_by_key(|node| (node.span, node.kind));
+        nodes.sort_by_key(|node| (node.span, node.kind));
+        assert_eq!(visited, nodes);
+
+        // Breaking stops the walk right away.
+        let mut calls = 0;
+        tracer
+            .visit(script, |_| {
+                calls += 1;
+                match calls {
+                    3 => ControlFlow::Break(()),
+                    _ => ControlFlow::Continue(()),
+                }
+            })
+            .unwrap();
+        assert_eq!(calls, 3);
+
+        // A parent is visited before the nodes nested inside it.
+        let mut kinds = Vec::new();
+        tracer
+            .visit("if ok { launch(); }", |node| {
+                kinds.push(node.kind);
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert_eq!(kinds[0], SpanKind::If);
+
+        assert!(tracer.visit("let = ;", |_| ControlFlow::Continue(())).is_err());
+    }
+
+    #[test]
+    fn test_variable_not_found_lists_scope() {
+        let engine = Engine::new();
+        let mut scope = rhai::Scope::new();
+        scope.push("foo", 1_i64);
+        scope.push("bar", 2_i64);
+        // Shadowing does not list a name twice.
+        scope.push("foo", 3_i64);
+
+        let code = "print(baz);";
+        let err = engine.run_with_scope(&mut scope, code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, Some(&scope)).unwrap();
+        let hint = better.hint.unwrap();
+        assert!(hint.contains("`foo`") && hint.contains("`bar`"), "{}", hint);
+        assert!(hint.ends_with("Variables in scope: `foo`, `bar`."), "{}", hint);
+
+        // Long scopes are trimmed, keeping the most recently bound variables.
+        let mut scope = rhai::Scope::new();
+        for i in 0..25 {
+            scope.push(format!("value_{}", i), i as i64);
+        }
+        let err = engine.run_with_scope(&mut scope, code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, Some(&scope)).unwrap();
+        let hint = better.hint.unwrap();
+        assert!(hint.contains("`value_24`, `value_23`"), "{}", hint);
+        assert!(hint.contains("`value_15` and 15 more."), "{}", hint);
+        assert!(!hint.contains("`value_14`"));
+
+        // Without a scope, or with an empty one, nothing is listed.
+        let err = engine.run(code).unwrap_err();
+        let empty = rhai::Scope::new();
+        for scope in [None, Some(&empty)] {
+            let better = BetterError::improve_eval_error(&err, code, &engine, None, scope).unwrap();
+            assert!(!better.hint.unwrap().contains("in scope"));
+        }
+    }
+
+    #[test]
+    fn test_spans_without_optimization() {
+        let code = "let x = 1 + 2;\nif false { print(x); }";
+        let one = code.find('1').unwrap();
+        let two = code.find('2').unwrap();
+        let print = code.find("print").unwrap();
+
+        // The operands of the folded addition and the dead branch keep their spans.
+        let spans = SpanTracer::new().extract_from(code).unwrap();
+        for offset in [one, two, print] {
+            assert!(spans.iter().any(|span| span.start() == offset), "no span at {}", offset);
+        }
+        let plus = code.find('+').unwrap();
+        assert!(spans.iter().any(|span| span.start() == plus && span.kind() == SpanKind::Operator));
+
+        // An optimizing tracer only sees the folded constant.
+        let optimized = SpanTracer::new()
+            .optimization_level(rhai::OptimizationLevel::Simple)
+            .extract_from(code)
+            .unwrap();
+        for offset in [one, two, print] {
+            assert!(!optimized.iter().any(|span| span.start() == offset));
+        }
+    }
+
+    #[test]
+    fn test_position_without_column() {
+        let code = "let a = 1;\n    let b = a + c;\n";
+        // A column of 0 means the position only knows its line.
+        let pos = Position::new(2, 0);
+        assert_eq!(pos.position(), None);
+
+        let span = Span::from_pos(code, &pos);
+        assert_eq!((span.line(), span.column()), (2, 1));
+        assert_eq!(span.start(), code.find("    let b").unwrap());
+        assert_eq!(span.end(), code.len() - 1);
+        assert_eq!(span.trim(code).start(), code.find("let b").unwrap());
+
+        // Errors at such positions still get a span on their line.
+        let err = rhai::EvalAltResult::ErrorRuntime("boom".into(), pos);
+        let better = BetterError::improve_eval_error(&err, code, &Engine::new(), None, None).unwrap();
+        assert_eq!(better.span.line(), 2);
+    }
+
+    #[test]
+    fn test_none_positions_do_not_panic() {
+        // A bare block, alone and between statements.
+        for code in ["{ let a = 1; }", "let b = 0;\n{ let a = 1; }\n{}"] {
+            let spans = SpanTracer::new().extract_from(code).unwrap();
+            let decl = code.find("let a").unwrap();
+            assert!(spans.iter().any(|span| span.kind() == SpanKind::Block));
+            assert!(spans.iter().any(|span| span.start() == decl));
+        }
+
+        let code = "let a = 1;\nlet b = 2;";
+        let start = Position::new(2, 5);
+
+        // An end without a position collapses onto the start.
+        let span = Span::from_rhai_start_end_pos(code, &start, &Position::NONE);
+        assert_eq!((span.start(), span.end()), (15, 15));
+        assert_eq!((span.end_line(), span.end_column()), (2, 5));
+
+        // Without any position there is nothing to point at.
+        assert_eq!(Span::from_rhai_start_end_pos(code, &Position::NONE, &start), Span::NONE);
+        assert_eq!(
+            Span::from_rhai_span(code, rhai::Span::NONE, &Position::NONE),
+            Span::NONE
+        );
+
+        // A reference position stands in for a missing start.
+        let span = Span::from_rhai_span(code, rhai::Span::NONE, &start);
+        assert_eq!((span.start(), span.line(), span.column()), (15, 2, 5));
+    }
+
+    #[test]
+    fn test_declaration_name_spans() {
+        let code = "let total = 0;\nconst MAX_ITEMS = 10;\nfn f() { let inner = MAX_ITEMS; inner }";
+        let nodes = SpanTracer::new().extract_nodes(code).unwrap();
+        let text = |node: &SpanNode| &code[node.span.start()..node.span.end()];
+
+        let names: Vec<(&str, SpanKind)> = nodes
+            .iter()
+            .filter(|node| matches!(node.kind, SpanKind::VariableName | SpanKind::ConstantName))
+            .map(|node| (text(node), node.kind))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("total", SpanKind::VariableName),
+                ("MAX_ITEMS", SpanKind::ConstantName),
+                ("inner", SpanKind::VariableName),
+            ]
+        );
+
+        // `const` declarations go through the same arm as `let`.
+        let decls = nodes.iter().filter(|node| node.kind == SpanKind::VariableDecl).count();
+        assert_eq!(decls, 3);
+
+        // Name spans cover a single line and stay apart from the initializer.
+        let name = nodes.iter().find(|node| node.kind == SpanKind::ConstantName).unwrap();
+        assert_eq!((name.span.line(), name.span.column()), (2, 7));
+        assert_eq!((name.span.end_line(), name.span.end_column()), (2, 16));
+        let init = nodes
+            .iter()
+            .find(|node| node.kind == SpanKind::Literal && text(node).starts_with("10"))
+            .unwrap();
+        assert!(init.span.start() >= name.span.end());
+
+        // In the tree, the name is a child of its declaration.
+        let tree = SpanTracer::new().extract_tree(code).unwrap();
+        let decl = &tree.children[0];
+        assert_eq!(decl.kind, SpanKind::VariableDecl);
+        assert_eq!(decl.children[0].kind, SpanKind::VariableName);
+    }
+
+    #[test]
+    fn test_assignment_part_spans() {
+        let code = "let a = #{ b: [1, 2] };\nfn f(x) { x }\na.b[0] *= f(3);";
+        let nodes = SpanTracer::new().extract_nodes(code).unwrap();
+        let part = |kind| {
+            let node = nodes.iter().find(|node| node.kind == kind).unwrap();
+            &code[node.span.start()..node.span.end()]
+        };
+
+        assert_eq!(part(SpanKind::AssignmentTarget), "a.b[0]");
+        assert_eq!(part(SpanKind::AssignmentOperator), "*=");
+        assert!(part(SpanKind::AssignmentValue).starts_with("f(3)"));
+        // The whole assignment starts at its target.
+        assert!(part(SpanKind::Assignment).starts_with("a.b[0] *= f(3)"));
+
+        // A plain assignment, with an operator on the right-hand side.
+        let code = "let x = 0;\nx = x + 1;";
+        let nodes = SpanTracer::new().extract_nodes(code).unwrap();
+        let part = |kind| {
+            let node = nodes.iter().find(|node| node.kind == kind).unwrap();
+            &code[node.span.start()..node.span.end()]
+        };
+        assert_eq!(part(SpanKind::AssignmentTarget), "x");
+        assert_eq!(part(SpanKind::AssignmentOperator), "=");
+        assert_eq!(part(SpanKind::AssignmentValue), "x + 1;");
+
+        // Errors on the target point at exactly the target.
+        let code = "let total = 1;\nLIMIT = total + 1;";
+        let engine = Engine::new();
+        let mut scope = rhai::Scope::new();
+        scope.push_constant("LIMIT", 1_i64);
+        let err = engine.run_with_scope(&mut scope, code).unwrap_err();
+        let better =
+            BetterError::improve_eval_error(&err, code, &engine, None, Some(&scope)).unwrap();
+        assert_eq!(&code[better.span.start()..better.span.end()], "LIMIT");
+        assert_eq!(better.span.line(), 2);
+    }
+
+    #[test]
+    fn test_chained_property_spans() {
+        let mut engine = Engine::new();
+        engine.set_fail_on_invalid_map_property(true);
+
+        let code = "let a = #{ b: #{ d: 1 } };\nlet v = a.b.c;";
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.code, Some(ErrorCode::PropertyNotFound));
+        assert_eq!(&code[better.span.start()..better.span.end()], "c");
+        assert_eq!(better.span.kind(), SpanKind::Property);
+
+        // Every operand of a chain of dots, indexing and method calls has a span.
+        let code = "let n = obj.field[idx].method(arg) ?? fallback;";
+        let spans = SpanTracer::new().extract_from(code).unwrap();
+        for needle in ["obj", "field", "idx", "method", "arg", "fallback"] {
+            let start = code.find(needle).unwrap();
+            assert!(spans.iter().any(|span| span.start() == start), "no span for {}", needle);
+        }
+        let field = spans.iter().find(|span| span.kind() == SpanKind::Property).unwrap();
+        assert_eq!(&code[field.start()..field.end()], "field");
+    }
+
+    #[test]
+    fn test_extract_functions() {
+        let code = "fn multiply(x, y) {\n    x * y\n}\nfn area(r) {\n    let sq = |v| v * v;\n    3 * sq.call(r)\n}\nfn area() { 0 }\nlet c = multiply(\"a\", 2);";
+        let functions = SpanTracer::new().extract_functions(code).unwrap();
+
+        let names: Vec<(&str, usize)> =
+            functions.iter().map(|f| (f.name.as_str(), f.arity)).collect();
+        assert_eq!(names, [("multiply", 2), ("area", 1), ("area", 0)]);
+
+        // Each function only holds the spans of its own body.
+        let multiply = &functions[0];
+        let body = code.find('{').unwrap()..code.find('}').unwrap();
+        assert!(!multiply.spans.is_empty());
+        assert!(multiply.spans.iter().all(|span| body.contains(&span.start())));
+        // The closure's spans belong to the function it is written in.
+        let closure = code.find("v * v").unwrap();
+        assert!(functions[1].spans.iter().any(|span| span.start() == closure));
+
+        // A runtime error in the body maps to the multiplication, not the call.
+        let engine = Engine::new();
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        let star = code.find('*').unwrap();
+        assert_eq!(better.span.start(), star);
+        assert!(multiply.spans.iter().any(|span| span.start() == star));
+    }
+
+    #[test]
+    fn test_import_path_spans() {
+        let engine = Engine::new();
+        for (code, path) in [
+            ("let a = 1;\nimport \"utils/math\" as math;", "\"utils/math\""),
+            ("let p = \"x\";\nimport `a/${p}` as m;", "`a/${p}`"),
+            ("let p = \"x\";\nimport p + \"y\" as m;", "p + \"y\""),
+            ("let p = \"x\";\nimport (p) as m;", "(p)"),
+        ] {
+            let spans = SpanTracer::new().extract_from(code).unwrap();
+            let span = spans.iter().find(|span| span.kind() == SpanKind::ImportPath).unwrap();
+            assert_eq!(&code[span.start()..span.end()], path);
+
+            // A missing module underlines exactly the path.
+            let err = engine.run(code).unwrap_err();
+            let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+            assert_eq!(&code[better.span.start()..better.span.end()], path);
+        }
+    }
+
+    #[test]
+    fn test_visit_counts_fn_calls() {
+        use std::ops::ControlFlow;
+
+        let script = "let a = max(1, 2);\nprint(a);\nlet b = a + abs(-3);\nfn f() { g() }";
+        let tracer = SpanTracer::new();
+
+        // Filtering in the visitor finds the same calls as `extract_nodes`.
+        let mut calls = Vec::new();
+        tracer
+            .visit(script, |node| {
+                if node.kind == SpanKind::FnCall {
+                    calls.push(node.span);
+                }
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        calls.sort();
+
+        let expected: Vec<Span> = tracer
+            .extract_nodes(script)
+            .unwrap()
+            .into_iter()
+            .filter(|node| node.kind == SpanKind::FnCall)
+            .map(|node| node.span)
+            .collect();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(calls, expected);
+    }
+
+    #[test]
+    fn test_call_name_and_argument_spans() {
+        let code = "cleanup();\nlet a = to_json(1);\nlet b = a.pad(3, \"x\");\nlet c = max(\n    a,\n    [1, 2]\n);";
+        let nodes = SpanTracer::new().extract_nodes(code).unwrap();
+        let texts = |kind: SpanKind| -> Vec<&str> {
+            nodes
+                .iter()
+                .filter(|node| node.kind == kind)
+                .map(|node| &code[node.span.start()..node.span.end()])
+                .collect()
+        };
+
+        // Zero-argument calls get a name span but no argument span.
+        assert_eq!(texts(SpanKind::FnName), ["cleanup", "to_json", "pad", "max"]);
+        assert_eq!(texts(SpanKind::Arguments), ["1", "3, \"x\"", "a,\n    [1, 2]"]);
+
+        // A missing function underlines exactly its name.
+        let engine = Engine::new();
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(&code[better.span.start()..better.span.end()], "cleanup");
+    }
+
+    #[test]
+    fn test_uncaught_throw_span() {
+        let code = "fn check(x) {\n    if x > 1 {\n        throw #{ code: 42, msg: \"bad\" };\n    }\n}\ncheck(2);";
+        let nodes = SpanTracer::new().extract_nodes(code).unwrap();
+        let throw = code.find("throw").unwrap();
+        let payload = code.find("#{").unwrap();
+        assert!(nodes.iter().any(|node| node.span.start() == throw && node.kind == SpanKind::Throw));
+        assert!(nodes.iter().any(|node| node.span.start() == payload && node.kind == SpanKind::Map));
+
+        // The uncaught throw maps to the throw site, not to the call or `Span::NONE`.
+        let engine = Engine::new();
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.code, Some(ErrorCode::RuntimeError));
+        assert_eq!(better.span.line(), 3);
+        assert!(code[better.span.start()..].starts_with("throw #{"));
+    }
+
+    #[test]
+    fn test_extract_tree_loop_body() {
+        let script = "let total = 0;\nfor i in 0..3 {\n    let sq = i * i;\n    total += sq;\n    print(total);\n}";
+        let tree = SpanTracer::new().extract_tree(script).unwrap();
+
+        let for_node = tree.children.iter().find(|node| node.kind == SpanKind::For).unwrap();
+        let body = ["let sq", "total +=", "print(total)"].map(|needle| script.find(needle).unwrap());
+
+        // The statements of the body are direct children of the loop, in order.
+        let starts: Vec<usize> = for_node
+            .children
+            .iter()
+            .map(|node| node.span.start())
+            .filter(|start| body.contains(start))
+            .collect();
+        assert_eq!(starts, body);
+    }
+
+    #[test]
+    fn test_try_catch_spans() {
+        let code = "try {\n    try {\n        throw \"inner\";\n    } catch (inner) {\n        throw inner;\n    }\n} catch (outer) {\n    print(outer);\n    throw outer;\n}";
+        let nodes = SpanTracer::new().extract_nodes(code).unwrap();
+        let texts = |kind: SpanKind| -> Vec<&str> {
+            nodes
+                .iter()
+                .filter(|node| node.kind == kind)
+                .map(|node| &code[node.span.start()..node.span.end()])
+                .collect()
+        };
+
+        assert_eq!(texts(SpanKind::CatchVariable), ["inner", "outer"]);
+        let try_blocks = texts(SpanKind::TryBlock);
+        assert_eq!(try_blocks.len(), 2);
+        // Like other blocks, these run from the `{` up to the closing `}`.
+        assert!(try_blocks.iter().all(|block| block.starts_with('{')));
+        assert!(try_blocks[0].contains("catch (inner)"));
+        let catch_blocks = texts(SpanKind::CatchBlock);
+        assert_eq!(catch_blocks, ["{\n        throw inner;\n    ", "{\n    print(outer);\n    throw outer;\n"]);
+
+        // A catch without a variable has no variable span.
+        let nodes = SpanTracer::new().extract_nodes("try { f() } catch { 0 }").unwrap();
+        assert!(!nodes.iter().any(|node| node.kind == SpanKind::CatchVariable));
+
+        // The error re-thrown by the outer catch block labels that block.
+        let engine = Engine::new();
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert!(code[better.span.start()..].starts_with("throw outer"));
+        assert_eq!(better.labels.len(), 1);
+        let (block, message) = &better.labels[0];
+        assert_eq!(&code[block.start()..block.end()], catch_blocks[1]);
+        assert_eq!(message, "the error was re-thrown from this catch block");
+    }
+
+    #[test]
+    fn test_span_len() {
+        let code = "let a = 1;";
+        let span = Span::from_byte_range(code, 4, 5);
+        assert_eq!(span.len(), 1);
+        assert!(!span.is_empty());
+
+        // A zero-width span, e.g. a caret between two characters.
+        let caret = Span::from_byte_range(code, 5, 5);
+        assert_eq!(caret.len(), 0);
+        assert!(caret.is_empty());
+        assert!(Span::NONE.is_empty());
+
+        // Reversed offsets are not negative.
+        assert_eq!(Span::new(5, 3, 1, 6).len(), 0);
+    }
+
+    #[test]
+    fn test_extract_partial() {
+        let tracer = SpanTracer::new();
+        let lines = |spans: &[Span]| {
+            let mut lines: Vec<usize> = spans.iter().map(|span| span.line()).collect();
+            lines.dedup();
+            lines
+        };
+
+        // A script that compiles gives the same spans as `extract_from`.
+        let code = "let a = 1;\nprint(a);";
+        let (spans, error) = tracer.extract_partial(code).unwrap();
+        assert!(error.is_none());
+        assert_eq!(spans, tracer.extract_from(code).unwrap());
+
+        // A parse error late in the file still yields spans for earlier statements,
+        // and for the statements after it.
+        let code = "let a = 1;\nlet b = a * 2;\nlet c = b +;\nprint(b);";
+        let (spans, error) = tracer.extract_partial(code).unwrap();
+        assert_eq!(error.unwrap().position().line(), Some(3));
+        assert_eq!(lines(&spans), [1, 2, 4]);
+        let full = tracer.extract_from("let a = 1;\nlet b = a * 2;").unwrap();
+        assert!(full.iter().all(|span| spans.contains(span)));
+
+        // An unclosed block cuts the script after the last complete statement.
+        let code = "let a = 1;\nif a > 0 {\n    print(a);\nlet b = 2;";
+        let (spans, error) = tracer.extract_partial(code).unwrap();
+        assert!(error.is_some());
+        assert_eq!(lines(&spans), [1]);
+    }
+
+    #[test]
+    fn test_span_cache() {
+        use std::sync::Arc;
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SpanCache>();
+
+        let cache = SpanCache::new(2);
+        let tracer = SpanTracer::new();
+        let (a, b, c) = ("let a = 1;", "let b = 2;", "let c = 3;");
+
+        let first = cache.extract_from(&tracer, a).unwrap();
+        assert_eq!(&*first, &tracer.extract_from(a).unwrap()[..]);
+        assert!(Arc::ptr_eq(&first, &cache.extract_from(&tracer, a).unwrap()));
+
+        // The optimization level is part of the key.
+        let optimized = SpanTracer::new().optimization_level(rhai::OptimizationLevel::Full);
+        assert!(!Arc::ptr_eq(&first, &cache.extract_from(&optimized, a).unwrap()));
+        assert_eq!(cache.len(), 2);
+
+        // So are the tab width and the limits of the tracer.
+        let keyed = SpanCache::new(8);
+        let tabbed = "if true {\n\tlet a = [[1]];\n}";
+        keyed.extract_from(&tracer, tabbed).unwrap();
+        let wide = SpanTracer::new().tab_width(4);
+        let spans = keyed.extract_from(&wide, tabbed).unwrap();
+        assert_eq!(&*spans, &wide.extract_from(tabbed).unwrap()[..]);
+        let shallow = SpanTracer::new().max_nesting_depth(3);
+        assert!(shallow.extract_from(tabbed).is_err());
+        assert!(keyed.extract_from(&shallow, tabbed).is_err());
+        let small = SpanTracer::new().max_source_bytes(8);
+        assert!(keyed.extract_from(&small, tabbed).is_err());
+        assert_eq!(keyed.len(), 2);
+
+        // The least recently used entry is evicted first.
+        cache.clear();
+        let first = cache.extract_from(&tracer, a).unwrap();
+        let second = cache.extract_from(&tracer, b).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(&first, &cache.extract_from(&tracer, a).unwrap()));
+        cache.extract_from(&tracer, c).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(&first, &cache.extract_from(&tracer, a).unwrap()));
+        assert!(!Arc::ptr_eq(&second, &cache.extract_from(&tracer, b).unwrap()));
+
+        // Errors are not cached, and a cache without room keeps nothing.
+        cache.clear();
+        assert!(cache.extract_from(&tracer, "let x = ;").is_err());
+        assert!(cache.is_empty());
+        let none = SpanCache::new(0);
+        none.extract_from(&tracer, a).unwrap();
+        assert!(none.is_empty());
+
+        // Improving errors through the cache gives the same result.
+        let engine = Engine::new();
+        let code = "let a = 1;\nlet b = a + c;";
+        let err = engine.run(code).unwrap_err();
+        let shared = Arc::new(SpanCache::new(8));
+        let cached = BetterError::improve_eval_error_cached(&err, code, &engine, &shared, None).unwrap();
+        let fresh = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(cached, fresh);
+        assert_eq!(shared.len(), 1);
+    }
+
+    #[test]
+    fn test_extract_identifiers() {
+        let code = "let x = 1;\nlet total = x;\nif total > 0 {\n    let x = x * 2;\n    total += x;\n}\nprint(x);\nfn scale(x, factor) { x * factor }\nlet f = |v| v + total;\nfor (item, i) in [1, 2] { total += item * i; }\ntry { throw 1; } catch (err) { print(err); }\nprint(missing);";
+        let table = SpanTracer::new().extract_identifiers(code).unwrap();
+        let lines = |symbol: &Symbol| -> Vec<usize> {
+            symbol.references.iter().map(|span| span.line()).collect()
+        };
+
+        // The shadowing `x` is a symbol of its own; its initializer reads the outer one.
+        let xs: Vec<&Symbol> = table.named("x").collect();
+        assert_eq!(xs.len(), 3);
+        assert_eq!(xs[0].declaration.unwrap().line(), 1);
+        assert_eq!(lines(xs[0]), [2, 4, 7]);
+        assert_eq!(xs[1].declaration.unwrap().line(), 4);
+        assert_eq!(lines(xs[1]), [5]);
+
+        // Parameters are declared for the body, which cannot see the script's variables.
+        assert_eq!(xs[2].declaration.unwrap().line(), 8);
+        assert_eq!(lines(xs[2]), [8]);
+        let factor = table.named("factor").next().unwrap();
+        assert_eq!(lines(factor), [8]);
+
+        // Closures see the variables around them.
+        let total = table.named("total").next().unwrap();
+        assert_eq!(lines(total), [3, 5, 9, 10]);
+        assert_eq!(lines(table.named("v").next().unwrap()), [9]);
+
+        // Loop and catch variables are declared for their block.
+        assert_eq!(lines(table.named("item").next().unwrap()), [10]);
+        assert_eq!(lines(table.named("i").next().unwrap()), [10]);
+        assert_eq!(lines(table.named("err").next().unwrap()), [11]);
+
+        // Undeclared variables have no declaration.
+        let missing = table.named("missing").next().unwrap();
+        assert_eq!(missing.declaration, None);
+        assert_eq!(lines(missing), [12]);
+
+        // Every span covers exactly the name.
+        for symbol in &table.symbols {
+            for span in symbol.declaration.iter().chain(&symbol.references) {
+                assert_eq!(&code[span.start()..span.end()], symbol.name);
+            }
+        }
+        let offset = code.find("total += x").unwrap() + "total += ".len();
+        assert_eq!(table.symbol_at(offset), Some(xs[1]));
+    }
+
+    #[test]
+    fn test_tab_display_column() {
+        let script = "fn greet(name) {\n\tlet message = name;\n\tmessage\n}";
+        let at = script.find("message").unwrap();
+
+        let tracer = SpanTracer::new().tab_width(4);
+        let spans = tracer.extract_from(script).unwrap();
+        let span = spans.iter().find(|span| span.start() == at).unwrap();
+        assert_eq!(span.column(), 6);
+        assert_eq!(span.display_column(), 9);
+
+        // The default tab width keeps both columns equal.
+        let spans = SpanTracer::new().extract_from(script).unwrap();
+        let narrow = spans.iter().find(|span| span.start() == at).unwrap();
+        assert_eq!(narrow.display_column(), narrow.column());
+
+        // Spans differing only in their display column are neither equal nor
+        // ordered as equal.
+        assert_ne!(narrow, span);
+        assert_eq!(narrow.cmp(span), std::cmp::Ordering::Less);
+
+        let index = LineIndex::new("a\tb").with_tab_width(4);
+        assert_eq!(index.display_column(1), 2);
+        assert_eq!(index.display_column(2), 5);
+    }
+
+    #[test]
+    fn test_better_error_builder() {
+        let code = "let total = price * count;";
+        let span = Span::from_range(code, 12..17);
+        let error = BetterError::builder()
+            .message("`price` is not set")
+            .span(span)
+            .help("Set `price` before running the script.")
+            .hint("Prices come from the host.")
+            .code(ErrorCode::VariableNotFound)
+            .with_label(Span::from_range(code, 20..25), "`count` is set")
+            .build();
+
+        assert_eq!(error.to_string(), "`price` is not set");
+        assert_eq!(error.span, span);
+        assert_eq!(error.note, None);
+        assert_eq!(error.labels.len(), 1);
+        assert_eq!(error.severity, Severity::Error);
+
+        // Post-process an improved error.
+        let engine = Engine::new();
+        let code = "let a = 1;\nb";
+        let err = engine.eval::<i64>(code).unwrap_err();
+        let improved = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        let original = improved.clone();
+        let tweaked = improved
+            .into_builder()
+            .help("Declare `b` first.")
+            .note("Scripts get no variables from the host.")
+            .build();
+
+        assert_eq!(tweaked.help.as_deref(), Some("Declare `b` first."));
+        assert!(tweaked.note.is_some());
+        assert_eq!(tweaked.span, original.span);
+        assert_eq!(tweaked.code, original.code);
+    }
+
+    #[test]
+    fn test_semantic_tokens() {
+        let script = "fn area(w, h) { w * h }\nlet size = area(2, 3);\nlet text = `size:\n${size}`;\nconst LIMIT = 10;\nif size > LIMIT { print(text); }";
+        let tokens = SpanTracer::new().semantic_tokens(script).unwrap();
+        let text = |token: &SemanticToken| &script[token.span.start()..token.span.end()];
+
+        // Sorted, without overlaps, and each token on a single line.
+        for pair in tokens.windows(2) {
+            assert!(pair[0].span.end() <= pair[1].span.start());
+        }
+        assert!(tokens.iter().all(|token| !text(token).contains('\n')));
+
+        let find = |name: &str, declaration: bool| {
+            tokens
+                .iter()
+                .find(|token| text(token) == name && token.modifiers.declaration == declaration)
+                .unwrap()
+        };
+        assert_eq!(find("area", true).token_type, TokenType::Function);
+        assert_eq!(find("area", false).token_type, TokenType::Function);
+        assert_eq!(find("w", true).token_type, TokenType::Parameter);
+        assert_eq!(find("w", false).token_type, TokenType::Parameter);
+        assert_eq!(find("size", true).token_type, TokenType::Variable);
+        assert_eq!(find("size", false).token_type, TokenType::Variable);
+        assert!(find("LIMIT", false).modifiers.readonly);
+        assert_eq!(find("print", false).token_type, TokenType::Function);
+        assert_eq!(find("if", false).token_type, TokenType::Keyword);
+        assert_eq!(find(">", false).token_type, TokenType::Operator);
+
+        // The multi-line string is split at the line break.
+        let strings: Vec<&str> = tokens
+            .iter()
+            .filter(|token| token.token_type == TokenType::String)
+            .map(text)
+            .collect();
+        assert_eq!(strings[0], "`size:");
+    }
+
+    #[test]
+    fn test_diagnostic_messages() {
+        let engine = Engine::new();
+        let messages = DiagnosticMessages::new()
+            .with_help(ErrorCode::VariableNotFound, "Unbekannte Variable '{name}'.")
+            .with_help(ErrorCode::DataTypeMismatch, "{found} statt {expected}, {name}.");
+
+        let code = "let a = 1;\nlet b = a + c;";
+        let err = engine.run(code).unwrap_err();
+        let better =
+            BetterError::improve_eval_error_with_messages(&err, code, &engine, None, None, &messages)
+                .unwrap();
+        assert_eq!(better.help.as_deref(), Some("Unbekannte Variable 'c'."));
+        // Without a hint template, the built-in hint is kept.
+        let default = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.hint, default.hint);
+        assert_eq!(better.span, default.span);
+
+        // Errors with another code keep their texts.
+        let code = "let a = [1];\na[5]";
+        let err = engine.eval::<Dynamic>(code).unwrap_err();
+        let better =
+            BetterError::improve_eval_error_with_messages(&err, code, &engine, None, None, &messages)
+                .unwrap();
+        let default = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.help, default.help);
+
+        let err = engine.eval::<i64>("\"text\"").unwrap_err();
+        let better =
+            BetterError::improve_eval_error_with_messages(&err, "\"text\"", &engine, None, None, &messages)
+                .unwrap();
+        assert_eq!(better.help.as_deref(), Some("string statt i64, {name}."));
+    }
+
+    #[test]
+    fn test_document_symbols() {
+        let script = "import \"utils\" as u;\nlet total = 0;\nprivate fn step(a, b) {\n    let sum = a + b;\n    if sum > 0 { const SIGN = 1; }\n    sum\n}\nif total == 0 { let hidden = 1; }\nconst MAX = 3;";
+        let outline = SpanTracer::new().document_symbols(script).unwrap();
+        let text = |span: Span| &script[span.start()..span.end()];
+
+        let names: Vec<&str> = outline.iter().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(names, ["u", "total", "step", "MAX"]);
+
+        assert_eq!(outline[0].kind, DocumentSymbolKind::Module);
+        assert_eq!(text(outline[0].full_span), "import \"utils\" as u;");
+        assert_eq!(outline[1].kind, DocumentSymbolKind::Variable);
+        assert_eq!(text(outline[1].full_span), "let total = 0;");
+
+        let step = &outline[2];
+        assert_eq!(step.kind, DocumentSymbolKind::Function);
+        assert_eq!(step.detail.as_deref(), Some("(a, b)"));
+        assert_eq!(text(step.name_span), "step");
+        assert!(text(step.full_span).starts_with("private fn step(a, b) {"));
+        assert!(text(step.full_span).ends_with('}'));
+
+        // Declarations anywhere in a function body are its children.
+        let children: Vec<(&str, DocumentSymbolKind)> = step
+            .children
+            .iter()
+            .map(|child| (child.name.as_str(), child.kind))
+            .collect();
+        assert_eq!(
+            children,
+            [
+                ("sum", DocumentSymbolKind::Variable),
+                ("SIGN", DocumentSymbolKind::Constant)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_folding_ranges() {
+        let script = "fn deep(x) {\n    if x > 0 {\n        while x > 1 {\n            loop {\n                break;\n            }\n            x -= 1;\n        }\n    }\n    switch x {\n        0 => #{\n            a: 1\n        },\n        _ => { 2 }\n    }\n}\nlet flat = [1, 2];\nif true { flat.len(); }";
+        let ranges = SpanTracer::new().folding_ranges(script).unwrap();
+        let lines: Vec<(usize, usize)> = ranges
+            .iter()
+            .map(|span| (span.line(), span.end_line()))
+            .collect();
+
+        assert_eq!(lines, [(1, 16), (2, 9), (3, 8), (4, 6), (10, 15), (11, 13)]);
+        for span in &ranges {
+            assert!(span.end_line() > span.line());
+            let text = &script[span.start()..span.end()];
+            assert!(text.starts_with('{') || text.starts_with("#{"));
+            assert!(text.ends_with('}'));
+        }
+    }
+
+    #[test]
+    fn test_improve_compile_error() {
+        let code = "let a = 1;\nlet b = (a + ;";
+        let parse_err = Engine::new().compile(code).unwrap_err();
+        let expected = BetterError::improve_parse_error(&parse_err, code).unwrap();
+
+        // Errors from the tracer, from `compile` and from `eval` all take the same path.
+        let from_tracer = SpanTracer::new().extract_from(code).unwrap_err();
+        assert_eq!(BetterError::improve_compile_error(&*from_tracer, code), Some(expected.clone()));
+
+        let boxed: Box<dyn std::error::Error> = Box::new(parse_err);
+        assert_eq!(BetterError::improve_compile_error(&*boxed, code), Some(expected.clone()));
+
+        let eval_err = Engine::new().eval::<Dynamic>(code).unwrap_err();
+        let better = BetterError::improve_compile_error(&*eval_err, code).unwrap();
+        assert_eq!(better.span, expected.span);
+        assert_eq!(better.code, Some(ErrorCode::SyntaxError));
+
+        // Errors raised while running are not compile errors.
+        let runtime_err = Engine::new().eval::<Dynamic>("let a = b;").unwrap_err();
+        assert_eq!(BetterError::improve_compile_error(&*runtime_err, "let a = b;"), None);
+        let too_large = SpanTracer::new().max_source_bytes(4).extract_from(code).unwrap_err();
+        assert_eq!(BetterError::improve_compile_error(&*too_large, code), None);
+    }
+
+    #[test]
+    fn test_unused_variable_lint() {
+        let code = r#"
+let a = 1;
+let a = 2;
+print(a);
+let total = 0;
+total = 5;
+for _n in 0..3 {}
+for i in 0..3 { print(i); }
+for (item, index) in [1, 2] { print(item); }
+let _ignored = 1;
+        "#;
+
+        let tracer = SpanTracer::new();
+        let lints: Vec<_> = tracer
+            .lint(code)
+            .unwrap()
+            .into_iter()
+            .filter(|lint| lint.name == "unused_variable")
+            .collect();
+
+        let flagged: Vec<_> = lints
+            .iter()
+            .map(|lint| (lint.span.line(), lint.span.snippet(code)))
+            .collect();
+        assert_eq!(
+            flagged,
+            vec![(2, Some("a")), (5, Some("total")), (9, Some("index"))],
+            "The shadowed `a`, the write-only `total` and the unused `index` should be flagged"
+        );
+        assert_eq!(lints[1].message, "Variable `total` is assigned to, but never read.");
+        assert_eq!(lints[2].help.as_deref(), Some("Rename it to `_index` if this is intended."));
+
+        let warning: BetterError = lints[0].clone().into();
+        assert_eq!(warning.code, Some(ErrorCode::UnusedVariable));
+        assert_eq!(warning.severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_eval_traced() {
+        let engine = Engine::new();
+        let mut scope = rhai::Scope::new();
+        let tracer = SpanTracer::new();
+
+        let value: i64 = tracer.eval(&engine, &mut scope, "let x = 20; x * 2 + 2").unwrap();
+        assert_eq!(value, 42);
+
+        let code = "let total = 0;\ntotal += missing;";
+        let err = tracer.eval::<()>(&engine, &mut scope, code).unwrap_err();
+        assert!(!err.message.is_empty(), "The error should carry a message");
+        assert_eq!(err.code, Some(ErrorCode::VariableNotFound));
+        assert_eq!(err.span.line(), 2);
+
+        let err = tracer.eval::<()>(&engine, &mut scope, "let = 1;").unwrap_err();
+        assert_eq!(err.code, Some(ErrorCode::SyntaxError));
+    }
+
+    #[test]
+    fn test_shadowed_variable_lint() {
+        let code = r#"
+let total = 0;
+let total = total + 1;
+if total > 0 { let total = 2; print(total); }
+if true { let inner = 1; print(inner); }
+let inner = 2;
+fn double(x) { let x = x * 2; x }
+print(double(inner));
+        "#;
+
+        let tracer = SpanTracer::new();
+        let lints: Vec<_> = tracer
+            .lint(code)
+            .unwrap()
+            .into_iter()
+            .filter(|lint| lint.name == "shadowed_variable")
+            .collect();
+
+        let flagged: Vec<_> = lints.iter().map(|lint| lint.span.line()).collect();
+        assert_eq!(flagged, vec![3, 4, 7], "Only real shadowing should be flagged");
+        assert_eq!(
+            lints[0].message,
+            "`total` is declared again, shadowing the earlier variable in the same scope."
+        );
+        assert_eq!(lints[1].message, "`total` shadows a variable of an enclosing scope.");
+        assert_eq!(
+            lints[2].message,
+            "`x` is declared again, shadowing the earlier parameter in the same scope."
+        );
+
+        let warning: BetterError = lints[1].clone().into();
+        assert_eq!(warning.code, Some(ErrorCode::ShadowedVariable));
+        assert_eq!(warning.labels.len(), 1);
+        assert_eq!(warning.labels[0].0.line(), 3);
+        assert_eq!(warning.labels[0].0.snippet(code), Some("total"));
+    }
+
+    #[test]
+    fn test_arithmetic_error_span() {
+        let engine = Engine::new();
+
+        let code = "let a = 1;\nlet z = a/0;";
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.span.snippet(code), Some("a/0"), "Not the whole `let`");
+        assert_eq!(better.span.line(), 2);
+
+        // Operands match the values in the message, and brackets are kept whole.
+        let code = "let a = 4;\nlet b = a / 2;\nlet c = (a + 1) / 0;";
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.span.snippet(code), Some("(a + 1) / 0"));
+
+        // Without an operator to tell them apart, no operation is guessed.
+        let code = "let a = 4;\nlet b = 0;\nlet c = a / b + a / b;";
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_ne!(better.span.snippet(code), Some("a / b"));
+    }
+
+    #[test]
+    fn test_unreachable_code_lint() {
+        let code = r#"
+fn sign(x) {
+    if x < 0 { return -1; } else { return 1; }
+    print("never");
+}
+fn first(items) {
+    for item in items {
+        if item > 0 { break; }
+        print(item);
+    }
+    return items[0];
+    let unused = 1;
+    print(unused);
+}
+let value = switch sign(2) { 1 => { throw "one"; }, _ => 0 };
+print(first([value]));
+return;
+fn hoisted() { 1 }
+        "#;
+
+        for tracer in [SpanTracer::new(), SpanTracer::with_engine(Engine::new())] {
+            let lints: Vec<_> = tracer
+                .lint(code)
+                .unwrap()
+                .into_iter()
+                .filter(|lint| lint.name == "unreachable_code")
+                .collect();
+
+            let flagged: Vec<_> = lints.iter().map(|lint| lint.span.snippet(code)).collect();
+            assert_eq!(
+                flagged,
+                vec![Some(r#"print("never")"#), Some("let unused = 1;\n    print(unused)")],
+                "Conditional breaks should not be flagged, even when optimized"
+            );
+            assert_eq!(lints[0].labels[0].0.line(), 3);
+            assert_eq!(lints[0].labels[0].1, "every branch of this `if` leaves the block");
+            assert_eq!(lints[1].labels[0].0.snippet(code), Some("return items[0];"));
+
+            let warning: BetterError = lints[1].clone().into();
+            assert_eq!(warning.code, Some(ErrorCode::UnreachableCode));
+        }
+    }
+
+    #[test]
+    fn test_recursion_cycle_hint() {
+        let engine = Engine::new();
+        let code = "fn render(x) {\n    layout(x + 1)\n}\nfn layout(x) {\n    render(x)\n}\nrender(0);";
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+
+        assert_eq!(
+            better.hint.as_deref(),
+            Some("Recursion cycle: render -> layout -> render. Make sure one of these calls stops at a base case.")
+        );
+        let labels: Vec<(usize, &str)> = better
+            .labels
+            .iter()
+            .map(|(span, label)| (span.line(), label.as_str()))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![(2, "`render` calls `layout` here"), (5, "`layout` calls `render` here")]
+        );
+    }
+
+    #[test]
+    fn test_index_out_of_bounds_span() {
+        let engine = Engine::new();
+        let code = "let a=[1,2]; a[5]";
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+
+        assert_eq!(&code[better.span.start()..better.span.end()], "5");
+        assert_eq!(better.span.kind(), SpanKind::Index);
+
+        // A computed index is underlined whole.
+        let code = "let s = \"ab\";\nlet i = 3;\nprint(s[i + 2]);";
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+
+        assert_eq!(&code[better.span.start()..better.span.end()], "i + 2");
+    }
+
+    #[test]
+    fn test_span_shift() {
+        let span = Span::new(10, 14, 2, 3).with_end_position(3, 1);
+        let shifted = span.shift(100, 5);
+
+        assert_eq!(
+            (shifted.start(), shifted.end(), shifted.line(), shifted.end_line()),
+            (110, 114, 7, 8)
+        );
+        assert_eq!((shifted.column(), shifted.end_column()), (3, 1));
+        assert_eq!(shifted.shift(-100, -5), span);
+
+        // Shifting past the start of the document stops at its first byte and line.
+        let clamped = span.shift(-20, -4);
+        assert_eq!(
+            (clamped.start(), clamped.end(), clamped.line(), clamped.end_line()),
+            (0, 0, 1, 1)
+        );
+        assert!(Span::NONE.shift(10, 1).is_none());
+
+        // Only the end crosses the start of the document here.
+        let partly = span.shift(-12, -2);
+        assert_eq!(
+            (partly.start(), partly.end(), partly.line(), partly.end_line()),
+            (0, 2, 1, 1)
+        );
+        assert_ne!(partly.shift(12, 2), span);
+
+        // Large deltas saturate instead of overflowing.
+        let far = span.shift(isize::MAX, isize::MAX).shift(isize::MAX, isize::MAX);
+        assert_eq!((far.start(), far.end(), far.line()), (usize::MAX, usize::MAX, usize::MAX));
+        assert_eq!(span.shift(isize::MIN, isize::MIN).start(), 0);
+
+        // `with_offset` is a shift that also moves the columns of the first line.
+        let moved = Span::new(4, 5, 1, 5).with_offset(12, 2, 6);
+        assert_eq!(moved, Span::new(4, 5, 1, 11).shift(12, 2));
+    }
+
+    #[test]
+    #[cfg(feature = "sync")]
+    fn test_tracer_shared_between_threads() {
+        use std::sync::Arc;
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SpanTracer>();
+        assert_send_sync::<Span>();
+        assert_send_sync::<BetterError>();
+
+        let tracer = Arc::new(SpanTracer::new());
+        let expected = tracer.extract_from("let a = 1;").unwrap();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let tracer = Arc::clone(&tracer);
+                std::thread::spawn(move || tracer.extract_from("let a = 1;").unwrap())
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_nesting_depth_limit() {
+        // Rhai rejects the script before the walk, as it nests deeper than the
+        // engine's own limits.
+        let code = format!("let a = {}1{};", "[".repeat(10_000), "]".repeat(10_000));
+        assert!(SpanTracer::new().extract_from(&code).is_err());
+
+        // Without those limits, the walk stops at its own.
+        let mut engine = Engine::new();
+        engine.set_max_expr_depths(0, 0);
+        let tracer = SpanTracer::with_engine(engine).max_nesting_depth(32);
+        let code = format!("let x = 1; let a = {}x{};", "[".repeat(50), "]".repeat(50));
+        let err = tracer.extract_from(&code).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<TraceError>(),
+            Some(&TraceError::NestingTooDeep { max: 32 })
+        );
+        assert!(tracer.extract_tree(&code).is_err());
+
+        let tracer = tracer.max_nesting_depth(error::DEFAULT_MAX_NESTING_DEPTH);
+        assert_eq!(tracer.extract_from(&code).unwrap().len(), 56);
+    }
+
+    #[test]
+    fn test_custom_error_mapper() {
+        use rhai::EvalAltResult;
+
+        let messages = DiagnosticMessages::new().with_mapper(|err: &EvalAltResult| match err {
+            EvalAltResult::ErrorRuntime(value, _) if value.to_string() == "quota exceeded" => {
+                Some(MessageTemplate {
+                    help: Some("The account is over its quota.".into()),
+                    hint: None,
+                })
+            }
+            // Errors with built-in texts never reach the mapper.
+            EvalAltResult::ErrorVariableNotFound(..) => unreachable!(),
+            _ => None,
+        });
+        let engine = Engine::new();
+        let improve = |code: &str| {
+            let err = engine.run(code).unwrap_err();
+            BetterError::improve_eval_error_with_messages(&err, code, &engine, None, None, &messages)
+                .unwrap()
+        };
+
+        let better = improve("fn check() {\n    throw \"quota exceeded\";\n}\ncheck();");
+        assert_eq!(better.help.as_deref(), Some("The account is over its quota."));
+        assert_eq!(
+            better.hint.as_deref(),
+            Some("Inspect the error message and script logic for issues.")
+        );
+
+        let better = improve("throw \"disk full\";");
+        assert_eq!(better.help.as_deref(), Some("disk full"));
+
+        let better = improve("let total = price * 2;");
+        assert_eq!(better.help.as_deref(), Some("Unknown variable 'price'."));
+    }
+
+    #[test]
+    fn test_thrown_value_details() {
+        let engine = Engine::new();
+        let code = "fn order(item) {\n    throw #{code: 42, msg: \"out of stock\"};\n}\norder(\"pen\");";
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+
+        let note = better.note.unwrap();
+        assert!(note.starts_with("This error occurred during a call to 'order'."));
+        assert!(note.ends_with("\nThrown map: code = 42, msg = \"out of stock\"."));
+
+        // A thrown string becomes the help.
+        let code = "throw \"pen is out of stock\";";
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.help.as_deref(), Some("pen is out of stock"));
+    }
+
+    #[test]
+    fn test_extract_report() {
+        let tracer = SpanTracer::new();
+        let code = "let a = [1, 2];\nfn f(x) {\n    if x > 0 { x } else { 0 }\n}";
+        let (spans, stats) = tracer.extract_report(code).unwrap();
+
+        assert_eq!(spans, tracer.extract_from(code).unwrap());
+        assert_eq!(stats.total, spans.len());
+        assert_eq!(stats.per_kind.values().sum::<usize>(), stats.total);
+        assert_eq!(stats.per_kind[&SpanKind::Array], 1);
+        assert_eq!(stats.per_kind[&SpanKind::FnDef], 1);
+        // `if` > condition `>` > its operand `x`.
+        assert_eq!(stats.max_depth, 3);
+
+        let dump = render::dump_annotated(code, &spans);
+        assert!(dump.starts_with("1 | let a = [1, 2];\n  | ^^^^^^^^^^^^^^^ VariableDecl 0..15\n"));
+        assert!(dump.contains("\n2 | fn f(x) {\n  | ^^^^^^^ FnDef 16..23\n"));
+    }
+
+    #[test]
+    fn test_token_tight_spans() {
+        let engine = Engine::new();
+        let code = "let x = 1;\nlet y = undefined_var + 1;";
+        let err = engine.run(code).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+
+        assert_eq!(better.span.snippet(code), Some("undefined_var"));
+        assert_eq!((better.span.line(), better.span.column()), (2, 9));
+        assert_eq!(better.span.kind(), SpanKind::Variable);
+
+        let code = "print(\"a b\" + 42.5);";
+        let token = |column| Span::from_pos_token(code, &Position::new(1, column));
+        assert_eq!(token(7).snippet(code), Some("\"a b\""));
+        assert_eq!(token(15).snippet(code), Some("42.5"));
+        assert_eq!(token(13).snippet(code), Some("+"));
+        // Inside a token, there is no token to stop at.
+        assert_eq!(token(9), Span::from_pos(code, &Position::new(1, 9)));
+        assert!(Span::from_pos_token(code, &Position::NONE).is_none());
+
+        let code = "x += s.len() + 1_000 + \"a\\\"b\"; y = \"open";
+        let token = |column| Span::from_pos_token(code, &Position::new(1, column));
+        assert_eq!(token(3).snippet(code), Some("+="));
+        assert_eq!(token(6).snippet(code), Some("s"));
+        assert_eq!(token(16).snippet(code), Some("1_000"));
+        assert_eq!(token(24).snippet(code), Some("\"a\\\"b\""));
+        assert_eq!(token(24).end_column(), 30);
+        // A string not closed on its line runs on like `from_pos`.
+        assert_eq!(token(36), Span::from_pos(code, &Position::new(1, 36)));
+    }
+
+    #[test]
+    fn test_bom_and_shebang() {
+        let tracer = SpanTracer::new();
+        let engine = Engine::new();
+        let code = "\u{FEFF}#!/usr/bin/env rhai\r\nlet x = 1;\r\nlet y = x + missing;\r\n";
+        let spans = tracer.extract_from(code).unwrap();
+
+        let x = spans.iter().find(|span| span.kind() == SpanKind::VariableName).unwrap();
+        assert_eq!(x.snippet(code), Some("x"));
+        assert_eq!((x.line(), x.column()), (2, 5));
+        assert!(spans.iter().all(|span| span.line() > 1));
+
+        // Rhai reads script files without the shebang, keeping its line ending.
+        let compiled = &code[code.find('\n').unwrap()..];
+        let err = engine.run(compiled).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.span.snippet(code), Some("missing"));
+        assert_eq!((better.span.line(), better.span.column()), (3, 13));
+
+        // On the first line, columns start after the byte order mark.
+        let code = "\u{FEFF}let y = missing;\r\n";
+        let err = engine.run(code.trim_start_matches('\u{FEFF}')).unwrap_err();
+        let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+        assert_eq!(better.span.snippet(code), Some("missing"));
+        assert_eq!((better.span.start(), better.span.column()), (11, 9));
+        assert_eq!(Span::from_range(code, 0..6).column(), 1);
+
+        let code = "\u{FEFF}#!/usr/bin/env rhai\r\nlet a = ;\r\n";
+        let errors = BetterError::improve_all_parse_errors(code, &engine).unwrap();
+        let lines: Vec<usize> = errors.iter().map(|error| error.span.line()).collect();
+        assert_eq!(lines, [2]);
+    }
 }
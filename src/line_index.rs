@@ -0,0 +1,235 @@
+use std::ops::Range;
+
+/// Precomputed line starts of a source text, for converting between byte
+/// offsets and 1-based line/column positions without rescanning the source.
+///
+/// Building the index takes one pass over the source. After that, finding a
+/// line is a lookup and only the characters of that line are ever counted.
+/// Columns are counted in characters, matching how Rhai reports positions.
+/// [`display_column`](LineIndex::display_column) counts them as rendered instead,
+/// with tabs expanded to the next tab stop.
+///
+/// # Example
+///
+/// ```rust
+/// use rhai_trace::LineIndex;
+///
+/// let code = "let a = 1;\nlet b = a + 2;";
+/// let index = LineIndex::new(code);
+///
+/// assert_eq!(index.offset(2, 5), 15);
+/// assert_eq!(index.position(15), (2, 5));
+/// ```
+#[derive(Debug, Clone)]
+pub struct LineIndex<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+    tab_width: usize,
+}
+
+impl<'a> LineIndex<'a> {
+    /// Indexes the lines of `source`. Both `\n` and `\r\n` line endings are supported.
+    ///
+    /// A leading UTF-8 byte order mark is not part of the first line, so columns on
+    /// it are counted as Rhai counts them once the mark is stripped for compiling.
+    pub fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(first_line_start(source))
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self {
+            source,
+            line_starts,
+            tab_width: 1,
+        }
+    }
+
+    /// Sets how many columns apart tab stops are, for
+    /// [`display_column`](LineIndex::display_column). Defaults to 1, so a tab is
+    /// one column like any other character. A width of 0 is treated as 1.
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = tab_width.max(1);
+        self
+    }
+
+    /// Returns the indexed source text.
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    /// Returns the number of lines, counting the empty line after a trailing newline.
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Returns the byte offset of the 1-based `line` and `column`.
+    ///
+    /// Columns past the end of the line are clamped to its end, before its line
+    /// terminator, so the offset never lands on a later line. Lines past the end of
+    /// the source start at its length. A `line` or `column` of 0 is treated as 1.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::LineIndex;
+    ///
+    /// let index = LineIndex::new("ab\ncd\n");
+    ///
+    /// assert_eq!(index.offset(1, 10), 2);
+    /// assert_eq!(index.offset(5, 1), 6);
+    /// ```
+    pub fn offset(&self, line: usize, column: usize) -> usize {
+        column_offset(self.source, self.line_range(line), column)
+    }
+
+    /// Returns the 1-based line and column of a byte `offset`.
+    /// Offsets past the end of the source are clamped to its length, and offsets
+    /// inside a leading byte order mark to the start of the first line.
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.clamp(self.line_starts[0], self.source.len());
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let line_start = self.line_starts[line - 1];
+        // Count characters by skipping UTF-8 continuation bytes.
+        let column = self.source.as_bytes()[line_start..offset]
+            .iter()
+            .filter(|&&b| (b & 0xC0) != 0x80)
+            .count()
+            + 1;
+
+        (line, column)
+    }
+
+    /// Returns the 1-based column of a byte `offset` as rendered by an editor or
+    /// terminal, with each tab advancing to the next tab stop of the
+    /// [tab width](LineIndex::with_tab_width). Other characters count one column each.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::LineIndex;
+    ///
+    /// let code = "\tlet a = 1;";
+    /// let index = LineIndex::new(code).with_tab_width(4);
+    ///
+    /// assert_eq!(index.position(1), (1, 2));
+    /// assert_eq!(index.display_column(1), 5);
+    /// ```
+    pub fn display_column(&self, offset: usize) -> usize {
+        let (line, column) = self.position(offset);
+        if self.tab_width == 1 {
+            return column;
+        }
+        let line_start = self.line_starts[line - 1];
+        let offset = offset.min(self.source.len());
+
+        self.source[line_start..]
+            .char_indices()
+            .take_while(|&(i, _)| line_start + i < offset)
+            .fold(1, |column, (_, c)| match c {
+                '\t' => (column - 1) / self.tab_width * self.tab_width + self.tab_width + 1,
+                _ => column + 1,
+            })
+    }
+
+    /// Returns the 0-based line and UTF-16 column of a byte `offset`, as used by
+    /// the Language Server Protocol. Characters outside the Basic Multilingual
+    /// Plane, such as most emoji, count as two columns.
+    ///
+    /// Offsets past the end of the source are clamped to its length, and offsets
+    /// inside a character are moved back to its start.
+    pub fn utf16_position(&self, offset: usize) -> (usize, usize) {
+        let (line, _) = self.position(offset);
+        let line_start = self.line_starts[line - 1];
+        let offset = offset.clamp(line_start, self.source.len());
+        let column = self.source[line_start..]
+            .char_indices()
+            .take_while(|&(i, c)| line_start + i + c.len_utf8() <= offset)
+            .map(|(_, c)| c.len_utf16())
+            .sum();
+
+        (line - 1, column)
+    }
+
+    /// Returns the byte range of the 1-based `line`, excluding its line terminator.
+    /// Lines past the end of the source are empty ranges at its length.
+    pub fn line_range(&self, line: usize) -> Range<usize> {
+        let line_idx = line.saturating_sub(1);
+        let start = self.line_start(line_idx);
+
+        start..line_end(self.source, start, self.line_start(line_idx + 1))
+    }
+
+    /// Returns the byte offset at which the given (0-based) line starts.
+    fn line_start(&self, line_idx: usize) -> usize {
+        self.line_starts
+            .get(line_idx)
+            .copied()
+            .unwrap_or(self.source.len())
+    }
+}
+
+/// Returns the byte offset at which the first line of `source` starts, after a
+/// leading byte order mark.
+fn first_line_start(source: &str) -> usize {
+    source.len() - source.strip_prefix('\u{FEFF}').unwrap_or(source).len()
+}
+
+/// Returns where the line from `start` to the start of the next line `next` ends,
+/// before its line terminator.
+fn line_end(source: &str, start: usize, next: usize) -> usize {
+    let text = &source[start..next];
+    let text = match text.strip_suffix('\n') {
+        Some(text) => text.strip_suffix('\r').unwrap_or(text),
+        None => text,
+    };
+
+    start + text.len()
+}
+
+/// Returns the byte offset of the 1-based `column` in the line at `range`,
+/// clamped to the end of the line.
+pub(crate) fn column_offset(source: &str, range: Range<usize>, column: usize) -> usize {
+    match source[range.clone()]
+        .char_indices()
+        .nth(column.saturating_sub(1))
+    {
+        Some((offset, _)) => range.start + offset,
+        None => range.end,
+    }
+}
+
+/// Like [`LineIndex::line_range`], but scans `source` up to the line instead of
+/// indexing every line, for a one-off lookup that must not allocate.
+pub(crate) fn scan_line_range(source: &str, line: usize) -> Range<usize> {
+    let start = match line.saturating_sub(1) {
+        0 => first_line_start(source),
+        line_idx => source
+            .match_indices('\n')
+            .nth(line_idx - 1)
+            .map_or(source.len(), |(i, _)| i + 1),
+    };
+    let next = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i + 1);
+
+    start..line_end(source, start, next)
+}
+
+/// Like [`LineIndex::offset`], without indexing `source`.
+pub(crate) fn scan_offset(source: &str, line: usize, column: usize) -> usize {
+    column_offset(source, scan_line_range(source, line), column)
+}
+
+/// Like [`LineIndex::position`], without indexing `source`.
+pub(crate) fn scan_position(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.clamp(first_line_start(source), source.len());
+    let before = &source[..offset];
+    let line_start = before
+        .rfind('\n')
+        .map_or(first_line_start(source), |i| i + 1);
+
+    (
+        before.matches('\n').count() + 1,
+        source[line_start..offset].chars().count() + 1,
+    )
+}
@@ -0,0 +1,383 @@
+use crate::error::{BetterError, Severity};
+use crate::error_code::ErrorCode;
+use crate::lexer::{
+    self, Lexeme, block_ranges, enclosing_block, is_close, is_open, lexeme_at, statement_end,
+};
+use crate::line_index::LineIndex;
+use crate::span::{Span, SpanKind, pos_to_byte};
+use crate::symbols::{self, Shadowing, Symbol, SymbolKind};
+use rhai::{AST, ASTFlags, ASTNode, Engine, Expr, FlowControl, Position, Stmt, StmtBlock, Token};
+
+/// A warning produced by statically analysing a script.
+///
+/// Lints point at code that is valid Rhai but most likely not what the
+/// author intended.
+#[derive(Debug, Clone)]
+pub struct Lint {
+    /// Stable identifier of the lint, e.g. `infinite_loop` or `unused_variable`.
+    pub name: &'static str,
+    pub message: String,
+    pub help: Option<String>,
+    pub span: Span,
+    /// Related locations with a message each, such as the declaration a
+    /// shadowing variable hides.
+    pub labels: Vec<(Span, String)>,
+}
+
+impl Lint {
+    /// Returns the [`ErrorCode`] of this lint, if it has one.
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self.name {
+            "infinite_loop" => Some(ErrorCode::InfiniteLoop),
+            "unused_variable" => Some(ErrorCode::UnusedVariable),
+            "shadowed_variable" => Some(ErrorCode::ShadowedVariable),
+            "unreachable_code" => Some(ErrorCode::UnreachableCode),
+            _ => None,
+        }
+    }
+}
+
+impl From<Lint> for BetterError {
+    fn from(lint: Lint) -> Self {
+        let code = lint.code();
+        BetterError {
+            message: lint.message,
+            help: lint.help,
+            hint: None,
+            note: None,
+            span: lint.span,
+            code,
+            labels: lint.labels,
+            severity: Severity::Warning,
+        }
+    }
+}
+
+/// Runs every lint over an already compiled `AST` of `script` and the `nodes`
+/// collected by a walk of it, returning the lints in source order.
+pub(crate) fn run(
+    ast: &AST,
+    script: &str,
+    engine: &Engine,
+    nodes: &[(Span, SpanKind)],
+) -> Vec<Lint> {
+    let index = LineIndex::new(script);
+    let mut lints = Vec::new();
+
+    let mut terminators = Vec::new();
+    ast.walk(&mut |nodes: &[ASTNode]| {
+        match nodes.last() {
+            Some(ASTNode::Stmt(Stmt::While(flow, pos))) => {
+                check_infinite_loop(flow, pos, &index, &mut lints);
+            }
+            Some(ASTNode::Stmt(stmt @ Stmt::If(_, pos))) if terminates(stmt) => {
+                terminators.push(pos_to_byte(&index, pos));
+            }
+            _ => {}
+        }
+        true
+    });
+
+    let lexemes = lexer::tokenize(engine, script);
+    check_unreachable_code(terminators, &lexemes, &index, &mut lints);
+    for symbol in &symbols::collect(script, engine, nodes).symbols {
+        check_unused_variable(symbol, &lexemes, &mut lints);
+    }
+    for shadowing in symbols::shadowing(script, engine, nodes) {
+        check_shadowing(&shadowing, script, &mut lints);
+    }
+
+    lints.sort_by_key(|lint| lint.span);
+    lints
+}
+
+/// Flags `while true { ... }` loops that contain no `break`, `return` or `throw`.
+fn check_infinite_loop(flow: &FlowControl, pos: &Position, index: &LineIndex, lints: &mut Vec<Lint>) {
+    let script = index.source();
+    let always_true = match flow.expr {
+        Expr::BoolConstant(true, _) => true,
+        // The optimizer turns `while true` into a `loop`, which has a unit guard.
+        Expr::Unit(_) => {
+            let header = Span::from_pos_in(index, pos);
+            header.snippet(script).is_some_and(|s| s.starts_with("while"))
+        }
+        _ => false,
+    };
+
+    if !always_true || flow.body.iter().any(exits_loop) {
+        return;
+    }
+
+    let header = Span::from_rhai_start_end_pos_in(index, pos, &flow.body.span().start());
+    let header_text = header.snippet(script).unwrap_or_default();
+    let end = header.start() + header_text.trim_end().len();
+
+    lints.push(Lint {
+        name: "infinite_loop",
+        message: "This `while` loop never terminates.".into(),
+        help: Some("Add a `break` inside the loop, or use `loop` if this is intended.".into()),
+        span: Span::from_range_in(index, header.start()..end),
+        labels: Vec::new(),
+    });
+}
+
+/// Flags `let`, loop and `catch` variables that are never read, including ones
+/// that are only ever assigned to. Names starting with `_` are never flagged.
+fn check_unused_variable(symbol: &Symbol, lexemes: &[Lexeme], lints: &mut Vec<Lint>) {
+    let Some(declaration) = symbol.declaration else {
+        return;
+    };
+    if symbol.kind != SymbolKind::Variable || symbol.name.starts_with('_') {
+        return;
+    }
+
+    // `x = ...` and `x += ...` write to `x` without reading what it holds.
+    let is_write = |reference: &Span| {
+        lexemes
+            .get(lexeme_at(lexemes, reference.end()))
+            .is_some_and(|next| next.token == Token::Equals || next.token.is_op_assignment())
+    };
+    if !symbol.references.iter().all(is_write) {
+        return;
+    }
+
+    let message = if symbol.references.is_empty() {
+        format!("Variable `{}` is never used.", symbol.name)
+    } else {
+        format!("Variable `{}` is assigned to, but never read.", symbol.name)
+    };
+    // Loop and `catch` variables cannot simply be removed.
+    let declared_by_let = lexeme_at(lexemes, declaration.start())
+        .checked_sub(1)
+        .is_some_and(|before| lexemes[before].token == Token::Let);
+    let help = if declared_by_let {
+        format!(
+            "Remove it, or rename it to `_{}` if this is intended.",
+            symbol.name
+        )
+    } else {
+        format!("Rename it to `_{}` if this is intended.", symbol.name)
+    };
+
+    lints.push(Lint {
+        name: "unused_variable",
+        message,
+        help: Some(help),
+        span: declaration,
+        labels: Vec::new(),
+    });
+}
+
+/// Flags a `let` re-declaring a name already bound in the same or an enclosing
+/// block, pointing at the earlier declaration.
+fn check_shadowing(shadowing: &Shadowing, script: &str, lints: &mut Vec<Lint>) {
+    let name = &script[shadowing.declaration.start()..shadowing.declaration.end()];
+    if name.starts_with('_') {
+        return;
+    }
+
+    let shadowed = match shadowing.shadowed_kind {
+        SymbolKind::Variable => "variable",
+        SymbolKind::Constant => "constant",
+        SymbolKind::Parameter => "parameter",
+    };
+    let message = if shadowing.same_scope {
+        format!(
+            "`{}` is declared again, shadowing the earlier {} in the same scope.",
+            name, shadowed
+        )
+    } else {
+        format!("`{}` shadows a {} of an enclosing scope.", name, shadowed)
+    };
+    let help = match (shadowing.same_scope, shadowing.shadowed_kind) {
+        (true, SymbolKind::Constant) => "Rename one of them to tell them apart.".to_string(),
+        (true, _) => format!(
+            "Remove `let` to assign to the existing `{}` instead, or rename one of them.",
+            name
+        ),
+        (false, _) => format!(
+            "Rename it if the outer `{}` is meant to be used or changed in this block.",
+            name
+        ),
+    };
+
+    lints.push(Lint {
+        name: "shadowed_variable",
+        message,
+        help: Some(help),
+        span: shadowing.declaration,
+        labels: vec![(
+            shadowing.shadowed,
+            format!("the earlier `{}` is declared here", name),
+        )],
+    });
+}
+
+/// Flags the statements following a `return`, `throw`, `break` or `continue`, or
+/// an `if` all of whose branches end in one, in the same block.
+///
+/// `terminators` are the byte offsets of the `if` statements. The other
+/// statements, and the statements after them, are found among the `lexemes`, as
+/// the optimizer removes unreachable statements from the `AST` and may turn a
+/// `return` that ends up last into a plain expression.
+fn check_unreachable_code(
+    mut terminators: Vec<usize>,
+    lexemes: &[Lexeme],
+    index: &LineIndex,
+    lints: &mut Vec<Lint>,
+) {
+    let script = index.source();
+    let blocks = block_ranges(lexemes);
+    terminators.extend(
+        lexemes
+            .iter()
+            .filter(|lexeme| {
+                matches!(
+                    lexeme.token,
+                    Token::Return | Token::Throw | Token::Break | Token::Continue
+                )
+            })
+            .map(|lexeme| lexeme.span.start()),
+    );
+    terminators.sort();
+    terminators.dedup();
+
+    let mut unreachable_end = 0;
+    for offset in terminators {
+        let at = lexeme_at(lexemes, offset);
+        // Skip terminators in code already flagged, and ones that are not
+        // statements of a block, e.g. in a `switch` arm or a closure.
+        let starts_statement = match at.checked_sub(1).map(|before| &lexemes[before].token) {
+            None => true,
+            Some(token) => matches!(
+                token,
+                Token::LeftBrace | Token::RightBrace | Token::SemiColon
+            ),
+        };
+        if offset < unreachable_end || !starts_statement || at >= lexemes.len() {
+            continue;
+        }
+
+        let keyword = lexemes[at].span;
+        let end = match lexemes[at].token {
+            Token::If => if_end(lexemes, at),
+            _ => Some(statement_end(lexemes, at, script.len())),
+        };
+        let Some(end) = end else {
+            continue;
+        };
+        let block_end =
+            enclosing_block(&blocks, offset).map_or(script.len(), |block| block.end - 1);
+
+        // Functions are defined before the script runs, wherever they are.
+        let mut rest = lexemes[lexeme_at(lexemes, end)..lexeme_at(lexemes, block_end)]
+            .iter()
+            .take_while(|lexeme| !matches!(lexeme.token, Token::Fn | Token::Private))
+            .filter(|lexeme| lexeme.token != Token::SemiColon);
+        let Some(first) = rest.next() else {
+            continue;
+        };
+        let last = rest.last().unwrap_or(first);
+        unreachable_end = last.span.end();
+
+        let keyword_text = &script[keyword.start()..keyword.end()];
+        let label = match lexemes[at].token {
+            Token::If => "every branch of this `if` leaves the block".to_string(),
+            _ => format!("any code following this `{}` is never run", keyword_text),
+        };
+        lints.push(Lint {
+            name: "unreachable_code",
+            message: "Unreachable code.".into(),
+            help: Some(format!(
+                "Remove it, or move it before the `{}` if it should run.",
+                keyword_text
+            )),
+            span: Span::from_range_in(index, first.span.start()..last.span.end()),
+            labels: vec![(
+                Span::from_range_in(index, keyword.start()..end).trim(script),
+                label,
+            )],
+        });
+    }
+}
+
+/// Returns whether `stmt` never lets the statements after it run: a `return`,
+/// `throw`, `break` or `continue`, or an `if` all of whose branches terminate.
+fn terminates(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Return(..) | Stmt::BreakLoop(..) => true,
+        Stmt::If(flow, _) => block_terminates(&flow.body) && block_terminates(&flow.branch),
+        Stmt::Block(block) => block_terminates(block),
+        _ => false,
+    }
+}
+
+fn block_terminates(block: &StmtBlock) -> bool {
+    block.statements().iter().any(terminates)
+}
+
+/// Returns where the `if` statement at the lexeme at `at` ends: after the closing
+/// brace of its last branch.
+fn if_end(lexemes: &[Lexeme], mut at: usize) -> Option<usize> {
+    loop {
+        // The body is the first block opened outside of the condition's brackets.
+        let mut depth = 0usize;
+        let open = at
+            + lexemes[at..].iter().position(|lexeme| match lexeme.token {
+                Token::LeftBrace if depth == 0 => true,
+                ref token if is_open(token) => {
+                    depth += 1;
+                    false
+                }
+                ref token if is_close(token) => {
+                    depth = depth.saturating_sub(1);
+                    false
+                }
+                _ => false,
+            })?;
+        let close = lexeme_at(
+            lexemes,
+            lexer::balance(
+                lexemes,
+                lexemes[open].span.start()..lexemes[open].span.end(),
+            )
+            .end,
+        ) - 1;
+
+        match lexemes.get(close + 1).map(|lexeme| &lexeme.token) {
+            Some(Token::Else) => at = close + 2,
+            _ => return Some(lexemes[close].span.end()),
+        }
+    }
+}
+
+/// Returns whether `stmt` contains a `break`, `return` or `throw` that leaves
+/// the enclosing loop. A `break` inside a nested loop only leaves that loop.
+fn exits_loop(stmt: &Stmt) -> bool {
+    let mut exits = false;
+
+    stmt.walk(&mut Vec::new(), &mut |path: &[ASTNode]| {
+        let Some((ASTNode::Stmt(current), ancestors)) = path.split_last() else {
+            return true;
+        };
+
+        let inside_nested_loop = ancestors.iter().any(|node| {
+            matches!(
+                node,
+                ASTNode::Stmt(Stmt::While(..) | Stmt::Do(..) | Stmt::For(..))
+            )
+        });
+
+        exits = match current {
+            Stmt::Return(..) => true,
+            Stmt::BreakLoop(_, flags, _) => {
+                flags.intersects(ASTFlags::BREAK) && !inside_nested_loop
+            }
+            _ => false,
+        };
+
+        !exits
+    });
+
+    exits
+}
@@ -0,0 +1,148 @@
+//! Integration with the [`lsp-types`](https://docs.rs/lsp-types) crate.
+//!
+//! Enabled with the `lsp` feature, which adds [`BetterError::to_lsp_diagnostic`]
+//! and [`BetterError::to_lsp_diagnostic_in`] for language servers.
+//!
+//! Spans count lines and columns from 1, while the Language Server Protocol counts
+//! them from 0, so both are shifted down by one. Columns are counted in characters;
+//! for scripts with characters outside the Basic Multilingual Plane, build the range
+//! from [`Span::to_utf16_range`](crate::Span::to_utf16_range) instead, which counts
+//! UTF-16 code units as the protocol requires.
+//!
+//! Spans created from a bare Rhai position, as most improved errors are, only know
+//! the line and column they start at, so their range is empty. Clients still mark
+//! the start of the offending code.
+//!
+//! # Example
+//!
+//! ```rust
+//! use lsp_types::{DiagnosticSeverity, NumberOrString, Position};
+//! use rhai_trace::{BetterError, Span};
+//!
+//! let code = "let x = 1;\nlet y = x + z;";
+//!
+//! let engine = rhai::Engine::new();
+//! let err = engine.eval::<rhai::Dynamic>(code).unwrap_err();
+//! let mut better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+//! assert_eq!((better.span.line(), better.span.column()), (2, 13));
+//!
+//! let diagnostic = better.to_lsp_diagnostic();
+//! // Unknown variables are underlined up to the end of their name.
+//! assert_eq!(diagnostic.range.start, Position::new(1, 12));
+//! assert_eq!(diagnostic.range.end, Position::new(1, 13));
+//! assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
+//! assert_eq!(diagnostic.code, Some(NumberOrString::String("RT0101".into())));
+//!
+//! // A span covering `x + z`, from line 2, column 9 up to column 14.
+//! better.span = Span::from_range(code, 19..24);
+//! assert_eq!((better.span.end_line(), better.span.end_column()), (2, 14));
+//!
+//! let diagnostic = better.to_lsp_diagnostic();
+//! assert_eq!(diagnostic.range.start, Position::new(1, 8));
+//! assert_eq!(diagnostic.range.end, Position::new(1, 13));
+//! ```
+
+use crate::error::{BetterError, Severity};
+use crate::span::Span;
+use lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Location, NumberOrString,
+    Position, Range, Uri,
+};
+use std::fmt::Write;
+
+impl BetterError {
+    /// Converts the error into a Language Server Protocol diagnostic.
+    ///
+    /// The help, hint and note are appended to the message on lines of their own.
+    /// An error without a location is reported at the start of the script.
+    /// Without the URI of the document, [`labels`](BetterError::labels) cannot be
+    /// related information, so they are appended to the message too, as
+    /// `line:column: message`; use
+    /// [`to_lsp_diagnostic_in`](BetterError::to_lsp_diagnostic_in) to relate them.
+    pub fn to_lsp_diagnostic(&self) -> Diagnostic {
+        self.lsp_diagnostic(None)
+    }
+
+    /// Converts the error into a Language Server Protocol diagnostic like
+    /// [`to_lsp_diagnostic`](BetterError::to_lsp_diagnostic), with each of its
+    /// [`labels`](BetterError::labels) as related information in the document at
+    /// `uri`, so that clients show them next to their own lines.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use lsp_types::{Position, Uri};
+    /// use rhai_trace::SpanTracer;
+    ///
+    /// let code = "let total = 1;\nif total > 0 {\n    let total = 2;\n    print(total);\n}";
+    /// let lint = SpanTracer::new().lint(code).unwrap().remove(0);
+    ///
+    /// let uri: Uri = "file:///totals.rhai".parse().unwrap();
+    /// let diagnostic = rhai_trace::BetterError::from(lint).to_lsp_diagnostic_in(&uri);
+    /// let related = &diagnostic.related_information.unwrap()[0];
+    ///
+    /// assert_eq!(related.location.uri, uri);
+    /// assert_eq!(related.location.range.start, Position::new(0, 4));
+    /// assert_eq!(related.message, "the earlier `total` is declared here");
+    /// ```
+    pub fn to_lsp_diagnostic_in(&self, uri: &Uri) -> Diagnostic {
+        self.lsp_diagnostic(Some(uri))
+    }
+
+    fn lsp_diagnostic(&self, uri: Option<&Uri>) -> Diagnostic {
+        let mut message = self.message.clone();
+        for (label, text) in [("help", &self.help), ("hint", &self.hint), ("note", &self.note)] {
+            if let Some(text) = text {
+                write!(message, "\n{}: {}", label, text).unwrap();
+            }
+        }
+
+        let labels = self.labels.iter().filter(|(span, _)| span.line() > 0);
+        let related_information = match uri {
+            Some(uri) => Some(
+                labels
+                    .map(|(span, text)| DiagnosticRelatedInformation {
+                        location: Location::new(uri.clone(), lsp_range(span)),
+                        message: text.clone(),
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .filter(|related| !related.is_empty()),
+            None => {
+                for (span, text) in labels {
+                    write!(message, "\n{}:{}: {}", span.line(), span.column(), text).unwrap();
+                }
+                None
+            }
+        };
+
+        Diagnostic {
+            range: lsp_range(&self.span),
+            severity: Some(match self.severity {
+                Severity::Error => DiagnosticSeverity::ERROR,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+                Severity::Note => DiagnosticSeverity::INFORMATION,
+            }),
+            code: self.code.map(|code| NumberOrString::String(code.as_str().into())),
+            source: Some("rhai_trace".into()),
+            message,
+            related_information,
+            ..Diagnostic::default()
+        }
+    }
+}
+
+/// Converts the 1-based lines and columns of `span` to a 0-based LSP range.
+fn lsp_range(span: &Span) -> Range {
+    let position = |line: usize, column: usize| {
+        Position::new(
+            line.saturating_sub(1) as u32,
+            column.saturating_sub(1) as u32,
+        )
+    };
+
+    Range::new(
+        position(span.line(), span.column()),
+        position(span.end_line(), span.end_column()),
+    )
+}
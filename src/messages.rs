@@ -0,0 +1,195 @@
+use crate::error_code::ErrorCode;
+use rhai::EvalAltResult;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Replacement help and hint texts for improved errors, per [`ErrorCode`], e.g.
+/// to translate diagnostics.
+///
+/// Pass them to [`BetterError::improve_eval_error_with_messages`]. Errors whose
+/// code has no template, or that have no code, keep the built-in English texts,
+/// as does the help or hint a template leaves unset.
+///
+/// Templates may contain these placeholders, filled in from the error:
+///
+/// - `{name}`: the name of the variable, function, property, module or
+///   constant, e.g. `count` for [`ErrorCode::VariableNotFound`]. Functions are
+///   named with their signature, e.g. `add (i64, i64)`.
+/// - `{expected}` and `{found}`: the type names of an
+///   [`ErrorCode::DataTypeMismatch`].
+///
+/// Placeholders without a value for the error are left as they are.
+///
+/// Errors `rhai_trace` has no specific texts for, such as the runtime errors a
+/// script raises with `throw` and new Rhai error variants, can instead be
+/// described by a [`CustomErrorMapper`] registered with
+/// [`with_mapper`](DiagnosticMessages::with_mapper).
+///
+/// # Example
+///
+/// ```rust
+/// use rhai::Engine;
+/// use rhai_trace::{BetterError, DiagnosticMessages, ErrorCode};
+///
+/// let messages = DiagnosticMessages::new()
+///     .with_help(ErrorCode::VariableNotFound, "Variable inconnue « {name} ».")
+///     .with_hint(ErrorCode::VariableNotFound, "Déclarez-la avec `let`.");
+///
+/// let engine = Engine::new();
+/// let code = "let total = prix * 2;";
+/// let err = engine.run(code).unwrap_err();
+/// let better =
+///     BetterError::improve_eval_error_with_messages(&err, code, &engine, None, None, &messages)
+///         .unwrap();
+///
+/// assert_eq!(better.help.as_deref(), Some("Variable inconnue « prix »."));
+/// assert_eq!(better.hint.as_deref(), Some("Déclarez-la avec `let`."));
+/// ```
+///
+/// [`BetterError::improve_eval_error_with_messages`]: crate::BetterError::improve_eval_error_with_messages
+#[derive(Clone, Default)]
+pub struct DiagnosticMessages {
+    templates: HashMap<ErrorCode, MessageTemplate>,
+    mapper: Option<Arc<dyn CustomErrorMapper>>,
+}
+
+/// The replacement texts of one [`ErrorCode`] in [`DiagnosticMessages`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageTemplate {
+    pub help: Option<String>,
+    pub hint: Option<String>,
+}
+
+impl DiagnosticMessages {
+    /// Creates an empty set of messages, keeping every built-in text.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the help of errors with `code` by `template`.
+    pub fn with_help(mut self, code: ErrorCode, template: impl Into<String>) -> Self {
+        self.templates.entry(code).or_default().help = Some(template.into());
+        self
+    }
+
+    /// Replaces the hint of errors with `code` by `template`.
+    pub fn with_hint(mut self, code: ErrorCode, template: impl Into<String>) -> Self {
+        self.templates.entry(code).or_default().hint = Some(template.into());
+        self
+    }
+
+    /// Returns the replacement texts of `code`, if any.
+    pub fn template(&self, code: ErrorCode) -> Option<&MessageTemplate> {
+        self.templates.get(&code)
+    }
+
+    /// Describes the errors without specific built-in texts with `mapper`,
+    /// replacing any mapper set before.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai::{Engine, EvalAltResult};
+    /// use rhai_trace::{BetterError, DiagnosticMessages, MessageTemplate};
+    ///
+    /// let messages = DiagnosticMessages::new().with_mapper(|err: &EvalAltResult| match err {
+    ///     EvalAltResult::ErrorRuntime(value, _) if value.to_string() == "out of stock" => {
+    ///         Some(MessageTemplate {
+    ///             help: Some("The item is out of stock.".into()),
+    ///             hint: Some("Check `stock(item)` before ordering.".into()),
+    ///         })
+    ///     }
+    ///     _ => None,
+    /// });
+    ///
+    /// let engine = Engine::new();
+    /// let code = r#"throw "out of stock";"#;
+    /// let err = engine.run(code).unwrap_err();
+    /// let better =
+    ///     BetterError::improve_eval_error_with_messages(&err, code, &engine, None, None, &messages)
+    ///         .unwrap();
+    ///
+    /// assert_eq!(better.help.as_deref(), Some("The item is out of stock."));
+    /// ```
+    pub fn with_mapper(mut self, mapper: impl CustomErrorMapper + 'static) -> Self {
+        self.mapper = Some(Arc::new(mapper));
+        self
+    }
+
+    /// Returns the texts the mapper gives `err`, if a mapper is set and knows it.
+    pub(crate) fn map(&self, err: &EvalAltResult) -> Option<MessageTemplate> {
+        self.mapper.as_ref()?.map(err)
+    }
+}
+
+impl fmt::Debug for DiagnosticMessages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiagnosticMessages")
+            .field("templates", &self.templates)
+            .field("mapper", &self.mapper.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+/// Messages are equal if they have the same templates and share the same mapper,
+/// or both have none.
+impl PartialEq for DiagnosticMessages {
+    fn eq(&self, other: &Self) -> bool {
+        let same_mapper = match (&self.mapper, &other.mapper) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        self.templates == other.templates && same_mapper
+    }
+}
+
+impl Eq for DiagnosticMessages {}
+
+/// Supplies the help and hint of errors that have no specific built-in texts:
+/// [`EvalAltResult::ErrorRuntime`], raised by `throw` or by functions of the
+/// host, and variants `rhai_trace` does not know.
+///
+/// Implemented for closures taking the error, which is the root cause of what
+/// the script raised, with Rhai's function call and module wrappers removed.
+pub trait CustomErrorMapper: Send + Sync {
+    /// Returns the texts for `err`, or `None` to keep the built-in ones. A
+    /// field left as `None` keeps its built-in text too.
+    fn map(&self, err: &EvalAltResult) -> Option<MessageTemplate>;
+}
+
+impl<F> CustomErrorMapper for F
+where
+    F: Fn(&EvalAltResult) -> Option<MessageTemplate> + Send + Sync,
+{
+    fn map(&self, err: &EvalAltResult) -> Option<MessageTemplate> {
+        self(err)
+    }
+}
+
+/// Fills the placeholders of `template` with the values of `err`.
+pub(crate) fn render(template: &str, err: &EvalAltResult) -> String {
+    let mut text = template.to_string();
+    for (placeholder, value) in placeholders(err) {
+        text = text.replace(placeholder, value);
+    }
+    text
+}
+
+/// Returns the placeholders of `err` with their values.
+fn placeholders(err: &EvalAltResult) -> Vec<(&'static str, &str)> {
+    match err {
+        EvalAltResult::ErrorVariableNotFound(name, ..)
+        | EvalAltResult::ErrorFunctionNotFound(name, ..)
+        | EvalAltResult::ErrorPropertyNotFound(name, ..)
+        | EvalAltResult::ErrorModuleNotFound(name, ..)
+        | EvalAltResult::ErrorAssignmentToConstant(name, ..)
+        | EvalAltResult::ErrorDataTooLarge(name, ..) => vec![("{name}", name)],
+        // Rhai wraps the requested type first, then the actual one.
+        EvalAltResult::ErrorMismatchDataType(expected, found, ..)
+        | EvalAltResult::ErrorMismatchOutputType(expected, found, ..) => {
+            vec![("{expected}", expected), ("{found}", found)]
+        }
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,84 @@
+//! Integration with the [`miette`](https://docs.rs/miette) crate.
+//!
+//! Enabled with the `miette` feature. [`BetterError`] implements
+//! [`miette::Diagnostic`]:
+//!
+//! - the `message` is the error itself,
+//! - the `help` is shown as help, followed by the `note`,
+//! - the `span` is labeled with the `hint`, followed by the secondary `labels`,
+//! - the [`code`](BetterError::code) is the diagnostic code, e.g. `RT0101`,
+//! - the [`severity`](BetterError::severity) maps [`Note`](Severity::Note) to advice.
+//!
+//! A `BetterError` does not hold the script, so attach it to the report with
+//! [`miette::Report::with_source_code`].
+//!
+//! # Example
+//!
+//! ```rust
+//! use miette::{NarratableReportHandler, Report};
+//! use rhai_trace::BetterError;
+//!
+//! let code = "let x = 1;\nlet y = x + z;";
+//!
+//! let engine = rhai::Engine::new();
+//! let err = engine.eval::<rhai::Dynamic>(code).unwrap_err();
+//! let better = BetterError::improve_eval_error(&err, code, &engine, None, None).unwrap();
+//!
+//! let report = Report::new(better).with_source_code(code);
+//! let mut output = String::new();
+//! NarratableReportHandler::new()
+//!     .render_report(&mut output, report.as_ref())
+//!     .unwrap();
+//!
+//! assert!(output.contains("Variable not found: z"));
+//! assert!(output.contains("RT0101"));
+//! assert!(output.contains("Unknown variable 'z'."));
+//! assert!(output.contains("let y = x + z;"));
+//! ```
+
+use crate::error::{BetterError, Severity};
+use miette::{Diagnostic, LabeledSpan};
+use std::fmt::Display;
+
+impl Diagnostic for BetterError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.code.map(|code| Box::new(code) as Box<dyn Display>)
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        Some(match self.severity {
+            Severity::Error => miette::Severity::Error,
+            Severity::Warning => miette::Severity::Warning,
+            Severity::Note => miette::Severity::Advice,
+        })
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let help = match (&self.help, &self.note) {
+            (Some(help), Some(note)) => format!("{}\nnote: {}", help, note),
+            (Some(help), None) => help.clone(),
+            (None, Some(note)) => format!("note: {}", note),
+            (None, None) => return None,
+        };
+        Some(Box::new(help))
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        if self.span.is_none() {
+            return None;
+        }
+
+        let primary = LabeledSpan::new_primary_with_span(
+            self.hint.clone(),
+            self.span.start()..self.span.end(),
+        );
+        let secondary = self
+            .labels
+            .iter()
+            .filter(|(span, _)| !span.is_none())
+            .map(|(span, message)| {
+                LabeledSpan::new_with_span(Some(message.clone()), span.start()..span.end())
+            });
+        Some(Box::new(std::iter::once(primary).chain(secondary)))
+    }
+}
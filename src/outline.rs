@@ -0,0 +1,120 @@
+use crate::lexer::{self, block_ranges, enclosing_block, lexeme_at, statement_end};
+use crate::line_index::LineIndex;
+use crate::span::{Span, SpanKind};
+use rhai::{Engine, Token};
+use std::ops::Range;
+
+/// An entry of the outline of a script, as found by
+/// [`SpanTracer::document_symbols`](crate::SpanTracer::document_symbols).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DocumentSymbol {
+    pub name: String,
+    pub kind: DocumentSymbolKind,
+    /// Extra information shown next to the name: the parameter list of a
+    /// function, e.g. `(x, y)`.
+    pub detail: Option<String>,
+    /// The name in the definition or declaration.
+    pub name_span: Span,
+    /// The whole definition or declaration, e.g. from `fn` through the closing
+    /// brace of the body, or from `let` through the `;`.
+    pub full_span: Span,
+    /// The declarations inside a function's body, in source order.
+    pub children: Vec<DocumentSymbol>,
+}
+
+/// What a [`DocumentSymbol`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DocumentSymbolKind {
+    /// A script function, e.g. `fn add(x, y) { ... }`.
+    Function,
+    /// A `let` declaration.
+    Variable,
+    /// A `const` declaration.
+    Constant,
+    /// An `import`, named after its alias, or after its path without one.
+    Module,
+}
+
+/// Builds the outline of `script` from the `nodes` collected by a walk of its `AST`.
+pub(crate) fn collect(
+    script: &str,
+    engine: &Engine,
+    tab_width: usize,
+    nodes: &[(Span, SpanKind)],
+) -> Vec<DocumentSymbol> {
+    let index = LineIndex::new(script).with_tab_width(tab_width);
+    let lexemes = lexer::tokenize(engine, script);
+    let blocks = block_ranges(&lexemes);
+    let span = |range: Range<usize>| Span::from_range_in(&index, range);
+
+    let mut functions: Vec<DocumentSymbol> = Vec::new();
+    let mut bodies: Vec<Range<usize>> = Vec::new();
+    for function in lexer::function_bodies(engine, script) {
+        let params: Vec<&str> = function
+            .params
+            .iter()
+            .map(|param| &script[param.start()..param.end()])
+            .collect();
+        functions.push(DocumentSymbol {
+            name: function.name,
+            kind: DocumentSymbolKind::Function,
+            detail: Some(format!("({})", params.join(", "))),
+            name_span: span(function.name_span.start()..function.name_span.end()),
+            full_span: span(function.start..function.body.end),
+            children: Vec::new(),
+        });
+        bodies.push(function.body);
+    }
+
+    let mut top_level: Vec<DocumentSymbol> = Vec::new();
+    for &(node, kind) in nodes {
+        let at = lexeme_at(&lexemes, node.start());
+        let (kind, keyword, name) = match kind {
+            SpanKind::VariableName => (DocumentSymbolKind::Variable, Token::Let, node),
+            SpanKind::ConstantName => (DocumentSymbolKind::Constant, Token::Const, node),
+            SpanKind::ImportPath => {
+                // Name the module after its alias, if it has one.
+                let after = lexeme_at(&lexemes, node.end());
+                let name = match (lexemes.get(after), lexemes.get(after + 1)) {
+                    (Some(alias), Some(name)) if alias.token == Token::As => name.span,
+                    _ => node,
+                };
+                (DocumentSymbolKind::Module, Token::Import, name)
+            }
+            _ => continue,
+        };
+        let Some(keyword) = lexemes[..at].iter().rposition(|l| l.token == keyword) else {
+            continue;
+        };
+
+        let start = lexemes[keyword].span.start();
+        let end = statement_end(&lexemes, at, script.len());
+        let symbol = DocumentSymbol {
+            name: script[name.start()..name.end()].to_string(),
+            kind,
+            detail: None,
+            name_span: span(name.start()..name.end()),
+            full_span: span(start..end).trim(script),
+            children: Vec::new(),
+        };
+
+        match bodies.iter().position(|body| body.contains(&start)) {
+            Some(i) => functions[i].children.push(symbol),
+            // Declarations nested in a block are not part of the outline.
+            None if enclosing_block(&blocks, start).is_none() => top_level.push(symbol),
+            None => {}
+        }
+    }
+
+    let mut symbols: Vec<DocumentSymbol> = functions.into_iter().chain(top_level).collect();
+    for symbol in &mut symbols {
+        symbol.children.sort_by_key(|child| child.full_span);
+        symbol.children.dedup_by_key(|child| child.name_span);
+    }
+    symbols.sort_by_key(|symbol| symbol.full_span);
+    symbols.dedup_by_key(|symbol| symbol.name_span);
+
+    symbols
+}
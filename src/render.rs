@@ -0,0 +1,275 @@
+//! Built-in plain-text renderers for [`BetterError`], and for spans when
+//! debugging the tracer itself.
+//!
+//! These need no extra dependencies and are what the `rhai-trace` binary prints.
+//! For richer output, plug the error into [`ariadne`](https://docs.rs/ariadne) instead.
+
+use crate::diagnostics::Diagnostics;
+use crate::error::{BetterError, Severity};
+use crate::line_index::LineIndex;
+use crate::span::{SourceNames, Span};
+use std::fmt::Write;
+
+/// Renders `error` for a terminal, quoting the offending line of `source`
+/// with a caret underneath, followed by the line of each of its labels with
+/// the label's message next to the caret:
+///
+/// ```text
+/// warning[RT0203]: `total` shadows an earlier declaration.
+///  --> script.rhai:3:9
+///   |
+/// 3 |     let total = 2;
+///   |         ^^^^^
+///   |
+/// 1 | let total = 1;
+///   |     ^^^^^ the earlier `total` is declared here
+///   = help: ...
+/// ```
+///
+/// `origin` names the source in the location line, e.g. its file path.
+pub fn render_text(error: &BetterError, origin: &str, source: &str) -> String {
+    let mut out = String::new();
+    let span = &error.span;
+
+    match error.code {
+        Some(code) => writeln!(out, "{}[{}]: {}", error.severity, code, error.message),
+        None => writeln!(out, "{}: {}", error.severity, error.message),
+    }
+    .unwrap();
+
+    // Spans without a location have line 0 and nothing to quote.
+    let labels: Vec<&(Span, String)> =
+        error.labels.iter().filter(|(label, _)| label.line() > 0).collect();
+    let last_line = labels.iter().map(|(label, _)| label.line()).fold(span.line(), usize::max);
+    let gutter = last_line.to_string().len();
+    let index = LineIndex::new(source);
+
+    if span.line() > 0 {
+        writeln!(out, "{:gutter$}--> {}:{}:{}", "", origin, span.line(), span.column()).unwrap();
+        writeln!(out, "{:gutter$} |", "").unwrap();
+        quote(&mut out, &index, span, "", gutter);
+    }
+    for (label, message) in labels {
+        writeln!(out, "{:gutter$} |", "").unwrap();
+        quote(&mut out, &index, label, message, gutter);
+    }
+
+    for (label, text) in [("help", &error.help), ("hint", &error.hint), ("note", &error.note)] {
+        if let Some(text) = text {
+            let indent = format!("\n{:gutter$}   {:width$}", "", "", width = label.len() + 2);
+            writeln!(out, "{:gutter$} = {}: {}", "", label, text.replace('\n', &indent)).unwrap();
+        }
+    }
+
+    out
+}
+
+/// Like [`render_text`], naming the source after the [`SourceId`](crate::SourceId)
+/// of the error's span in `names`, or `<unknown>` if it has none.
+pub fn render_text_named(error: &BetterError, names: &SourceNames, source: &str) -> String {
+    render_text(error, origin(error, names), source)
+}
+
+/// Returns the name of the source `error` points into, for the `_named` renderers.
+fn origin<'a>(error: &BetterError, names: &'a SourceNames) -> &'a str {
+    error.span.source_name(names).unwrap_or("<unknown>")
+}
+
+/// Quotes the first line of `span` with a caret underneath, followed by `message`.
+fn quote(out: &mut String, index: &LineIndex, span: &Span, message: &str, gutter: usize) {
+    let source = index.source();
+    let line = index.line_range(span.line());
+    let text = &source[line.clone()];
+
+    let before = span.start().clamp(line.start, line.end) - line.start;
+    let after = span.end().clamp(line.start, line.end) - line.start;
+    let padding: String = text
+        .get(..before)
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = text.get(before..after).map_or(0, |s| s.chars().count()).max(1);
+
+    writeln!(out, "{:>gutter$} | {}", span.line(), text).unwrap();
+    match message {
+        "" => writeln!(out, "{:gutter$} | {}{}", "", padding, "^".repeat(width)),
+        message => writeln!(out, "{:gutter$} | {}{} {}", "", padding, "^".repeat(width), message),
+    }
+    .unwrap();
+}
+
+/// How [`render_diagnostics`] shows diagnostics that
+/// [`Diagnostics::correlate`] linked to an earlier root cause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Cascade {
+    /// Show every diagnostic in the order it was found.
+    #[default]
+    Show,
+    /// Show each correlated diagnostic indented under its root cause.
+    Indent,
+    /// Hide correlated diagnostics, noting on their root cause how many were hidden.
+    Suppress,
+}
+
+/// Renders every diagnostic with [`render_text`], each followed by a blank line.
+///
+/// Diagnostics linked to a root cause are shown according to `cascade`; call
+/// [`Diagnostics::correlate`] first to find those links.
+pub fn render_diagnostics(diagnostics: &Diagnostics, origin: &str, cascade: Cascade) -> String {
+    let source = diagnostics.source();
+    let render = |error: &BetterError| render_text(error, origin, source) + "\n";
+
+    let mut out = String::new();
+    for (id, diagnostic) in diagnostics.iter() {
+        if cascade != Cascade::Show && diagnostic.caused_by.is_some() {
+            continue;
+        }
+
+        let children: Vec<_> = diagnostics.caused_by(id).collect();
+        match cascade {
+            Cascade::Suppress if !children.is_empty() => {
+                let mut error = diagnostic.error.clone();
+                let hidden = match children.len() {
+                    1 => "1 more diagnostic likely caused by this one was hidden.".to_string(),
+                    n => format!("{} more diagnostics likely caused by this one were hidden.", n),
+                };
+                error.note = Some(match error.note {
+                    Some(note) => format!("{}\n{}", note, hidden),
+                    None => hidden,
+                });
+                out.push_str(&render(&error));
+            }
+            _ => out.push_str(&render(&diagnostic.error)),
+        }
+
+        if cascade == Cascade::Indent {
+            for (_, child) in children {
+                for line in render(&child.error).lines() {
+                    match line {
+                        "" => out.push('\n'),
+                        line => writeln!(out, "    {}", line).unwrap(),
+                    }
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// Renders `error` as a GitHub Actions workflow command, which shows it as an
+/// annotation on the offending line of `origin` in pull requests.
+pub fn render_github(error: &BetterError, origin: &str) -> String {
+    let command = match error.severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "notice",
+    };
+
+    let mut properties = format!("file={}", escape_property(origin));
+    let span = &error.span;
+    if span.line() > 0 {
+        write!(
+            properties,
+            ",line={},col={},endLine={},endColumn={}",
+            span.line(),
+            span.column(),
+            span.end_line(),
+            span.end_column()
+        )
+        .unwrap();
+    }
+    if let Some(code) = error.code {
+        write!(properties, ",title={}", escape_property(code.as_str())).unwrap();
+    }
+
+    let mut message = error.message.clone();
+    for (label, text) in [("help", &error.help), ("hint", &error.hint), ("note", &error.note)] {
+        if let Some(text) = text {
+            write!(message, "\n{}: {}", label, text).unwrap();
+        }
+    }
+    for (label, text) in error.labels.iter().filter(|(label, _)| label.line() > 0) {
+        write!(message, "\n{}:{}: {}", label.line(), label.column(), text).unwrap();
+    }
+
+    format!("::{} {}::{}\n", command, properties, escape_data(&message))
+}
+
+/// Like [`render_github`], annotating the source named after the
+/// [`SourceId`](crate::SourceId) of the error's span in `names`, or `<unknown>`
+/// if it has none.
+pub fn render_github_named(error: &BetterError, names: &SourceNames) -> String {
+    render_github(error, origin(error, names))
+}
+
+/// Prints every line of `script` followed by a marker for each of `spans`
+/// starting on it, underlining the span up to the end of the line and naming
+/// its kind and byte range:
+///
+/// ```text
+/// 1 | let a = 1;
+///   | ^^^^^^^^^^ VariableDecl 0..10
+///   |     ^ VariableName 4..5
+///   |         ^ Literal 8..9
+/// ```
+///
+/// Spans are listed in the order given, e.g. that of
+/// [`SpanTracer::extract_from`](crate::SpanTracer::extract_from), so the output
+/// of two versions of the tracer can be compared as text.
+/// [`Span::NONE`] and spans outside `script` are left out.
+pub fn dump_annotated(script: &str, spans: &[Span]) -> String {
+    let index = LineIndex::new(script);
+    let lines = index.line_count();
+    let gutter = lines.to_string().len();
+    let mut out = String::new();
+
+    for line in 1..=lines {
+        let range = index.line_range(line);
+        let text = &script[range.clone()];
+        match text.is_empty() {
+            true => writeln!(out, "{:>gutter$} |", line),
+            false => writeln!(out, "{:>gutter$} | {}", line, text),
+        }
+        .unwrap();
+
+        for span in spans {
+            if span.is_none() || span.start() < range.start || span.start() > range.end {
+                continue;
+            }
+            // Keep tabs, so the markers line up however wide they are shown.
+            let padding: String = script
+                .get(range.start..span.start())
+                .unwrap_or_default()
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let width = script
+                .get(span.start()..span.end().clamp(span.start(), range.end))
+                .map_or(0, |text| text.chars().count())
+                .max(1);
+            writeln!(
+                out,
+                "{:>gutter$} | {}{} {:?} {}..{}",
+                "",
+                padding,
+                "^".repeat(width),
+                span.kind(),
+                span.start(),
+                span.end()
+            )
+            .unwrap();
+        }
+    }
+
+    out
+}
+
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
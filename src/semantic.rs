@@ -0,0 +1,208 @@
+use crate::lexer;
+use crate::line_index::LineIndex;
+use crate::span::{Span, SpanKind};
+use crate::symbols::{SymbolKind, SymbolTable};
+use rhai::{Engine, Token};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A classified token of a script, as found by
+/// [`SpanTracer::semantic_tokens`](crate::SpanTracer::semantic_tokens), for
+/// semantic highlighting in an editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SemanticToken {
+    /// The bytes of the token, always on a single line.
+    pub span: Span,
+    pub token_type: TokenType,
+    pub modifiers: TokenModifiers,
+}
+
+/// What a [`SemanticToken`] is, named after the standard token types of the
+/// Language Server Protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TokenType {
+    /// A keyword, e.g. `let`, `fn`, `true` or `this`, or a custom syntax keyword.
+    Keyword,
+    /// The name of a called or defined function.
+    Function,
+    /// A variable or constant.
+    Variable,
+    /// A parameter of a function or closure.
+    Parameter,
+    /// A property, e.g. `field` in `obj.field`.
+    Property,
+    /// A module name before `::`.
+    Namespace,
+    /// A string or character literal.
+    String,
+    /// A number literal.
+    Number,
+    /// An operator, e.g. `+`, `=` or `..`.
+    Operator,
+}
+
+impl TokenType {
+    /// Returns the name of this type in the Language Server Protocol, e.g. `"keyword"`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenType::Keyword => "keyword",
+            TokenType::Function => "function",
+            TokenType::Variable => "variable",
+            TokenType::Parameter => "parameter",
+            TokenType::Property => "property",
+            TokenType::Namespace => "namespace",
+            TokenType::String => "string",
+            TokenType::Number => "number",
+            TokenType::Operator => "operator",
+        }
+    }
+}
+
+/// Extra facts about a [`SemanticToken`], matching the standard token modifiers
+/// of the Language Server Protocol of the same names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TokenModifiers {
+    /// The token declares the name, e.g. `x` in `let x = 1;` or a function's
+    /// name in its definition, rather than referring to it.
+    pub declaration: bool,
+    /// The token is a constant.
+    pub readonly: bool,
+}
+
+/// Classifies the tokens of `script`, using the `nodes` collected by a walk of
+/// its `AST` and its `symbols` to tell identifiers apart.
+pub(crate) fn collect(
+    script: &str,
+    engine: &Engine,
+    tab_width: usize,
+    nodes: &[(Span, SpanKind)],
+    symbols: &SymbolTable,
+) -> Vec<SemanticToken> {
+    let index = LineIndex::new(script).with_tab_width(tab_width);
+    let lexemes = lexer::tokenize(engine, script);
+
+    // What the walk knows about the identifiers, by their start offset.
+    let mut identifiers: HashMap<usize, (TokenType, TokenModifiers)> = HashMap::new();
+    for &(span, kind) in nodes {
+        let token_type = match kind {
+            SpanKind::FnName => TokenType::Function,
+            SpanKind::Property => TokenType::Property,
+            _ => continue,
+        };
+        identifiers.insert(span.start(), (token_type, TokenModifiers::default()));
+    }
+    for symbol in &symbols.symbols {
+        let token_type = match symbol.kind {
+            SymbolKind::Parameter => TokenType::Parameter,
+            SymbolKind::Variable | SymbolKind::Constant => TokenType::Variable,
+        };
+        let readonly = symbol.kind == SymbolKind::Constant;
+        for (span, declaration) in symbol
+            .declaration
+            .iter()
+            .map(|span| (span, true))
+            .chain(symbol.references.iter().map(|span| (span, false)))
+        {
+            let modifiers = TokenModifiers {
+                declaration,
+                readonly,
+            };
+            identifiers.insert(span.start(), (token_type, modifiers));
+        }
+    }
+
+    let mut tokens = Vec::new();
+    let mut end = 0;
+    for (i, lexeme) in lexemes.iter().enumerate() {
+        let range = lexeme.span.start()..lexeme.span.end();
+        // Interpolated strings resume with tokens that may share bytes.
+        if range.start < end || range.is_empty() {
+            continue;
+        }
+
+        let next = lexemes.get(i + 1).map(|lexeme| &lexeme.token);
+        let previous = i.checked_sub(1).map(|prev| &lexemes[prev].token);
+        let classified = match &lexeme.token {
+            // Rhai reserves `this`, as well as functions such as `print` and `call`.
+            Token::Reserved(name) if **name == *"this" => {
+                Some((TokenType::Keyword, TokenModifiers::default()))
+            }
+            Token::Reserved(_) => identifiers.get(&range.start).copied(),
+            Token::Identifier(_) if next == Some(&Token::DoubleColon) => {
+                Some((TokenType::Namespace, TokenModifiers::default()))
+            }
+            Token::Identifier(_) if previous == Some(&Token::Fn) => Some((
+                TokenType::Function,
+                TokenModifiers {
+                    declaration: true,
+                    readonly: false,
+                },
+            )),
+            Token::Identifier(_) => identifiers.get(&range.start).copied(),
+            token => token_type(token).map(|token_type| (token_type, TokenModifiers::default())),
+        };
+        let Some((token_type, modifiers)) = classified else {
+            continue;
+        };
+
+        end = range.end;
+        tokens.extend(lines(&index, range).map(|span| SemanticToken {
+            span,
+            token_type,
+            modifiers,
+        }));
+    }
+
+    tokens
+}
+
+/// Returns the type of a token that is not an identifier, or `None` for
+/// punctuation such as brackets, `,` and `;`, which are left uncolored.
+fn token_type(token: &Token) -> Option<TokenType> {
+    match token {
+        Token::StringConstant(..) | Token::InterpolatedString(..) | Token::CharConstant(..) => {
+            Some(TokenType::String)
+        }
+        Token::IntegerConstant(..) | Token::FloatConstant(..) => Some(TokenType::Number),
+        Token::Custom(syntax) => Some(match syntax.chars().next() {
+            Some(c) if c.is_alphabetic() || c == '_' => TokenType::Keyword,
+            _ => TokenType::Operator,
+        }),
+        Token::LeftBrace
+        | Token::RightBrace
+        | Token::LeftParen
+        | Token::RightParen
+        | Token::LeftBracket
+        | Token::RightBracket
+        | Token::Unit
+        | Token::MapStart
+        | Token::SemiColon
+        | Token::Colon
+        | Token::Comma
+        | Token::Period
+        | Token::DoubleColon => None,
+        token if token.is_standard_keyword() => Some(TokenType::Keyword),
+        token if token.is_literal() => Some(match token.literal_syntax().chars().next() {
+            Some(c) if c.is_alphabetic() => TokenType::Keyword,
+            _ => TokenType::Operator,
+        }),
+        _ => None,
+    }
+}
+
+/// Splits the byte `range` into a span per line it covers, as most editors
+/// expect semantic tokens not to span lines.
+fn lines<'a>(index: &'a LineIndex, range: Range<usize>) -> impl Iterator<Item = Span> + 'a {
+    let (first, _) = index.position(range.start);
+    let (last, _) = index.position(range.end.saturating_sub(1).max(range.start));
+
+    (first..=last).filter_map(move |line| {
+        let bounds = index.line_range(line);
+        let start = range.start.max(bounds.start);
+        let end = range.end.min(bounds.end);
+        (start < end).then(|| Span::from_range_in(index, start..end))
+    })
+}
@@ -1,4 +1,10 @@
+use crate::error::TraceError;
+use crate::lexer;
+use crate::line_index::{self, LineIndex};
 use rhai::Position;
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Range;
 
 /// Represents a contiguous segment of source code.
 ///
@@ -17,22 +23,177 @@ use rhai::Position;
 /// let span = Span::new(10, 20, 2, 5);
 /// println!("Span covers bytes {}..{} on line {}", span.start(), span.end(), span.line());
 /// ```
-#[derive(Debug, Clone)]
+///
+/// # Ordering
+///
+/// Spans sort by `start` ascending, then by `end` **descending**, so an
+/// enclosing span always sorts before the spans nested inside it. Remaining
+/// ties are broken by line, column and display column, then by end line and end
+/// column, and finally by kind and source, so two spans compare as equal exactly
+/// when they are equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Span {
     start: usize,
     end: usize,
     line: usize,
     column: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    display_column: usize,
+    end_line: usize,
+    end_column: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    kind: SpanKind,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    source_id: Option<SourceId>,
 }
 
 impl Span {
+    /// A span that carries no source location, used wherever Rhai reports
+    /// `Position::NONE` or no better span is known.
+    ///
+    /// Check for it with [`is_none`](Span::is_none). Its byte range is empty and
+    /// says nothing about the source, so it should never be sliced or rendered.
+    pub const NONE: Span = Span {
+        start: 0,
+        end: 0,
+        line: 0,
+        column: 0,
+        display_column: 0,
+        end_line: 0,
+        end_column: 0,
+        kind: SpanKind::Other,
+        source_id: None,
+    };
+
     /// Creates a new `Span` from byte offsets, line, and column.
+    /// The end line and column are set to the start line and column,
+    /// and the kind to [`SpanKind::Other`].
     pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
         Span {
             start,
             end,
             line,
             column,
+            display_column: column,
+            end_line: line,
+            end_column: column,
+            kind: SpanKind::Other,
+            source_id: None,
+        }
+    }
+    /// Creates a new `Span` like [`Span::new`], but fails with
+    /// [`TraceError::InvalidSpan`] if `start` is past `end`.
+    pub fn try_new(
+        start: usize,
+        end: usize,
+        line: usize,
+        column: usize,
+    ) -> Result<Self, TraceError> {
+        if start > end {
+            return Err(TraceError::InvalidSpan { start, end });
+        }
+        Ok(Self::new(start, end, line, column))
+    }
+    /// Sets the line and column (1-based) at which this span ends.
+    pub fn with_end_position(mut self, end_line: usize, end_column: usize) -> Self {
+        self.end_line = end_line;
+        self.end_column = end_column;
+        self
+    }
+    /// Sets the script or module this span points into.
+    pub fn with_source_id(mut self, source_id: Option<SourceId>) -> Self {
+        self.source_id = source_id;
+        self
+    }
+    /// Sets the kind of AST node this span belongs to.
+    pub fn with_kind(mut self, kind: SpanKind) -> Self {
+        self.kind = kind;
+        self
+    }
+    /// Shifts this span from the coordinates of a script into those of a host
+    /// document the script is embedded in, e.g. a YAML or Markdown file.
+    ///
+    /// `byte_delta` and `line_delta` are added to the offsets and lines. When the
+    /// script starts mid-line, `first_line_column_delta` is the number of columns
+    /// before it on that line; it is only added to positions on the first line
+    /// of the script. The offsets and lines are then moved with
+    /// [`shift`](Span::shift), so they saturate rather than overflow.
+    /// [`Span::NONE`] is returned unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::Span;
+    ///
+    /// // The script `let a = 1;\nlet b = 2;` starts at byte 12, column 7 of line 3.
+    /// let first = Span::new(4, 5, 1, 5).with_offset(12, 2, 6);
+    /// let second = Span::new(15, 16, 2, 5).with_offset(12, 2, 6);
+    ///
+    /// assert_eq!((first.start(), first.line(), first.column()), (16, 3, 11));
+    /// assert_eq!((second.start(), second.line(), second.column()), (27, 4, 5));
+    /// ```
+    pub fn with_offset(
+        self,
+        byte_delta: usize,
+        line_delta: usize,
+        first_line_column_delta: usize,
+    ) -> Span {
+        if self.is_none() {
+            return self;
+        }
+
+        let column = |line: usize, column: usize| match line {
+            1 => column + first_line_column_delta,
+            _ => column,
+        };
+        let signed = |delta: usize| isize::try_from(delta).unwrap_or(isize::MAX);
+
+        Span {
+            column: column(self.line, self.column),
+            display_column: column(self.line, self.display_column),
+            end_column: column(self.end_line, self.end_column),
+            ..self
+        }
+        .shift(signed(byte_delta), signed(line_delta))
+    }
+    /// Returns this span moved by `byte_delta` bytes and `line_delta` lines,
+    /// keeping its columns, e.g. to translate the spans of a snippet compiled on
+    /// its own into the coordinates of the document it was taken from.
+    ///
+    /// Unlike [`with_offset`](Span::with_offset), the deltas may be negative, so
+    /// the same call with negated deltas translates the spans back. Offsets
+    /// saturate at 0 and `usize::MAX`, and lines at 1 and `usize::MAX`, each on its
+    /// own: a span shifted past the start of the document collapses onto byte 0 and
+    /// line 1, and negating the deltas of a shift that saturated does not restore
+    /// the original span. [`Span::NONE`] is returned unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::Span;
+    ///
+    /// // The snippet starts at byte 40, the start of line 4 of the document.
+    /// let span = Span::new(4, 5, 1, 5).shift(40, 3);
+    ///
+    /// assert_eq!((span.start(), span.end(), span.line(), span.column()), (44, 45, 4, 5));
+    /// assert_eq!(span.shift(-40, -3), Span::new(4, 5, 1, 5));
+    /// ```
+    pub fn shift(&self, byte_delta: isize, line_delta: isize) -> Span {
+        if self.is_none() {
+            return *self;
+        }
+
+        let line = |line: usize| line.saturating_add_signed(line_delta).max(1);
+        Span {
+            start: self.start.saturating_add_signed(byte_delta),
+            end: self.end.saturating_add_signed(byte_delta),
+            line: line(self.line),
+            end_line: line(self.end_line),
+            ..*self
         }
     }
     /// Returns the starting byte offset of this span.
@@ -47,84 +208,711 @@ impl Span {
     pub fn line(&self) -> usize {
         self.line
     }
-    /// Returns the column number (1-based) of this span.
+    /// Returns the column number (1-based) of this span, counting characters
+    /// like Rhai does.
     pub fn column(&self) -> usize {
         self.column
     }
+    /// Returns the column number (1-based) at which this span starts when
+    /// rendered, with tabs expanded to the [tab width](crate::SpanTracer::tab_width)
+    /// of the tracer that extracted it. Equal to [`column`](Span::column) unless
+    /// the line has tabs before the span and the tab width is more than 1.
+    pub fn display_column(&self) -> usize {
+        self.display_column.max(self.column)
+    }
+    /// Returns the line number (1-based) at which this span ends.
+    pub fn end_line(&self) -> usize {
+        self.end_line
+    }
+    /// Returns the column number (1-based) at which this span ends.
+    pub fn end_column(&self) -> usize {
+        self.end_column
+    }
+    /// Returns the script or module this span points into, if known.
+    pub fn source_id(&self) -> Option<SourceId> {
+        self.source_id
+    }
+    /// Returns the name of the script or module this span points into, if known,
+    /// looked up in the table that handed out its [`SourceId`].
+    pub fn source_name<'a>(&self, names: &'a SourceNames) -> Option<&'a str> {
+        names.name(self.source_id?)
+    }
+    /// Returns the kind of AST node this span was extracted from.
+    /// Spans not produced by [`SpanTracer`](crate::SpanTracer) are [`SpanKind::Other`].
+    pub fn kind(&self) -> SpanKind {
+        self.kind
+    }
+    /// Returns the length of this span in bytes.
+    pub fn len(&self) -> usize {
+        self.end.saturating_sub(self.start)
+    }
+    /// Returns whether this span covers no bytes, e.g. a caret position.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-    /// Creates a `Span` from a Rhai `Position` and the script text.
-    /// Computes byte offsets based on line and column.
-    pub fn from_pos(script: &str, pos: &Position) -> Self {
-        if pos.is_none() {
-            return Self {
-                start: 0,
-                end: 0,
-                line: 0,
-                column: 0,
+    /// Returns the smallest span covering both `self` and `other`.
+    ///
+    /// The line and column are taken from whichever span starts first, and the
+    /// end line and column from whichever ends last, so merging spans on different
+    /// lines gives a multi-line span covering everything between them. The kind is kept if both
+    /// spans share it and is [`SpanKind::Other`] otherwise. Merging with
+    /// [`Span::NONE`] returns the other span.
+    pub fn merge(&self, other: &Span) -> Span {
+        if self.is_none() {
+            return *other;
+        }
+        if other.is_none() {
+            return *self;
+        }
+
+        let first = if other.start < self.start { other } else { self };
+        let last = if other.end > self.end { other } else { self };
+
+        Span {
+            start: first.start,
+            end: last.end,
+            line: first.line,
+            column: first.column,
+            display_column: first.display_column,
+            end_line: last.end_line,
+            end_column: last.end_column,
+            kind: self.common_kind(other),
+            source_id: first.source_id,
+        }
+    }
+    /// Returns whether the byte `offset` falls within this span.
+    pub fn contains_offset(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+    /// Returns whether `other` lies entirely within this span.
+    pub fn contains(&self, other: &Span) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+    /// Returns whether this span and `other` share at least one byte.
+    ///
+    /// A zero-length span is treated as a point, overlapping any span that
+    /// contains its offset.
+    pub fn overlaps(&self, other: &Span) -> bool {
+        match (self.start == self.end, other.start == other.end) {
+            (true, true) => self.start == other.start,
+            (true, false) => other.contains_offset(self.start),
+            (false, true) => self.contains_offset(other.start),
+            (false, false) => self.start < other.end && other.start < self.end,
+        }
+    }
+    /// Returns the part of this span that also lies within `other`, e.g. to clip
+    /// a diagnostic against a visible viewport.
+    ///
+    /// The line and column come from whichever span starts later, and the end
+    /// line and column from whichever ends earlier, so no source text is needed.
+    /// The kind is combined as in [`merge`](Span::merge).
+    /// Returns `None` if the spans do not [overlap](Span::overlaps).
+    pub fn intersection(&self, other: &Span) -> Option<Span> {
+        if !self.overlaps(other) {
+            return None;
+        }
+
+        let first = if other.start > self.start { other } else { self };
+        let last = if other.end < self.end { other } else { self };
+
+        Some(Span {
+            start: first.start,
+            end: last.end.max(first.start),
+            line: first.line,
+            column: first.column,
+            display_column: first.display_column,
+            end_line: last.end_line,
+            end_column: last.end_column,
+            kind: self.common_kind(other),
+            source_id: first.source_id,
+        })
+    }
+    fn common_kind(&self, other: &Span) -> SpanKind {
+        if self.kind == other.kind {
+            self.kind
+        } else {
+            SpanKind::Other
+        }
+    }
+    /// Returns whether this is [`Span::NONE`], which carries no source location.
+    pub fn is_none(&self) -> bool {
+        *self == Span::NONE
+    }
+
+    /// Returns this span in a compact form that [`parse_compact`](Span::parse_compact)
+    /// reads back, e.g. `12..27@3:12..3:27` for bytes `12..27` running from
+    /// line 3, column 12 to line 3, column 27. The kind is not included.
+    pub fn to_compact_string(&self) -> String {
+        format!(
+            "{}..{}@{}:{}..{}:{}",
+            self.start, self.end, self.line, self.column, self.end_line, self.end_column
+        )
+    }
+
+    /// Parses a span written by [`to_compact_string`](Span::to_compact_string).
+    /// The kind of the parsed span is [`SpanKind::Other`].
+    ///
+    /// Fails with [`TraceError::MalformedSpan`] if `s` is not in that form, and with
+    /// [`TraceError::InvalidSpan`] if it starts after it ends.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::Span;
+    ///
+    /// let span = Span::new(12, 27, 3, 12).with_end_position(3, 27);
+    /// assert_eq!(span.to_compact_string(), "12..27@3:12..3:27");
+    /// assert_eq!(Span::parse_compact("12..27@3:12..3:27").unwrap(), span);
+    /// assert!(Span::parse_compact("12..27").is_err());
+    /// ```
+    pub fn parse_compact(s: &str) -> Result<Span, TraceError> {
+        let malformed = || TraceError::MalformedSpan { input: s.to_string() };
+        let pair = |text: &str, separator: &str| -> Result<(usize, usize), TraceError> {
+            let (a, b) = text.split_once(separator).ok_or_else(malformed)?;
+            Ok((
+                a.parse().map_err(|_| malformed())?,
+                b.parse().map_err(|_| malformed())?,
+            ))
+        };
+
+        let (range, position) = s.trim().split_once('@').ok_or_else(malformed)?;
+        let (from, to) = position.split_once("..").ok_or_else(malformed)?;
+        let (start, end) = pair(range, "..")?;
+        let (line, column) = pair(from, ":")?;
+        let (end_line, end_column) = pair(to, ":")?;
+
+        Ok(Span::try_new(start, end, line, column)?.with_end_position(end_line, end_column))
+    }
+
+    /// Returns the source text covered by this span.
+    ///
+    /// Returns `None` instead of panicking when the byte range is out of
+    /// bounds for `code` or does not fall on a `char` boundary.
+    pub fn snippet<'a>(&self, code: &'a str) -> Option<&'a str> {
+        code.get(self.start..self.end)
+    }
+
+    /// Converts this span into 0-based `(line, character)` start and end pairs
+    /// in UTF-16 code units, as used for ranges in the Language Server Protocol.
+    ///
+    /// See [`LineIndex::utf16_position`] for converting many spans of the same source.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::Span;
+    ///
+    /// let code = "let s = \"😀\"; x";
+    /// let span = Span::from_range(code, 15..16);
+    /// assert_eq!(span.to_utf16_range(code), ((0, 13), (0, 14)));
+    /// ```
+    pub fn to_utf16_range(&self, source: &str) -> ((u32, u32), (u32, u32)) {
+        let index = LineIndex::new(source);
+        let convert = |offset| {
+            let (line, column) = index.utf16_position(offset);
+            (line as u32, column as u32)
+        };
+
+        (convert(self.start), convert(self.end))
+    }
+
+    /// Returns this span shrunk to start at its first non-whitespace character in
+    /// `source` and end after its last, recomputing the line and column.
+    ///
+    /// Spans that are already tight, or that do not fit `source`, are returned
+    /// unchanged. A span of only whitespace collapses to an empty span at its start.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::Span;
+    ///
+    /// let code = "let a = 1;  \n";
+    /// let span = Span::from_range(code, 8..13).trim(code);
+    /// assert_eq!(span.snippet(code), Some("1;"));
+    /// ```
+    pub fn trim(&self, source: &str) -> Span {
+        let Some(text) = self.snippet(source) else {
+            return *self;
+        };
+        let leading = text.len() - text.trim_start().len();
+        let trailing = text.len() - text.trim_end().len();
+        if leading == 0 && trailing == 0 {
+            return *self;
+        }
+        if leading == text.len() {
+            return Span {
+                end: self.start,
+                end_line: self.line,
+                end_column: self.column,
+                ..*self
             };
         }
-        
-        let line_idx = pos.line().expect("Position missing line") - 1;
-        let column_idx = pos.position().expect("Position missing column") - 1;
-
-        let start = script
-            .lines()
-            .take(line_idx)
-            .map(|l| l.len() + 1)
-            .sum::<usize>()
-            + column_idx;
-
-        let line_content = script.lines().nth(line_idx).unwrap_or("");
-        let end = script
-            .lines()
-            .take(line_idx)
-            .map(|l| l.len() + 1)
-            .sum::<usize>()
-            + line_content.len();
 
-        Self {
+        // Walk forward from the known start position instead of indexing the
+        // whole source, as only this span's text is involved.
+        let advance = |(line, column): (usize, usize), skipped: &str| {
+            skipped.chars().fold((line, column), |(line, column), c| match c {
+                '\n' => (line + 1, 1),
+                _ => (line, column + 1),
+            })
+        };
+
+        let start = self.start + leading;
+        let end = self.end - trailing;
+        let (line, column) = advance((self.line, self.column), &text[..start - self.start]);
+        let (end_line, end_column) = advance((line, column), &source[start..end]);
+        // The tab width is not known here, so skipped tabs count one column.
+        let display_column = if line == self.line {
+            self.display_column() + (column - self.column)
+        } else {
+            column
+        };
+
+        Span {
             start,
             end,
-            line: pos.line().expect("Position missing line"),
-            column: pos.position().expect("Position missing column"),
+            line,
+            column,
+            display_column,
+            end_line,
+            end_column,
+            kind: self.kind,
+            source_id: self.source_id,
+        }
+    }
+
+    /// Returns this span widened to the start of its first line and the end of
+    /// its last line in `source`, e.g. to print the whole line with a caret
+    /// underneath. Line terminators are not included.
+    ///
+    /// Returns [`Span::NONE`] unchanged.
+    pub fn expand_to_line(&self, source: &str) -> Span {
+        self.context(source, 0, 0)
+    }
+
+    /// Like [`expand_to_line`](Span::expand_to_line), but also takes in up to
+    /// `lines_before` lines above and `lines_after` lines below the span,
+    /// stopping at the start and end of `source`.
+    pub fn context(&self, source: &str, lines_before: usize, lines_after: usize) -> Span {
+        if self.is_none() {
+            return Span::NONE;
+        }
+
+        let index = LineIndex::new(source);
+        let (first, _) = index.position(self.start);
+        // The end is exclusive, so a span ending right after a newline ends on
+        // the line before it.
+        let (last, _) = index.position(self.end.saturating_sub(1).max(self.start));
+
+        let first = first.saturating_sub(lines_before).max(1);
+        let last = last.saturating_add(lines_after).min(index.line_count());
+
+        Self::from_range_in(
+            &index,
+            index.line_range(first).start..index.line_range(last).end,
+        )
+        .with_source_id(self.source_id)
+    }
+
+    /// Creates a `Span` from a Rhai `Position` and the script text.
+    /// Computes byte offsets based on line and column.
+    /// Returns [`Span::NONE`] for `Position::NONE`. A position without a column
+    /// is taken to be at the start of its line.
+    pub fn from_pos(script: &str, pos: &Position) -> Self {
+        let Some(line) = pos.line() else {
+            return Span::NONE;
+        };
+        let column = pos.position().unwrap_or(1);
+
+        // A single position is looked up without indexing every line of the script.
+        let range = line_index::scan_line_range(script, line);
+        let start = line_index::column_offset(script, range.clone(), column);
+
+        Span {
+            display_column: script[range.start..start].chars().count() + 1,
+            ..Self::new(start, range.end, line, column)
+        }
+    }
+
+    /// Creates a `Span` covering only the token at a Rhai `Position`, e.g. just
+    /// `count` in `count + 1`, where [`from_pos`](Span::from_pos) would run to the
+    /// end of the line.
+    ///
+    /// The token is found by scanning forward from the position over the
+    /// characters of an identifier, number, string or operator, without
+    /// tokenizing the script. Falls back to [`from_pos`](Span::from_pos) if the
+    /// position is at whitespace or at a string that is not closed on its line.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai::Position;
+    /// use rhai_trace::Span;
+    ///
+    /// let script = "let total = count + 1;";
+    /// let span = Span::from_pos_token(script, &Position::new(1, 13));
+    ///
+    /// assert_eq!(span.snippet(script), Some("count"));
+    /// ```
+    pub fn from_pos_token(script: &str, pos: &Position) -> Self {
+        let line_span = Self::from_pos(script, pos);
+        let Some(len) = line_span.snippet(script).and_then(lexer::token_len) else {
+            return line_span;
+        };
+
+        // The token ends on the line it starts on, so only its characters need counting.
+        let token = &script[line_span.start..line_span.start + len];
+        Span {
+            end: line_span.start + len,
+            end_line: line_span.line,
+            end_column: line_span.column + token.chars().count(),
+            ..line_span
         }
     }
 
     /// Creates a `Span` from Rhai start and end `Position`s.
+    /// Returns [`Span::NONE`] if `start` is `Position::NONE`, and an empty span at
+    /// `start` if `end` is.
     pub fn from_rhai_start_end_pos(script: &str, start: &Position, end: &Position) -> Self {
-        let start_offset = pos_to_byte(script, start);
-        let end_offset = pos_to_byte(script, end);
+        Self::from_rhai_start_end_pos_in(&LineIndex::new(script), start, end)
+    }
+
+    /// Converts a Rhai `Span` to our `Span` type using a reference `Position`.
+    /// Returns [`Span::NONE`] if `pos` is `Position::NONE`. Without a position in
+    /// `rhai_span`, the span starts at `pos`.
+    pub fn from_rhai_span(script: &str, rhai_span: rhai::Span, pos: &Position) -> Self {
+        Self::from_rhai_span_in(&LineIndex::new(script), rhai_span, pos)
+    }
+
+    /// Creates a `Span` from a byte range into the script.
+    /// Computes the line and column of the range's start from the script text.
+    pub fn from_range(script: &str, range: Range<usize>) -> Self {
+        let (line, column) = line_index::scan_position(script, range.start);
+        let (end_line, end_column) = line_index::scan_position(script, range.end);
 
         Self {
-            start: start_offset,
-            end: end_offset,
-            line: start.line().expect("Position missing line"),
-            column: start.position().expect("Position missing column"),
+            start: range.start,
+            end: range.end,
+            line,
+            column,
+            display_column: column,
+            end_line,
+            end_column,
+            kind: SpanKind::Other,
+            source_id: None,
         }
     }
 
-    /// Converts a Rhai `Span` to our `Span` type using a reference `Position`.
-    pub fn from_rhai_span(script: &str, rhai_span: rhai::Span, pos: &Position) -> Self {
-        let start_byte = pos_to_byte(script, &rhai_span.start());
-        let end_byte = pos_to_byte(script, &rhai_span.end());
+    /// Like [`Span::from_pos`], reusing an already built [`LineIndex`].
+    pub(crate) fn from_pos_in(index: &LineIndex, pos: &Position) -> Self {
+        let Some(line) = pos.line() else {
+            return Span::NONE;
+        };
+        // Some positions only know their line; start those at its beginning.
+        let column = pos.position().unwrap_or(1);
+
+        let start = index.offset(line, column);
+        let end = index.line_range(line).end;
+
+        Span {
+            display_column: index.display_column(start),
+            ..Self::new(start, end, line, column)
+        }
+    }
+
+    /// Like [`Span::from_rhai_start_end_pos`], reusing an already built [`LineIndex`].
+    pub(crate) fn from_rhai_start_end_pos_in(
+        index: &LineIndex,
+        start: &Position,
+        end: &Position,
+    ) -> Self {
+        Self::from_rhai_positions(index, start, start, end)
+    }
+
+    /// Like [`Span::from_rhai_span`], reusing an already built [`LineIndex`].
+    pub(crate) fn from_rhai_span_in(index: &LineIndex, rhai_span: rhai::Span, pos: &Position) -> Self {
+        let start = match rhai_span.start() {
+            start if start.is_none() => *pos,
+            start => start,
+        };
+
+        Self::from_rhai_positions(index, pos, &start, &rhai_span.end())
+    }
+
+    /// Builds a span from `start` to `end`, reporting the line and column of `pos`.
+    ///
+    /// Returns [`Span::NONE`] if `pos` has no line. An `end` without a line
+    /// collapses the span onto its start rather than pointing at the top of the script.
+    fn from_rhai_positions(
+        index: &LineIndex,
+        pos: &Position,
+        start: &Position,
+        end: &Position,
+    ) -> Self {
+        let Some(line) = pos.line() else {
+            return Span::NONE;
+        };
+        let column = pos.position().unwrap_or(1);
+        let start = pos_to_byte(index, start);
+
+        let (end, end_line, end_column) = match end.line() {
+            Some(end_line) => (
+                pos_to_byte(index, end).max(start),
+                end_line,
+                end.position().unwrap_or(1),
+            ),
+            None => (start, line, column),
+        };
+
+        Self {
+            start,
+            end,
+            line,
+            column,
+            display_column: index.display_column(index.offset(line, column)),
+            end_line,
+            end_column,
+            kind: SpanKind::Other,
+            source_id: None,
+        }
+    }
+
+    /// Like [`Span::from_range`], reusing an already built [`LineIndex`].
+    pub(crate) fn from_range_in(index: &LineIndex, range: Range<usize>) -> Self {
+        let (line, column) = index.position(range.start);
+        let (end_line, end_column) = index.position(range.end);
 
         Self {
-            start: start_byte,
-            end: end_byte,
-            line: pos.line().expect("Position missing line"),
-            column: pos.position().expect("Position missing column"),
+            start: range.start,
+            end: range.end,
+            line,
+            column,
+            display_column: index.display_column(range.start),
+            end_line,
+            end_column,
+            kind: SpanKind::Other,
+            source_id: None,
+        }
+    }
+
+    /// Creates a `Span` from byte offsets into the script, e.g. as reported by a
+    /// text editor, computing the 1-based line and column of `start`.
+    ///
+    /// Offsets past the end of the script are clamped to its length, and `start`
+    /// is clamped to `end`.
+    pub fn from_byte_range(script: &str, start: usize, end: usize) -> Self {
+        let end = end.min(script.len());
+        let start = start.min(end);
+
+        Self::from_range(script, start..end)
+    }
+}
+
+/// Identifies the script or module a [`Span`] points into, e.g. by file name.
+///
+/// Ids are cheap to copy and compare. They are handed out by a [`SourceNames`]
+/// table owned by the caller, which maps them back to names; ids from different
+/// tables should not be mixed.
+///
+/// # Example
+///
+/// ```rust
+/// use rhai_trace::SourceNames;
+///
+/// let mut names = SourceNames::new();
+/// let id = names.intern("scripts/main.rhai");
+/// assert_eq!(id, names.intern("scripts/main.rhai"));
+/// assert_eq!(names.name(id), Some("scripts/main.rhai"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct SourceId(u32);
+
+/// The names of the scripts and modules of a project, each with its own [`SourceId`].
+///
+/// Pass the table to [`SpanTracer::extract_from_named`](crate::SpanTracer::extract_from_named)
+/// to tag spans with the id of a name, and to [`Span::source_name`] or the
+/// [`render`](crate::render) functions to turn the id back into the name.
+/// Ids are numbered in the order names were added.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourceNames {
+    names: Vec<String>,
+}
+
+impl SourceNames {
+    /// Creates an empty table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the id of the source called `name`, adding the name if it is new.
+    pub fn intern(&mut self, name: &str) -> SourceId {
+        match self.get(name) {
+            Some(id) => id,
+            None => {
+                self.names.push(name.to_string());
+                SourceId((self.names.len() - 1) as u32)
+            }
         }
     }
+
+    /// Returns the id of the source called `name`, if it was added.
+    pub fn get(&self, name: &str) -> Option<SourceId> {
+        let index = self.names.iter().position(|known| known == name)?;
+        Some(SourceId(index as u32))
+    }
+
+    /// Returns the name of `id`, or `None` if it was not handed out by this table.
+    pub fn name(&self, id: SourceId) -> Option<&str> {
+        self.names.get(id.0 as usize).map(String::as_str)
+    }
+}
+
+/// Describes which kind of AST node a span was extracted from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum SpanKind {
+    /// A literal value, e.g. `42`, `"text"` or `()`.
+    Literal,
+    /// A variable reference, including `this`.
+    Variable,
+    /// A property access, e.g. `field` in `obj.field`.
+    Property,
+    /// A `let` or `const` declaration.
+    VariableDecl,
+    /// The name declared by a `let` statement, e.g. `total` in `let total = 0;`.
+    VariableName,
+    /// The name declared by a `const` statement, e.g. `MAX` in `const MAX = 10;`.
+    ConstantName,
+    /// An assignment, including compound assignments such as `x += 1`.
+    Assignment,
+    /// The target of an assignment, e.g. `a.b[0]` in `a.b[0] *= 2`.
+    AssignmentTarget,
+    /// The operator of an assignment, e.g. `=` or `*=`.
+    AssignmentOperator,
+    /// The value assigned by an assignment, e.g. `2` in `a.b[0] *= 2`.
+    AssignmentValue,
+    /// A function call, including operators.
+    FnCall,
+    /// A method call, e.g. `obj.method()`.
+    MethodCall,
+    /// The name of the function in a function or method call, e.g. `print` in `print(x)`.
+    FnName,
+    /// The arguments of a function or method call, from the first argument to the last.
+    Arguments,
+    /// An array literal.
+    Array,
+    /// An object map literal.
+    Map,
+    /// A dot expression, e.g. `obj.field`.
+    Dot,
+    /// An indexing expression, e.g. `arr[0]`.
+    Index,
+    /// An operator, e.g. `a + b`, `&&`, or a custom operator registered with the engine.
+    Operator,
+    /// An `if` statement.
+    If,
+    /// A `while` or `loop` statement.
+    While,
+    /// A `do` ... `while`/`until` statement.
+    Do,
+    /// A `for` statement.
+    For,
+    /// A `try` ... `catch` statement.
+    TryCatch,
+    /// The block after `try`.
+    TryBlock,
+    /// The variable bound by `catch`, e.g. `err` in `catch (err)`.
+    CatchVariable,
+    /// The block after `catch`.
+    CatchBlock,
+    /// A `switch` expression.
+    Switch,
+    /// A statement block.
+    Block,
+    /// The signature of a function definition, e.g. `fn add(x, y)`.
+    FnDef,
+    /// A `return` statement.
+    Return,
+    /// A `throw` statement.
+    Throw,
+    /// A `break` statement.
+    Break,
+    /// A `continue` statement.
+    Continue,
+    /// An `import` statement.
+    Import,
+    /// The module path of an `import` statement, e.g. `"utils/math"`.
+    ImportPath,
+    /// A custom syntax expression.
+    Custom,
+    /// The whole script, only used for the root of
+    /// [`SpanTracer::extract_tree`](crate::SpanTracer::extract_tree).
+    Script,
+    /// Any other node.
+    #[default]
+    Other,
+}
+
+impl Ord for Span {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.start
+            .cmp(&other.start)
+            .then_with(|| other.end.cmp(&self.end))
+            .then_with(|| self.line.cmp(&other.line))
+            .then_with(|| self.column.cmp(&other.column))
+            .then_with(|| self.display_column.cmp(&other.display_column))
+            .then_with(|| self.end_line.cmp(&other.end_line))
+            .then_with(|| self.end_column.cmp(&other.end_column))
+            .then_with(|| self.kind.cmp(&other.kind))
+            .then_with(|| self.source_id.cmp(&other.source_id))
+    }
+}
+
+impl PartialOrd for Span {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Shows the span as `line:column..end_line:end_column`, e.g. `3:12..3:27`.
+///
+/// The alternate form `{:#}` spells the start out along with the byte range for
+/// logs, e.g. `line 3, col 12 (bytes 12..27)`.
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return write!(
+                f,
+                "line {}, col {} (bytes {}..{})",
+                self.line, self.column, self.start, self.end
+            );
+        }
+        write!(
+            f,
+            "{}:{}..{}:{}",
+            self.line, self.column, self.end_line, self.end_column
+        )
+    }
 }
 
-fn pos_to_byte(script: &str, pos: &Position) -> usize {
-    let line_idx = pos.line().unwrap_or(1).saturating_sub(1);
-    let col_idx = pos.position().unwrap_or(1).saturating_sub(1);
+impl From<Span> for Range<usize> {
+    fn from(span: Span) -> Self {
+        span.start..span.end
+    }
+}
+
+pub(crate) fn pos_to_byte(index: &LineIndex, pos: &Position) -> usize {
+    index.offset(pos.line().unwrap_or(1), pos.position().unwrap_or(1))
+}
 
-    script
-        .lines()
-        .take(line_idx)
-        .map(|l| l.len() + 1)
-        .sum::<usize>()
-        + col_idx
+/// Like [`pos_to_byte`], but scans `script` to the line of `pos` instead of
+/// indexing it, for a single position looked up without allocating.
+pub(crate) fn pos_offset(script: &str, pos: &Position) -> usize {
+    line_index::scan_offset(script, pos.line().unwrap_or(1), pos.position().unwrap_or(1))
 }
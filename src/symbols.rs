@@ -0,0 +1,405 @@
+use crate::error::identifier_at;
+use crate::lexer::{
+    self, FunctionBody, Lexeme, block_ranges, enclosing_block, is_close, is_open, lexeme_at,
+    statement_end,
+};
+use crate::line_index::LineIndex;
+use crate::span::{Span, SpanKind};
+use rhai::{Engine, Token};
+use std::ops::Range;
+
+/// A variable, constant or parameter of a script along with every place it is
+/// used, as found by [`SpanTracer::extract_identifiers`](crate::SpanTracer::extract_identifiers).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The name in the declaration, or `None` for a variable the script never
+    /// declares, e.g. one pushed into the scope by the host.
+    pub declaration: Option<Span>,
+    /// Every read of and assignment to the symbol, in source order.
+    pub references: Vec<Span>,
+}
+
+/// What declared a [`Symbol`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SymbolKind {
+    /// A `let` variable, a loop or `catch` variable, or a variable the script
+    /// never declares.
+    Variable,
+    /// A `const` declaration.
+    Constant,
+    /// A parameter of a function or closure.
+    Parameter,
+}
+
+/// The symbols of a script, resolved with Rhai's scoping rules.
+///
+/// Every declaration is a symbol of its own, so a `let` shadowing an earlier
+/// variable of the same name starts a new symbol. Function parameters are
+/// declared for the function's body, and functions do not see the variables of
+/// the script around them. Closures do, and their parameters are declared for
+/// the closure's body. Loop variables and `catch` variables are declared for the
+/// block they belong to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SymbolTable {
+    /// The symbols, in the order of their declaration, or of their first
+    /// reference for symbols without one.
+    pub symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    /// Returns every symbol called `name`, in source order.
+    pub fn named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Symbol> + 'a {
+        self.symbols
+            .iter()
+            .filter(move |symbol| symbol.name == name)
+    }
+
+    /// Returns the symbol declared or referenced at the byte `offset`, if any.
+    pub fn symbol_at(&self, offset: usize) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| {
+            symbol
+                .declaration
+                .iter()
+                .chain(&symbol.references)
+                .any(|span| span.contains_offset(offset))
+        })
+    }
+}
+
+/// A declared name along with the byte range its declaration is visible in.
+struct Declaration {
+    span: Span,
+    kind: SymbolKind,
+    visible: Range<usize>,
+    /// The index of the function whose body the declaration is in, if any.
+    function: Option<usize>,
+}
+
+/// Resolves the variables among the `nodes` of `script`, as collected by a walk
+/// of its `AST`, to their declarations.
+pub(crate) fn collect(script: &str, engine: &Engine, nodes: &[(Span, SpanKind)]) -> SymbolTable {
+    let index = LineIndex::new(script);
+    let lexemes = lexer::tokenize(engine, script);
+    let functions = lexer::function_bodies(engine, script);
+    let function_at = |offset: usize| functions.iter().position(|f| f.body.contains(&offset));
+    let declarations = declarations(script, &lexemes, &functions, nodes);
+
+    let mut symbols: Vec<Symbol> = declarations
+        .iter()
+        .map(|declaration| Symbol {
+            name: script[declaration.span.start()..declaration.span.end()].to_string(),
+            kind: declaration.kind,
+            declaration: Some(declaration.span),
+            references: Vec::new(),
+        })
+        .collect();
+    // Undeclared names, with the function they are used in.
+    let mut undeclared: Vec<(Option<usize>, Symbol)> = Vec::new();
+
+    let mut references: Vec<Span> = nodes
+        .iter()
+        .filter(|(_, kind)| *kind == SpanKind::Variable)
+        .filter_map(|(span, _)| {
+            let name = identifier_at(script, span)?;
+            let end = span.start() + name.len();
+            // `this` is no variable, and namespaced variables live in modules.
+            let namespaced = script[end..].starts_with("::");
+            (name != "this" && !namespaced).then(|| {
+                Span::from_range_in(&index, span.start()..end).with_kind(SpanKind::Variable)
+            })
+        })
+        .collect();
+    // Variables captured by a closure are also passed to it where they are used.
+    references.sort();
+    references.dedup();
+
+    for reference in references {
+        let name = &script[reference.start()..reference.end()];
+        let offset = reference.start();
+        let function = function_at(offset);
+        let declared = declarations
+            .iter()
+            .enumerate()
+            .filter(|(i, declaration)| {
+                symbols[*i].name == name
+                    && declaration.function == function
+                    && declaration.visible.contains(&offset)
+            })
+            .max_by_key(|(_, declaration)| declaration.visible.start)
+            .map(|(i, _)| i);
+
+        match declared {
+            Some(i) => symbols[i].references.push(reference),
+            None => match undeclared
+                .iter_mut()
+                .find(|(f, symbol)| *f == function && symbol.name == name)
+            {
+                Some((_, symbol)) => symbol.references.push(reference),
+                None => undeclared.push((
+                    function,
+                    Symbol {
+                        name: name.to_string(),
+                        kind: SymbolKind::Variable,
+                        declaration: None,
+                        references: vec![reference],
+                    },
+                )),
+            },
+        }
+    }
+
+    symbols.extend(undeclared.into_iter().map(|(_, symbol)| symbol));
+    symbols.sort_by_key(|symbol| symbol.declaration.or(symbol.references.first().copied()));
+
+    SymbolTable { symbols }
+}
+
+/// Finds every declaration among the `nodes` of `script`, along with the
+/// parameters of its `functions`.
+fn declarations(
+    script: &str,
+    lexemes: &[Lexeme],
+    functions: &[FunctionBody],
+    nodes: &[(Span, SpanKind)],
+) -> Vec<Declaration> {
+    let function_at = |offset: usize| functions.iter().position(|f| f.body.contains(&offset));
+    let blocks = block_ranges(lexemes);
+
+    let mut declarations: Vec<Declaration> = Vec::new();
+    let mut declare =
+        |span: Span, kind: SymbolKind, visible: Range<usize>, function: Option<usize>| {
+            if !declarations.iter().any(|d| d.span == span) {
+                declarations.push(Declaration {
+                    span,
+                    kind,
+                    visible,
+                    function,
+                });
+            }
+        };
+
+    for (i, function) in functions.iter().enumerate() {
+        for param in &function.params {
+            declare(
+                *param,
+                SymbolKind::Parameter,
+                function.body.clone(),
+                Some(i),
+            );
+        }
+    }
+
+    for &(span, kind) in nodes {
+        let at = lexeme_at(lexemes, span.start());
+        match kind {
+            // A variable is not visible in its own initializer.
+            SpanKind::VariableName | SpanKind::ConstantName => {
+                let block = enclosing_block(&blocks, span.start()).unwrap_or(0..script.len());
+                let visible = statement_end(lexemes, at, script.len())..block.end;
+                let kind = match kind {
+                    SpanKind::ConstantName => SymbolKind::Constant,
+                    _ => SymbolKind::Variable,
+                };
+                declare(span, kind, visible, function_at(span.start()));
+            }
+            SpanKind::CatchVariable => {
+                if let Some(block) = following_block(lexemes, at) {
+                    declare(span, SymbolKind::Variable, block, function_at(span.start()));
+                }
+            }
+            SpanKind::For if matches!(lexemes.get(at), Some(lexeme) if lexeme.token == Token::For) =>
+            {
+                let names: Vec<Span> = lexemes[at + 1..]
+                    .iter()
+                    .take_while(|lexeme| lexeme.token != Token::In)
+                    .filter(|lexeme| matches!(lexeme.token, Token::Identifier(_)))
+                    .map(|lexeme| lexeme.span)
+                    .collect();
+                if let Some(block) = following_block(lexemes, at) {
+                    for name in names {
+                        declare(
+                            name,
+                            SymbolKind::Variable,
+                            block.clone(),
+                            function_at(span.start()),
+                        );
+                    }
+                }
+            }
+            // Closures are literal function pointers starting at their `|`.
+            SpanKind::Literal => {
+                let Some((params, body)) = closure(lexemes, at, script.len()) else {
+                    continue;
+                };
+                for param in params {
+                    declare(
+                        param,
+                        SymbolKind::Parameter,
+                        body.clone(),
+                        function_at(span.start()),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    declarations
+}
+
+/// A `let` declaring a name that is already bound where it is declared, as
+/// found by [`shadowing`].
+pub(crate) struct Shadowing {
+    /// The name in the new declaration.
+    pub declaration: Span,
+    /// The name in the declaration it shadows.
+    pub shadowed: Span,
+    pub shadowed_kind: SymbolKind,
+    /// Whether both declarations are in the same block, rather than the
+    /// shadowed one belonging to an enclosing block.
+    pub same_scope: bool,
+}
+
+/// Finds every `let` among the `nodes` of `script` that shadows an earlier
+/// declaration of the same name, in source order.
+pub(crate) fn shadowing(
+    script: &str,
+    engine: &Engine,
+    nodes: &[(Span, SpanKind)],
+) -> Vec<Shadowing> {
+    let lexemes = lexer::tokenize(engine, script);
+    let functions = lexer::function_bodies(engine, script);
+    let blocks = block_ranges(&lexemes);
+    let declarations = declarations(script, &lexemes, &functions, nodes);
+    let name =
+        |declaration: &Declaration| &script[declaration.span.start()..declaration.span.end()];
+    let keyword = |declaration: &Declaration| {
+        lexeme_at(&lexemes, declaration.span.start())
+            .checked_sub(1)
+            .map(|before| &lexemes[before].token)
+    };
+
+    let mut shadowing: Vec<Shadowing> = declarations
+        .iter()
+        .filter(|declaration| keyword(declaration) == Some(&Token::Let))
+        .filter_map(|declaration| {
+            let offset = declaration.span.start();
+            let shadowed = declarations
+                .iter()
+                .filter(|earlier| {
+                    earlier.span.start() < offset
+                        && name(earlier) == name(declaration)
+                        && earlier.function == declaration.function
+                        && earlier.visible.contains(&offset)
+                })
+                .max_by_key(|earlier| earlier.visible.start)?;
+
+            // Variables and constants belong to the block around them, other
+            // names to the block they are declared for.
+            let scope = match keyword(shadowed) {
+                Some(Token::Let | Token::Const) => enclosing_block(&blocks, shadowed.span.start()),
+                _ => Some(shadowed.visible.clone()),
+            };
+            Some(Shadowing {
+                declaration: declaration.span,
+                shadowed: shadowed.span,
+                shadowed_kind: shadowed.kind,
+                same_scope: scope == enclosing_block(&blocks, offset),
+            })
+        })
+        .collect();
+
+    shadowing.sort_by_key(|shadowing| shadowing.declaration);
+    shadowing
+}
+
+/// Returns the byte range of the first `{ ... }` block after the lexeme at `at`
+/// that is not nested in brackets, braces included.
+fn following_block(lexemes: &[Lexeme], at: usize) -> Option<Range<usize>> {
+    let mut depth = 0usize;
+    let open = lexemes
+        .iter()
+        .enumerate()
+        .skip(at)
+        .find_map(|(i, lexeme)| {
+            match lexeme.token {
+                Token::LeftBrace if depth == 0 => return Some(i),
+                ref token if is_open(token) => depth += 1,
+                ref token if is_close(token) => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            None
+        })?;
+
+    let mut depth = 0usize;
+    for lexeme in &lexemes[open..] {
+        match lexeme.token {
+            ref token if is_open(token) => depth += 1,
+            ref token if is_close(token) => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(lexemes[open].span.start()..lexeme.span.end());
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Reads the closure starting at the lexeme at `at`, returning its parameters
+/// and the byte range of its body.
+fn closure(lexemes: &[Lexeme], at: usize, len: usize) -> Option<(Vec<Span>, Range<usize>)> {
+    let (params, body_at) = match lexemes.get(at)?.token {
+        Token::Or => (Vec::new(), at + 1),
+        Token::Pipe => {
+            let close = at
+                + 1
+                + lexemes[at + 1..]
+                    .iter()
+                    .position(|lexeme| lexeme.token == Token::Pipe)?;
+            let params = lexemes[at + 1..close]
+                .iter()
+                .filter(|lexeme| matches!(lexeme.token, Token::Identifier(_)))
+                .map(|lexeme| lexeme.span)
+                .collect();
+            (params, close + 1)
+        }
+        _ => return None,
+    };
+
+    let body = match lexemes.get(body_at) {
+        Some(lexeme) if lexeme.token == Token::LeftBrace => following_block(lexemes, body_at)?,
+        Some(lexeme) => {
+            // An expression body runs until the end of the argument or statement.
+            let mut depth = 0usize;
+            let end = lexemes[body_at..]
+                .iter()
+                .find(|lexeme| match lexeme.token {
+                    ref token if is_open(token) => {
+                        depth += 1;
+                        false
+                    }
+                    ref token if is_close(token) => match depth {
+                        0 => true,
+                        _ => {
+                            depth -= 1;
+                            false
+                        }
+                    },
+                    Token::Comma | Token::SemiColon => depth == 0,
+                    _ => false,
+                })
+                .map_or(len, |lexeme| lexeme.span.start());
+            lexeme.span.start()..end
+        }
+        None => len..len,
+    };
+
+    Some((params, body))
+}
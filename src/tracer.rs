@@ -1,6 +1,152 @@
-use crate::span::Span;
-use rhai::{BinaryExpr, Engine, Expr, FlowControl, FnCallExpr, Position, Stmt, StmtBlock};
+use crate::analysis::validate_full;
+use crate::cache::ExtractSettings;
+use crate::error::{
+    BetterError, DEFAULT_MAX_NESTING_DEPTH, DEFAULT_MAX_SOURCE_BYTES, ImproveOptions, TraceError,
+    blank_line, check_source_size,
+};
+use crate::lexer::{self, Lexeme, bracket_contents, lexeme_at};
+use crate::line_index::{self, LineIndex};
+use crate::lint::{self, Lint};
+use crate::messages::DiagnosticMessages;
+use crate::outline::{self, DocumentSymbol};
+use crate::span::{SourceNames, Span, SpanKind, pos_to_byte};
+use crate::semantic::{self, SemanticToken};
+use crate::symbols::{self, SymbolTable};
+use rhai::{
+    AST, ASTFlags, BinaryExpr, Engine, Expr, FlowControl, FnCallExpr, OptimizationLevel, ParseError,
+    Position, Scope, Stmt, StmtBlock, Token, Variant,
+};
+use std::borrow::Cow;
+use std::cmp::Reverse;
 use std::error::Error;
+use std::collections::HashMap;
+use std::ops::{ControlFlow, Range};
+use std::time::{Duration, Instant};
+
+/// A byte range of a script along with the [`SpanKind`] it should be highlighted as.
+pub type HighlightRange = (Range<usize>, SpanKind);
+
+/// The category of a top-level statement, as reported by [`SpanTracer::statement_ranges`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatementKind {
+    /// A `let`, `const`, `export` or `import` statement.
+    Declaration,
+    /// An expression statement, such as a function call or an assignment.
+    Expression,
+    /// A control-flow statement: `if`, `switch`, loops, `try`, `return`, `throw`,
+    /// `break` and `continue`.
+    ControlFlow,
+    /// A function definition.
+    FunctionDefinition,
+}
+
+/// Statistics about the spans of a script, as returned by
+/// [`SpanTracer::extract_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanStats {
+    /// The number of spans, after removing duplicates.
+    pub total: usize,
+    /// The number of spans of each kind.
+    pub per_kind: HashMap<SpanKind, usize>,
+    /// The number of nodes on the longest path from a top-level statement down
+    /// to the innermost node, e.g. 1 for a script of `1;`.
+    pub max_depth: usize,
+    /// How long compiling the script and extracting its spans took.
+    pub duration: Duration,
+}
+
+/// The source boundaries of one top-level statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementRange {
+    /// Covers the statement from the start of its first token through the end of its
+    /// last token, including a trailing semicolon when present. Comments around the
+    /// statement are never included.
+    pub span: Span,
+    /// The position of the statement among the script's top-level statements.
+    pub index: usize,
+    pub kind: StatementKind,
+}
+
+/// A call of a binary operator, as found by [`SpanTracer::binary_operations`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BinaryOperation {
+    /// The operator, e.g. `/`.
+    pub name: String,
+    /// The byte offset of the operator.
+    pub operator: usize,
+    /// The byte range of the whole operation, from the left operand through the
+    /// right one.
+    pub range: Range<usize>,
+    pub lhs: Range<usize>,
+    pub rhs: Range<usize>,
+}
+
+/// A span extracted from a script along with the kind of AST node it belongs to,
+/// as returned by [`SpanTracer::extract_nodes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpanNode {
+    pub span: Span,
+    pub kind: SpanKind,
+}
+
+/// A span extracted from a script along with the spans nested inside it,
+/// as returned by [`SpanTracer::extract_tree`].
+///
+/// A child's span can start before its parent's: an operator is positioned at the
+/// operator itself, after its left operand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpanTree {
+    pub span: Span,
+    /// The kind of AST node the span belongs to. [`SpanKind::Script`] for the
+    /// root, which covers the whole script.
+    pub kind: SpanKind,
+    /// The nodes nested inside this one, sorted like spans.
+    pub children: Vec<SpanTree>,
+}
+
+impl SpanTree {
+    /// Returns the innermost node whose span contains the byte `offset`, or
+    /// `None` if the offset lies outside every node.
+    ///
+    /// The narrowest span wins; among nodes sharing a span, the most deeply
+    /// nested one.
+    pub fn innermost_at(&self, offset: usize) -> Option<&SpanTree> {
+        let width = |node: &SpanTree| (node.span.len(), Reverse(node.span.start()));
+
+        self.iter()
+            .filter(|node| node.span.contains_offset(offset))
+            .fold(None, |best, node| match best {
+                Some(best) if width(best) < width(node) => Some(best),
+                _ => Some(node),
+            })
+    }
+
+    /// Iterates over this node and all nodes nested inside it, in pre-order:
+    /// each node comes before its children.
+    pub fn iter(&self) -> impl Iterator<Item = &SpanTree> {
+        let mut stack = vec![self];
+
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some(node)
+        })
+    }
+}
+
+/// The spans of one script function's body, as returned by
+/// [`SpanTracer::extract_functions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FunctionSpans {
+    pub name: String,
+    /// The number of parameters, telling apart functions sharing a name.
+    pub arity: usize,
+    /// The spans of the body, sorted like those of [`SpanTracer::extract_from`].
+    pub spans: Vec<Span>,
+}
 
 /// [`SpanTracer`] extracts spans from Rhai scripts, providing
 /// byte offsets, line, and column information for each statement or expression.
@@ -23,93 +169,1274 @@ use std::error::Error;
 ///              span.start(), span.end(), span.line(), span.column());
 /// }
 /// ```
-
+///
+/// # Source size
+///
+/// Scripts larger than [`max_source_bytes`](SpanTracer::max_source_bytes) are rejected
+/// with [`TraceError::SourceTooLarge`] before compiling. Byte offsets are `usize`, so on
+/// 32-bit targets scripts are additionally limited to what fits in the address space.
+///
+/// # Thread safety
+///
+/// Every method takes `&self` and the tracer keeps no state besides its engine and
+/// settings, so it can be shared whenever its `Engine` can. With the `sync` feature,
+/// which enables Rhai's own `sync` feature, the tracer is `Send + Sync` and one
+/// tracer in an `Arc` can serve many threads. Without it, Rhai engines are neither,
+/// so create a tracer per thread instead, e.g. in a `thread_local!`.
 pub struct SpanTracer {
     engine: Engine,
+    max_source_bytes: usize,
+    tab_width: usize,
+    max_nesting_depth: usize,
+}
+
+impl Default for SpanTracer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SpanTracer {
+    /// Creates a tracer with a default `Engine` that does not optimize scripts, so
+    /// that expressions the optimizer would fold away, such as `1 + 2`, keep their spans.
     pub fn new() -> Self {
+        Self::with_engine(Engine::new()).optimization_level(OptimizationLevel::None)
+    }
+
+    /// Creates a tracer that compiles scripts with `engine`, so that scripts using
+    /// registered custom syntax, custom operators or disabled symbols parse the same
+    /// way as they do for execution.
+    ///
+    /// The tracer takes ownership of the engine, as Rhai engines cannot be cloned.
+    /// Use [`engine`](SpanTracer::engine) to keep running scripts with it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai::Engine;
+    /// use rhai_trace::SpanTracer;
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.register_custom_operator("implies", 160).unwrap();
+    /// engine.register_fn("implies", |a: bool, b: bool| !a || b);
+    ///
+    /// let tracer = SpanTracer::with_engine(engine);
+    /// let spans = tracer.extract_from("let x = true implies false;").unwrap();
+    /// assert!(!spans.is_empty());
+    ///
+    /// let x: bool = tracer.engine().eval("true implies false").unwrap();
+    /// assert!(!x);
+    /// ```
+    pub fn with_engine(engine: Engine) -> Self {
         Self {
-            engine: Engine::new(),
+            engine,
+            max_source_bytes: DEFAULT_MAX_SOURCE_BYTES,
+            tab_width: 1,
+            max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
         }
     }
 
+    /// Returns the engine this tracer compiles scripts with.
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Sets how much the engine optimizes scripts before their spans are extracted.
+    ///
+    /// [`new`](SpanTracer::new) uses [`OptimizationLevel::None`], so spans reflect the
+    /// script as written. An engine passed to [`with_engine`](SpanTracer::with_engine)
+    /// keeps its own level, which matches the `AST` it executes. This also changes
+    /// the level of scripts run with [`engine`](SpanTracer::engine).
+    pub fn optimization_level(mut self, level: OptimizationLevel) -> Self {
+        self.engine.set_optimization_level(level);
+        self
+    }
+
+    /// Sets the largest script, in bytes, that this tracer accepts.
+    /// Defaults to [`DEFAULT_MAX_SOURCE_BYTES`].
+    pub fn max_source_bytes(mut self, max_source_bytes: usize) -> Self {
+        self.max_source_bytes = max_source_bytes;
+        self
+    }
+
+    /// Sets how many columns apart tab stops are when computing the
+    /// [`display_column`](Span::display_column) of extracted spans, e.g. 4 or 8 to
+    /// match an editor or terminal. Defaults to 1, so a tab counts as one column,
+    /// like in [`column`](Span::column). A width of 0 is treated as 1.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::SpanTracer;
+    ///
+    /// let script = "if true {\n\tprint(1);\n}";
+    /// let spans = SpanTracer::new().tab_width(4).extract_from(script).unwrap();
+    ///
+    /// let call = spans.iter().find(|span| span.line() == 2).unwrap();
+    /// assert_eq!(call.column(), 2);
+    /// assert_eq!(call.display_column(), 5);
+    /// ```
+    pub fn tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = tab_width.max(1);
+        self
+    }
+
+    /// Sets how deeply the statements and expressions of a script may nest before
+    /// extracting its spans fails with [`TraceError::NestingTooDeep`], rather than
+    /// overflowing the stack. Defaults to [`DEFAULT_MAX_NESTING_DEPTH`].
+    ///
+    /// Rhai's own limits, set with `Engine::set_max_expr_depths`, already reject
+    /// most deeply nested scripts when compiling. This limit protects engines
+    /// that raise or lift them.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::{SpanTracer, TraceError};
+    ///
+    /// let tracer = SpanTracer::new().max_nesting_depth(4);
+    /// let err = tracer.extract_from("let a = [[[[[1]]]]];").unwrap_err();
+    ///
+    /// assert_eq!(
+    ///     err.downcast_ref::<TraceError>(),
+    ///     Some(&TraceError::NestingTooDeep { max: 4 })
+    /// );
+    /// ```
+    pub fn max_nesting_depth(mut self, max_nesting_depth: usize) -> Self {
+        self.max_nesting_depth = max_nesting_depth;
+        self
+    }
+
+    /// Returns the settings of this tracer that the walk of an `AST` uses.
+    fn walk_options(&self) -> WalkOptions {
+        WalkOptions {
+            tab_width: self.tab_width,
+            max_depth: self.max_nesting_depth,
+        }
+    }
+
+    /// Returns the settings of this tracer that a [`SpanCache`](crate::SpanCache)
+    /// keys its spans by.
+    pub(crate) fn extract_settings(&self) -> ExtractSettings {
+        ExtractSettings {
+            level: self.engine.optimization_level(),
+            walk: self.walk_options(),
+            max_source_bytes: self.max_source_bytes,
+        }
+    }
+
+    /// Checks the script against the size limit, then compiles it.
+    fn compile(&self, script: &str) -> Result<AST, Box<dyn Error>> {
+        check_source_size(script, self.max_source_bytes)?;
+        Ok(self.engine.compile(&*lexer::compilable(script))?)
+    }
+
     /// Extracts all spans (start/end byte offsets, line, column) from a Rhai script.
     /// Returns a `Vec<Span>` on success or an error if the script cannot be compiled.
+    ///
+    /// The spans are free of duplicates and sorted in ascending [order](Span#ordering):
+    /// by start offset, with an enclosing span before the spans nested inside it.
+    /// Each span carries the [`SpanKind`] of the innermost node found at its position.
     pub fn extract_from<S: AsRef<str>>(&self, script: S) -> Result<Vec<Span>, Box<dyn Error>> {
         let script_ref = script.as_ref();
-        let ast = self.engine.compile(script_ref)?;
-        let mut spans = Vec::new();
+        let ast = self.compile(script_ref)?;
 
-        let mut walk_result: Result<(), Box<dyn std::error::Error>> = Ok(());
+        self.extract_from_ast(&ast, script_ref)
+    }
 
-        ast.walk(&mut |nodes: &[rhai::ASTNode]| {
-            let current_node = &nodes[0];
+    /// Extracts spans like [`extract_from`](SpanTracer::extract_from), recovering what it
+    /// can from a script that does not compile. Returns the spans along with the first
+    /// parse error, which is `None` if the script compiles.
+    ///
+    /// Recovery is best-effort. As in [`BetterError::improve_all_parse_errors`], the
+    /// line of each parse error is blanked out and the script compiled again. When that
+    /// makes no progress, e.g. for an unclosed brace, the script is cut after the last
+    /// complete top-level statement that compiles. Blanked lines and everything after
+    /// the cut get no spans. Returns an error only if the script is too large.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::SpanTracer;
+    ///
+    /// let script = "let a = 1;\nlet b = a + 2;\nlet c = b +;";
+    /// let (spans, error) = SpanTracer::new().extract_partial(script).unwrap();
+    ///
+    /// assert_eq!(error.unwrap().position().line(), Some(3));
+    /// assert!(spans.iter().any(|span| span.line() == 2));
+    /// assert!(spans.iter().all(|span| span.line() < 3));
+    /// ```
+    ///
+    /// [`BetterError::improve_all_parse_errors`]: crate::BetterError::improve_all_parse_errors
+    pub fn extract_partial<S: AsRef<str>>(
+        &self,
+        script: S,
+    ) -> Result<(Vec<Span>, Option<ParseError>), Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        check_source_size(script_ref, self.max_source_bytes)?;
+
+        let mut first_error = None;
+        let mut masked = lexer::compilable(script_ref).into_owned();
+        let mut last_line = 0;
+
+        // Blanking keeps every other line and column where it was, so the spans
+        // of the masked script point into the original one.
+        let ast = loop {
+            let error = match self.engine.compile(&masked) {
+                Ok(ast) => break ast,
+                Err(error) => error,
+            };
+            let line = error.position().line().unwrap_or(0);
+            first_error.get_or_insert(error);
+            if line <= last_line {
+                break self.compile_prefix(&masked);
+            }
+
+            last_line = line;
+            masked = blank_line(&masked, line);
+        };
+
+        Ok((self.extract_from_ast(&ast, script_ref)?, first_error))
+    }
 
-            if let rhai::ASTNode::Stmt(stmt) = current_node {
-                if let Err(e) = Self::walk_stmt(stmt, script_ref, &mut spans) {
-                    walk_result = Err(e);
-                    return false;
+    /// Compiles the longest prefix of `script` that ends after a top-level statement
+    /// and compiles.
+    fn compile_prefix(&self, script: &str) -> AST {
+        let mut depth = 0usize;
+        let mut ends = vec![0];
+
+        for lexeme in lexer::tokenize(&self.engine, script) {
+            match lexeme.token {
+                Token::LeftParen | Token::LeftBracket | Token::LeftBrace | Token::MapStart => {
+                    depth += 1
+                }
+                Token::RightParen | Token::RightBracket | Token::RightBrace => {
+                    depth = depth.saturating_sub(1)
                 }
+                _ => {}
+            }
+            if depth == 0 && matches!(lexeme.token, Token::SemiColon | Token::RightBrace) {
+                ends.push(lexeme.span.end());
             }
+        }
 
-            true
+        ends.iter()
+            .rev()
+            .find_map(|&end| self.engine.compile(&script[..end]).ok())
+            .unwrap_or_else(AST::empty)
+    }
+
+    /// Extracts a [`SpanNode`] for every AST node of a Rhai script, describing both
+    /// where the node is and what kind of node it is.
+    /// Returns an error if the script cannot be compiled.
+    ///
+    /// Unlike [`extract_from`](SpanTracer::extract_from), nodes sharing a position are
+    /// all kept, e.g. an assignment and the variable it assigns to. Nodes are sorted
+    /// like spans, with a node before the nodes nested inside it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::{SpanKind, SpanTracer};
+    ///
+    /// let nodes = SpanTracer::new().extract_nodes("let x = 1;\nx += 2;").unwrap();
+    /// let kinds: Vec<SpanKind> = nodes.iter().map(|node| node.kind).collect();
+    ///
+    /// assert!(kinds.contains(&SpanKind::VariableDecl));
+    /// assert!(kinds.contains(&SpanKind::Assignment));
+    /// ```
+    pub fn extract_nodes<S: AsRef<str>>(
+        &self,
+        script: S,
+    ) -> Result<Vec<SpanNode>, Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        let ast = self.compile(script_ref)?;
+
+        Self::nodes_from_ast(&ast, script_ref, &self.engine, self.walk_options())
+    }
+
+    /// Calls `visitor` with a [`SpanNode`] for every AST node of a Rhai script, stopping
+    /// as soon as it returns [`ControlFlow::Break`].
+    /// Returns an error if the script cannot be compiled.
+    ///
+    /// Unlike [`extract_nodes`](SpanTracer::extract_nodes), no `Vec` is built, so
+    /// nodes arrive in walk order: each node before the nodes nested inside it, and
+    /// function signatures last. Nodes sharing a position are all visited.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::{SpanKind, SpanTracer};
+    /// use std::ops::ControlFlow;
+    ///
+    /// let script = "let a = 1;\nlet b = a + 2;\nprint(b);";
+    ///
+    /// // Find the first function call, without walking the rest of the script.
+    /// let mut call = None;
+    /// SpanTracer::new()
+    ///     .visit(script, |node| {
+    ///         if node.kind != SpanKind::FnCall {
+    ///             return ControlFlow::Continue(());
+    ///         }
+    ///         call = Some(node.span);
+    ///         ControlFlow::Break(())
+    ///     })
+    ///     .unwrap();
+    ///
+    /// // `a + 2` is an operator, not a call.
+    /// assert_eq!(call.unwrap().line(), 3);
+    /// ```
+    pub fn visit<S: AsRef<str>>(
+        &self,
+        script: S,
+        mut visitor: impl FnMut(&SpanNode) -> ControlFlow<()>,
+    ) -> Result<(), Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        let ast = self.compile(script_ref)?;
+
+        Self::visit_ast(&ast, script_ref, &self.engine, self.walk_options(), &mut visitor)
+    }
+
+    /// Extracts the spans of a Rhai script as a tree, with the spans of nested nodes
+    /// as children of the node they are nested in.
+    /// Returns an error if the script cannot be compiled.
+    ///
+    /// The root covers the whole script and is of kind [`SpanKind::Script`]. Function
+    /// signatures are children of the root, alongside the top-level statements.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::{SpanKind, SpanTracer};
+    ///
+    /// let script = "if ready { launch(1); }";
+    /// let tree = SpanTracer::new().extract_tree(script).unwrap();
+    ///
+    /// let node = tree.innermost_at(script.find("launch").unwrap()).unwrap();
+    /// assert_eq!(node.kind, SpanKind::FnName);
+    /// assert_eq!(tree.children[0].kind, SpanKind::If);
+    /// ```
+    pub fn extract_tree<S: AsRef<str>>(&self, script: S) -> Result<SpanTree, Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        let ast = self.compile(script_ref)?;
+        let collector = Self::collect(&ast, script_ref, &self.engine, self.walk_options())?;
+
+        Ok(collector.into_tree(Span::from_range(script_ref, 0..script_ref.len())))
+    }
+
+    /// Extracts the spans of the body of every script function defined in a Rhai
+    /// script, tagged with the function's name. Functions are in source order.
+    /// Returns an error if the script cannot be compiled.
+    ///
+    /// Closures are not listed; their spans belong to the function they are written in.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::SpanTracer;
+    ///
+    /// let script = "fn multiply(x, y) { x * y }\nlet c = multiply(2, 3);";
+    /// let functions = SpanTracer::new().extract_functions(script).unwrap();
+    ///
+    /// assert_eq!(functions[0].name, "multiply");
+    /// assert!(functions[0].spans.iter().all(|span| span.line() == 1));
+    /// ```
+    pub fn extract_functions<S: AsRef<str>>(
+        &self,
+        script: S,
+    ) -> Result<Vec<FunctionSpans>, Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        let ast = self.compile(script_ref)?;
+        let spans = Self::spans_from_ast(&ast, script_ref, &self.engine, self.walk_options())?;
+
+        // The lexer finds each body, so closures stay with the function they are in.
+        let functions = lexer::function_bodies(&self.engine, script_ref)
+            .into_iter()
+            .map(|function| FunctionSpans {
+                spans: spans
+                    .iter()
+                    .filter(|span| span.kind() != SpanKind::FnDef)
+                    .filter(|span| function.body.contains(&span.start()))
+                    .copied()
+                    .collect(),
+                arity: function.params.len(),
+                name: function.name,
+            })
+            .collect();
+
+        Ok(functions)
+    }
+
+    /// Extracts every variable, constant and parameter of a Rhai script along with
+    /// its declaration and references, resolved with Rhai's scoping rules as
+    /// described in [`SymbolTable`]. Returns an error if the script cannot be compiled.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::SpanTracer;
+    ///
+    /// let script = "let x = 1;\n{\n    let x = x + 1;\n    print(x);\n}\nprint(x);";
+    /// let table = SpanTracer::new().extract_identifiers(script).unwrap();
+    ///
+    /// // The inner `x` shadows the outer one, which its initializer still reads.
+    /// let lines = |i: usize| -> Vec<usize> {
+    ///     table.symbols[i].references.iter().map(|span| span.line()).collect()
+    /// };
+    /// assert_eq!(table.named("x").count(), 2);
+    /// assert_eq!(lines(0), [3, 6]);
+    /// assert_eq!(lines(1), [4]);
+    /// ```
+    pub fn extract_identifiers<S: AsRef<str>>(&self, script: S) -> Result<SymbolTable, Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        let ast = self.compile(script_ref)?;
+        let nodes = Self::kinded_spans_from_ast(&ast, script_ref, &self.engine, self.walk_options())?;
+
+        Ok(symbols::collect(script_ref, &self.engine, &nodes))
+    }
+
+    /// Returns the outline of a Rhai script: every function definition, with the
+    /// `let` and `const` declarations of its body as children, followed by the
+    /// top-level declarations and `import`s, all in source order.
+    /// Returns an error if the script cannot be compiled.
+    ///
+    /// Declarations inside blocks at the top level, such as the body of an `if`,
+    /// are left out.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::{DocumentSymbolKind, SpanTracer};
+    ///
+    /// let script = "const LIMIT = 10;\nfn clamp(x) {\n    let low = 0;\n    x.min(LIMIT).max(low)\n}";
+    /// let outline = SpanTracer::new().document_symbols(script).unwrap();
+    ///
+    /// assert_eq!(outline[0].name, "LIMIT");
+    /// assert_eq!(outline[0].kind, DocumentSymbolKind::Constant);
+    /// assert_eq!(outline[1].name, "clamp");
+    /// assert_eq!(outline[1].detail.as_deref(), Some("(x)"));
+    /// assert_eq!(outline[1].children[0].name, "low");
+    /// assert_eq!(outline[1].full_span.end(), script.len());
+    /// ```
+    pub fn document_symbols<S: AsRef<str>>(
+        &self,
+        script: S,
+    ) -> Result<Vec<DocumentSymbol>, Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        let ast = self.compile(script_ref)?;
+        let nodes = Self::kinded_spans_from_ast(&ast, script_ref, &self.engine, self.walk_options())?;
+
+        Ok(outline::collect(script_ref, &self.engine, self.tab_width, &nodes))
+    }
+
+    /// Classifies the tokens of a Rhai script for semantic highlighting, e.g. as
+    /// keywords, functions, variables or parameters, in the terms of the Language
+    /// Server Protocol. Returns an error if the script cannot be compiled.
+    ///
+    /// Tokens are in source order and never overlap. A token spanning several lines,
+    /// such as a multi-line string, is split into one token per line. Punctuation,
+    /// comments and identifiers that cannot be resolved get no token. Declarations
+    /// are told apart from references by [`TokenModifiers::declaration`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::{SpanTracer, TokenType};
+    ///
+    /// let script = "fn double(x) { x * 2 }\nconst TWO = double(1);";
+    /// let tokens = SpanTracer::new().semantic_tokens(script).unwrap();
+    ///
+    /// let types: Vec<&str> = tokens.iter().map(|token| token.token_type.as_str()).collect();
+    /// assert_eq!(
+    ///     types,
+    ///     [
+    ///         "keyword", "function", "parameter", "parameter", "operator", "number",
+    ///         "keyword", "variable", "operator", "function", "number",
+    ///     ]
+    /// );
+    /// assert!(tokens[7].modifiers.declaration && tokens[7].modifiers.readonly);
+    /// assert_eq!(tokens[9].token_type, TokenType::Function);
+    /// ```
+    ///
+    /// [`TokenModifiers::declaration`]: crate::TokenModifiers::declaration
+    pub fn semantic_tokens<S: AsRef<str>>(
+        &self,
+        script: S,
+    ) -> Result<Vec<SemanticToken>, Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        let ast = self.compile(script_ref)?;
+        let nodes = Self::kinded_spans_from_ast(&ast, script_ref, &self.engine, self.walk_options())?;
+        let symbols = symbols::collect(script_ref, &self.engine, &nodes);
+
+        Ok(semantic::collect(script_ref, &self.engine, self.tab_width, &nodes, &symbols))
+    }
+
+    /// Extracts all spans from a Rhai script like [`extract_from`](SpanTracer::extract_from),
+    /// tagging each one with the [`SourceId`](crate::SourceId) of `name` in `names`,
+    /// e.g. the script's file name.
+    ///
+    /// Pass the result to [`BetterError::improve_eval_error`](crate::BetterError::improve_eval_error)
+    /// to keep the source on the improved error's span.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::{SourceNames, SpanTracer};
+    ///
+    /// let mut names = SourceNames::new();
+    /// let tracer = SpanTracer::new();
+    /// let spans = tracer.extract_from_named(&mut names, "main.rhai", "let a = 1;").unwrap();
+    ///
+    /// assert_eq!(spans[0].source_name(&names), Some("main.rhai"));
+    /// ```
+    pub fn extract_from_named<S: AsRef<str>>(
+        &self,
+        names: &mut SourceNames,
+        name: &str,
+        script: S,
+    ) -> Result<Vec<Span>, Box<dyn Error>> {
+        let source_id = Some(names.intern(name));
+
+        Ok(self
+            .extract_from(script)?
+            .into_iter()
+            .map(|span| span.with_source_id(source_id))
+            .collect())
+    }
+
+    /// Extracts the spans of `script` like [`extract_from`](SpanTracer::extract_from),
+    /// along with statistics about them, e.g. to tune the tracer or to spot spans
+    /// missing after a change to the walk.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::{SpanKind, SpanTracer};
+    ///
+    /// let (spans, stats) = SpanTracer::new()
+    ///     .extract_report("let a = 1;\nif a > 0 { print(a); }")
+    ///     .unwrap();
+    ///
+    /// assert_eq!(stats.total, spans.len());
+    /// assert_eq!(stats.per_kind[&SpanKind::If], 1);
+    /// assert!(stats.max_depth >= 3);
+    /// ```
+    pub fn extract_report<S: AsRef<str>>(
+        &self,
+        script: S,
+    ) -> Result<(Vec<Span>, SpanStats), Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        let started = Instant::now();
+        let ast = self.compile(script_ref)?;
+        let collector = Self::collect(&ast, script_ref, &self.engine, self.walk_options())?;
+
+        // Parents are always collected before the nodes nested in them.
+        let mut depths: Vec<usize> = Vec::with_capacity(collector.parents.len());
+        for parent in &collector.parents {
+            depths.push(parent.map_or(1, |parent| depths[parent] + 1));
+        }
+        let spans = Self::dedup_nodes(Self::sort_nodes(collector.spans));
+        let duration = started.elapsed();
+
+        let mut per_kind: HashMap<SpanKind, usize> = HashMap::new();
+        for span in &spans {
+            *per_kind.entry(span.kind()).or_default() += 1;
+        }
+        let stats = SpanStats {
+            total: spans.len(),
+            per_kind,
+            max_depth: depths.into_iter().max().unwrap_or(0),
+            duration,
+        };
+        Ok((spans, stats))
+    }
+
+    /// Extracts all spans from an already compiled `AST` of `script`, deduplicated
+    /// and sorted like those of [`extract_from`](SpanTracer::extract_from).
+    ///
+    /// Use this instead of [`extract_from`](SpanTracer::extract_from) when the script has
+    /// already been compiled, e.g. for execution, to avoid parsing it a second time.
+    pub fn extract_from_ast(&self, ast: &AST, script: &str) -> Result<Vec<Span>, Box<dyn Error>> {
+        check_source_size(script, self.max_source_bytes)?;
+
+        Self::spans_from_ast(ast, script, &self.engine, self.walk_options())
+    }
+
+    /// Extracts all spans from a script embedded in a host document, such as a
+    /// YAML or Markdown file, in the coordinates of that document.
+    ///
+    /// `host_offset` is where the script starts in the host document, e.g.
+    /// `Span::from_byte_range(host, start, start)`. Spans are shifted with
+    /// [`Span::with_offset`], so a script starting mid-line is handled too.
+    pub fn extract_from_embedded<S: AsRef<str>>(
+        &self,
+        script: S,
+        host_offset: Span,
+    ) -> Result<Vec<Span>, Box<dyn Error>> {
+        let spans = self.extract_from(script)?;
+        let line_delta = host_offset.line().saturating_sub(1);
+        let column_delta = host_offset.column().saturating_sub(1);
+
+        Ok(spans
+            .into_iter()
+            .map(|span| span.with_offset(host_offset.start(), line_delta, column_delta))
+            .collect())
+    }
+
+    /// Runs a Rhai script with `engine` and `scope`, returning its result, or a
+    /// [`BetterError`] if it fails to compile or run.
+    ///
+    /// The script is compiled once, and the same `AST` is used to run it and to
+    /// improve its error, so this is the one-call form of running a script and
+    /// then calling [`BetterError::improve_eval_error`]. Compile errors are
+    /// reported like by [`validate_full`]. The size limit and tab width of this
+    /// tracer apply, but the script runs on `engine` rather than on the tracer's
+    /// own, so functions registered on it are available.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai::{Engine, Scope};
+    /// use rhai_trace::SpanTracer;
+    ///
+    /// let engine = Engine::new();
+    /// let mut scope = Scope::new();
+    /// let tracer = SpanTracer::new();
+    ///
+    /// assert_eq!(tracer.eval::<i64>(&engine, &mut scope, "40 + 2").unwrap(), 42);
+    ///
+    /// let err = tracer.eval::<i64>(&engine, &mut scope, "let a = 1;\na + b").unwrap_err();
+    /// assert_eq!(err.span.line(), 2);
+    /// ```
+    pub fn eval<T: Variant + Clone>(
+        &self,
+        engine: &Engine,
+        scope: &mut Scope,
+        code: &str,
+    ) -> Result<T, Box<BetterError>> {
+        check_source_size(code, self.max_source_bytes)
+            .map_err(|err| Box::new(BetterError::builder().message(err.to_string()).build()))?;
+        let ast = validate_full(code, engine)?;
+
+        engine.eval_ast_with_scope::<T>(scope, &ast).map_err(|err| {
+            let spans = Self::spans_from_ast(&ast, code, engine, self.walk_options()).unwrap_or_default();
+            Box::new(BetterError::improve_with(
+                &err,
+                code,
+                || Ok(Cow::Borrowed(&ast)),
+                &spans,
+                Some(engine),
+                Some(scope),
+                &DiagnosticMessages::new(),
+                ImproveOptions::new().max_source_bytes(self.max_source_bytes),
+            ))
+        })
+    }
+
+    /// Statically analyses a Rhai script and returns a [`Lint`] for every
+    /// suspicious construct found, such as a `while true` loop without a `break`,
+    /// a variable that is never read or a `let` shadowing an earlier declaration,
+    /// in source order.
+    /// Returns an error if the script cannot be compiled.
+    pub fn lint<S: AsRef<str>>(&self, script: S) -> Result<Vec<Lint>, Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        let ast = self.compile(script_ref)?;
+        let nodes = Self::kinded_spans_from_ast(&ast, script_ref, &self.engine, self.walk_options())?;
+
+        Ok(lint::run(&ast, script_ref, &self.engine, &nodes))
+    }
+
+    /// Returns the most specific span of a Rhai script covering the 1-based `line`
+    /// and `column`, as counted by Rhai.
+    /// Returns an error if the script cannot be compiled.
+    ///
+    /// Columns past the end of the line are treated as the end of the line. See
+    /// [`span_at_offset`](SpanTracer::span_at_offset) for how the span is chosen.
+    pub fn span_at<S: AsRef<str>>(
+        &self,
+        script: S,
+        line: usize,
+        column: usize,
+    ) -> Result<Option<Span>, Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        let spans = self.extract_from(script_ref)?;
+        let offset = line_index::scan_offset(script_ref, line, column);
+
+        Ok(Self::find_span(&spans, script_ref, offset))
+    }
+
+    /// Returns the most specific span of a Rhai script covering the byte `offset`.
+    /// Returns an error if the script cannot be compiled.
+    ///
+    /// The smallest span containing the offset is preferred. When none does, e.g.
+    /// in the indentation before a statement, the span starting nearest to the
+    /// offset on the same line is returned instead. Offsets on lines without any
+    /// span, such as blank lines, and offsets past the end of the script give `None`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::{SpanKind, SpanTracer};
+    ///
+    /// let script = "let a = 1;\n\n    print(a);";
+    /// let tracer = SpanTracer::new();
+    ///
+    /// let span = tracer.span_at_offset(script, script.find("print").unwrap()).unwrap();
+    /// assert_eq!(span.unwrap().kind(), SpanKind::FnName);
+    /// // The indentation falls back to the name of the call on the same line.
+    /// assert_eq!(tracer.span_at(script, 3, 1).unwrap(), span);
+    /// // The blank line has no span.
+    /// assert_eq!(tracer.span_at(script, 2, 1).unwrap(), None);
+    /// ```
+    pub fn span_at_offset<S: AsRef<str>>(
+        &self,
+        script: S,
+        offset: usize,
+    ) -> Result<Option<Span>, Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        let spans = self.extract_from(script_ref)?;
+
+        Ok(Self::find_span(&spans, script_ref, offset))
+    }
+
+    /// Returns the most specific of `spans` covering the byte `offset`, as described
+    /// in [`span_at_offset`](SpanTracer::span_at_offset).
+    ///
+    /// Nothing is allocated besides the returned span.
+    pub(crate) fn find_span(spans: &[Span], source: &str, offset: usize) -> Option<Span> {
+        if offset > source.len() {
+            return None;
+        }
+
+        let spans = spans.iter().filter(|span| !span.is_none());
+        let covering = spans
+            .clone()
+            .filter(|span| span.contains_offset(offset) || span.start() == offset)
+            // Among equally narrow spans, the one starting later is the more nested.
+            .min_by_key(|span| (span.len(), Reverse(span.start())));
+
+        let (line, _) = line_index::scan_position(source, offset);
+        covering
+            .or_else(|| {
+                spans
+                    .filter(|span| span.line() == line)
+                    .min_by_key(|span| (span.start().abs_diff(offset), span.len()))
+            })
+            .copied()
+    }
+
+    /// Returns non-overlapping byte ranges of a Rhai script, each colored with the
+    /// [`SpanKind`] of the most specific (shortest) span covering it.
+    ///
+    /// This is meant for syntax highlighters: the ranges are sorted, never overlap,
+    /// and bytes not covered by any span are left out.
+    /// Returns an error if the script cannot be compiled.
+    pub fn highlight_ranges<S: AsRef<str>>(
+        &self,
+        script: S,
+    ) -> Result<Vec<HighlightRange>, Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        let ast = self.compile(script_ref)?;
+        let mut spans = Self::kinded_spans_from_ast(&ast, script_ref, &self.engine, self.walk_options())?;
+        // Argument lists only group the arguments and have no color of their own.
+        spans.retain(|(_, kind)| *kind != SpanKind::Arguments);
+
+        // Paint longer spans first so that more specific ones overwrite them. The sort
+        // is stable, so children (which are walked after their parents) win ties.
+        spans.sort_by_key(|(span, _)| Reverse(span.len()));
+
+        let mut painted: Vec<Option<SpanKind>> = vec![None; script_ref.len()];
+        for (span, kind) in &spans {
+            let end = span.end().min(script_ref.len());
+            if span.start() < end {
+                painted[span.start()..end].fill(Some(*kind));
+            }
+        }
+
+        let mut ranges: Vec<HighlightRange> = Vec::new();
+        for (offset, kind) in painted.into_iter().enumerate() {
+            let Some(kind) = kind else { continue };
+            match ranges.last_mut() {
+                Some((range, last)) if range.end == offset && *last == kind => range.end += 1,
+                _ => ranges.push((offset..offset + 1, kind)),
+            }
+        }
+
+        Ok(ranges)
+    }
+
+    /// Returns the boundaries of every top-level statement of a Rhai script,
+    /// including function definitions, in source order.
+    ///
+    /// Statements are split on their tokens, without walking expression internals.
+    /// Returns an error if the script cannot be compiled.
+    pub fn statement_ranges<S: AsRef<str>>(
+        &self,
+        script: S,
+    ) -> Result<Vec<StatementRange>, Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        self.compile(script_ref)?;
+
+        let lexemes = lexer::tokenize(&self.engine, script_ref);
+        let index = LineIndex::new(script_ref);
+        let mut ranges = Vec::new();
+        let mut i = 0;
+
+        while i < lexemes.len() {
+            let first = &lexemes[i].token;
+            let ends_with_block = matches!(
+                first,
+                Token::If
+                    | Token::Switch
+                    | Token::While
+                    | Token::Loop
+                    | Token::For
+                    | Token::Do
+                    | Token::Try
+                    | Token::Fn
+                    | Token::Private
+                    | Token::LeftBrace
+            );
+
+            let mut depth = 0usize;
+            let mut last = i;
+            while last < lexemes.len() {
+                match &lexemes[last].token {
+                    Token::LeftBrace
+                    | Token::MapStart
+                    | Token::LeftParen
+                    | Token::LeftBracket
+                    | Token::QuestionBracket => depth += 1,
+                    Token::RightParen | Token::RightBracket => depth = depth.saturating_sub(1),
+                    Token::RightBrace => {
+                        depth = depth.saturating_sub(1);
+                        if depth == 0 && ends_with_block {
+                            match lexemes.get(last + 1).map(|l| &l.token) {
+                                // The statement continues with another clause.
+                                Some(Token::Else | Token::Catch) => {}
+                                Some(Token::While | Token::Until) if *first == Token::Do => {}
+                                Some(Token::SemiColon) => {
+                                    last += 1;
+                                    break;
+                                }
+                                _ => break,
+                            }
+                        }
+                    }
+                    Token::SemiColon if depth == 0 => break,
+                    _ => {}
+                }
+                last += 1;
+            }
+            let last = last.min(lexemes.len() - 1);
+
+            let kind = match first {
+                Token::Let | Token::Const | Token::Export | Token::Import => {
+                    StatementKind::Declaration
+                }
+                Token::Fn | Token::Private => StatementKind::FunctionDefinition,
+                Token::If
+                | Token::Switch
+                | Token::While
+                | Token::Loop
+                | Token::For
+                | Token::Do
+                | Token::Try
+                | Token::Return
+                | Token::Throw
+                | Token::Break
+                | Token::Continue => StatementKind::ControlFlow,
+                _ => StatementKind::Expression,
+            };
+
+            // A lone `;` is an empty statement, not worth reporting.
+            if !(last == i && *first == Token::SemiColon) {
+                ranges.push(StatementRange {
+                    span: Span::from_range_in(
+                        &index,
+                        lexemes[i].span.start()..lexemes[last].span.end(),
+                    ),
+                    index: ranges.len(),
+                    kind,
+                });
+            }
+
+            i = last + 1;
+        }
+
+        Ok(ranges)
+    }
+
+    /// Returns a span for every bracketed construct of a Rhai script that spans
+    /// several lines, for folding in an editor: blocks, function and `switch`
+    /// bodies, and array and object map literals.
+    /// Returns an error if the script cannot be compiled.
+    ///
+    /// Each span runs from the opening bracket through the closing one, so it
+    /// starts and ends on different lines. Constructs on a single line are left
+    /// out. The spans are sorted in [order](Span#ordering), outer spans first.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rhai_trace::SpanTracer;
+    ///
+    /// let script = "fn check(x) {\n    if x { print(x); }\n    let list = [\n        1,\n    ];\n}";
+    /// let ranges = SpanTracer::new().folding_ranges(script).unwrap();
+    ///
+    /// let lines: Vec<(usize, usize)> =
+    ///     ranges.iter().map(|span| (span.line(), span.end_line())).collect();
+    /// assert_eq!(lines, [(1, 6), (3, 5)]);
+    /// ```
+    pub fn folding_ranges<S: AsRef<str>>(&self, script: S) -> Result<Vec<Span>, Box<dyn Error>> {
+        let script_ref = script.as_ref();
+        self.compile(script_ref)?;
+
+        let lexemes = lexer::tokenize(&self.engine, script_ref);
+        let index = LineIndex::new(script_ref).with_tab_width(self.tab_width);
+        let mut open: Vec<usize> = Vec::new();
+        let mut ranges = Vec::new();
+
+        for lexeme in &lexemes {
+            match lexeme.token {
+                Token::LeftBrace
+                | Token::MapStart
+                | Token::LeftParen
+                | Token::LeftBracket
+                | Token::QuestionBracket => open.push(lexeme.span.start()),
+                Token::RightBrace | Token::RightParen | Token::RightBracket => {
+                    let Some(start) = open.pop() else { continue };
+                    // Argument lists and parenthesized expressions are not folded.
+                    if script_ref[start..].starts_with('(') {
+                        continue;
+                    }
+                    let span = Span::from_range_in(&index, start..lexeme.span.end());
+                    if span.end_line() > span.line() {
+                        ranges.push(span);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        ranges.sort();
+        ranges.dedup();
+        Ok(ranges)
+    }
+
+    /// Walks an already compiled `AST` of `script` and collects its spans.
+    ///
+    /// `engine` is the one that compiled the `AST`; it is consulted for custom operators.
+    /// `options` sets the tab width used for the spans'
+    /// [`display_column`](Span::display_column) and how deeply the walk may nest.
+    pub(crate) fn spans_from_ast(
+        ast: &AST,
+        script: &str,
+        engine: &Engine,
+        options: WalkOptions,
+    ) -> Result<Vec<Span>, Box<dyn Error>> {
+        let nodes = Self::nodes_from_ast(ast, script, engine, options)?;
+        Ok(Self::dedup_nodes(nodes))
+    }
+
+    /// Returns the spans of sorted `nodes`, one per position.
+    fn dedup_nodes(nodes: Vec<SpanNode>) -> Vec<Span> {
+        let mut spans: Vec<Span> = nodes.into_iter().map(|node| node.span).collect();
+
+        // A statement and the expression it starts with often share a position.
+        // Keep one span per position, with the kind of the innermost node.
+        spans.dedup_by(|next, kept| {
+            let same = next.with_kind(SpanKind::Other) == kept.with_kind(SpanKind::Other);
+            if same {
+                *kept = *next;
+            }
+            same
+        });
+
+        spans
+    }
+
+    /// Walks an already compiled `AST` of `script` and collects a sorted node for
+    /// every span, each span carrying the kind of its node.
+    fn nodes_from_ast(
+        ast: &AST,
+        script: &str,
+        engine: &Engine,
+        options: WalkOptions,
+    ) -> Result<Vec<SpanNode>, Box<dyn Error>> {
+        let spans = Self::kinded_spans_from_ast(ast, script, engine, options)?;
+        Ok(Self::sort_nodes(spans))
+    }
+
+    /// Turns the kinded `spans` of a walk into nodes sorted like spans.
+    fn sort_nodes(spans: Vec<(Span, SpanKind)>) -> Vec<SpanNode> {
+        let mut nodes: Vec<SpanNode> = spans
+            .into_iter()
+            .map(|(span, kind)| SpanNode {
+                span: span.with_kind(kind),
+                kind,
+            })
+            .collect();
+
+        // The sort is stable, and nested nodes are walked after their parents.
+        nodes.sort_by_key(|node| node.span.with_kind(SpanKind::Other));
+        nodes
+    }
+
+    /// Walks an already compiled `AST` of `script` and collects its spans
+    /// along with the kind of node each one belongs to.
+    fn kinded_spans_from_ast(
+        ast: &AST,
+        script: &str,
+        engine: &Engine,
+        options: WalkOptions,
+    ) -> Result<Vec<(Span, SpanKind)>, Box<dyn Error>> {
+        let mut spans = Vec::new();
+        Self::visit_ast(ast, script, engine, options, &mut |node| {
+            spans.push((node.span, node.kind));
+            ControlFlow::Continue(())
+        })?;
+
+        Ok(spans)
+    }
+
+    /// Walks an already compiled `AST` of `script`, handing every node to `visitor`
+    /// until it breaks.
+    fn visit_ast(
+        ast: &AST,
+        script: &str,
+        engine: &Engine,
+        options: WalkOptions,
+        visitor: &mut dyn FnMut(&SpanNode) -> ControlFlow<()>,
+    ) -> Result<(), Box<dyn Error>> {
+        Self::walk_ast(ast, script, engine, options, SpanCollector::visiting(visitor))?;
+        Ok(())
+    }
+
+    /// Walks an already compiled `AST` of `script`, recording each span's parent.
+    fn collect(
+        ast: &AST,
+        script: &str,
+        engine: &Engine,
+        options: WalkOptions,
+    ) -> Result<SpanCollector<'static>, Box<dyn Error>> {
+        Self::walk_ast(ast, script, engine, options, SpanCollector::default())
+    }
+
+    fn walk_ast<'v>(
+        ast: &AST,
+        script: &str,
+        engine: &Engine,
+        options: WalkOptions,
+        mut spans: SpanCollector<'v>,
+    ) -> Result<SpanCollector<'v>, Box<dyn Error>> {
+        let index = LineIndex::new(script).with_tab_width(options.tab_width);
+        spans.max_depth = options.max_depth;
+
+        let mut walk_result: Result<(), Box<dyn Error>> = Ok(());
+
+        // Only start at the outermost statements, which `walk_stmt` descends from.
+        // Besides the top-level statements, these are the statements of every
+        // script function, including the anonymous functions closures compile to.
+        ast.walk(&mut |nodes: &[rhai::ASTNode]| {
+            if let [rhai::ASTNode::Stmt(stmt)] = nodes
+                && let Err(e) = Self::walk_stmt(stmt, &index, engine, &mut spans)
+            {
+                walk_result = Err(e);
+                return false;
+            }
+
+            !spans.stopped
         });
 
         walk_result?;
 
+        if !spans.stopped && ast.iter_functions().next().is_some() {
+            Self::push_fn_signatures(script, &index, engine, &mut spans);
+        }
+
         Ok(spans)
     }
 
-    fn walk_stmt(stmt: &Stmt, script: &str, spans: &mut Vec<Span>) -> Result<(), Box<dyn Error>> {
+    /// Pushes a span for the signature of every function definition, from `fn`
+    /// (or `private`) through the parameter list.
+    ///
+    /// The `AST` keeps no position for function definitions, so they are found
+    /// among the script's tokens instead.
+    fn push_fn_signatures(
+        script: &str,
+        index: &LineIndex,
+        engine: &Engine,
+        spans: &mut SpanCollector<'_>,
+    ) {
+        let lexemes = lexer::tokenize(engine, script);
+
+        for (i, lexeme) in lexemes.iter().enumerate() {
+            if spans.stopped {
+                break;
+            }
+            if lexeme.token != Token::Fn {
+                continue;
+            }
+            // The signature ends right before the body's opening brace.
+            let Some(open) = lexemes[i..]
+                .iter()
+                .position(|lexeme| lexeme.token == Token::LeftBrace)
+            else {
+                continue;
+            };
+
+            let start = match i.checked_sub(1).map(|prev| &lexemes[prev]) {
+                Some(prev) if prev.token == Token::Private => prev.span.start(),
+                _ => lexeme.span.start(),
+            };
+            let end = lexemes[i + open - 1].span.end();
+            spans.push((Span::from_range_in(index, start..end), SpanKind::FnDef));
+        }
+    }
+
+    fn walk_stmt(
+        stmt: &Stmt,
+        index: &LineIndex,
+        engine: &Engine,
+        spans: &mut SpanCollector<'_>,
+    ) -> Result<(), Box<dyn Error>> {
         match stmt {
-            Stmt::Noop(pos) => spans.push(Span::from_pos(script, pos)),
-            Stmt::If(flow, pos) | Stmt::While(flow, pos) | Stmt::Do(flow, _, pos) => {
-                spans.push(Span::from_pos(script, pos));
-                Self::walk_flow_control(flow, script, spans)?;
+            Stmt::Noop(pos) => spans.push((Span::from_pos_in(index, pos), SpanKind::Other)),
+            Stmt::If(flow, pos) => {
+                spans.nested((Span::from_pos_in(index, pos), SpanKind::If), |spans| {
+                    Self::walk_flow_control(flow, index, engine, spans)
+                })?;
+            }
+            Stmt::While(flow, pos) => {
+                spans.nested((Span::from_pos_in(index, pos), SpanKind::While), |spans| {
+                    Self::walk_flow_control(flow, index, engine, spans)
+                })?;
+            }
+            Stmt::Do(flow, _, pos) => {
+                spans.nested((Span::from_pos_in(index, pos), SpanKind::Do), |spans| {
+                    Self::walk_flow_control(flow, index, engine, spans)
+                })?;
             }
             Stmt::For(boxed, pos) => {
-                spans.push(Span::from_pos(script, pos));
                 let (_, _, flow) = &**boxed;
-                Self::walk_flow_control(flow, script, spans)?;
+                spans.nested((Span::from_pos_in(index, pos), SpanKind::For), |spans| {
+                    Self::walk_flow_control(flow, index, engine, spans)
+                })?;
             }
-            Stmt::Var(boxed, _, pos) => {
-                spans.push(Span::from_pos(script, pos));
-                let (_, expr, _) = &**boxed;
-                Self::walk_expr(expr, script, spans)?;
+            Stmt::Var(boxed, flags, pos) => {
+                let (ident, expr, _) = &**boxed;
+                spans.nested((Span::from_pos_in(index, pos), SpanKind::VariableDecl), |spans| {
+                    if !ident.pos.is_none() {
+                        let start = pos_to_byte(index, &ident.pos);
+                        let kind = match flags.intersects(ASTFlags::CONSTANT) {
+                            true => SpanKind::ConstantName,
+                            false => SpanKind::VariableName,
+                        };
+                        spans.push((Span::from_range_in(index, start..start + ident.name.len()), kind));
+                    }
+                    Self::walk_expr(expr, index, engine, spans)
+                })?;
             }
             Stmt::Assignment(boxed) => {
-                let (_, expr) = &**boxed;
-                Self::walk_binary_expr(expr, script, spans)?;
+                let (op, expr) = &**boxed;
+                let start = Self::expr_start(&expr.lhs);
+                spans.nested((Span::from_pos_in(index, &start), SpanKind::Assignment), |spans| {
+                    let op_pos = op.position();
+                    if !start.is_none() && !op_pos.is_none() {
+                        let op_start = pos_to_byte(index, &op_pos);
+                        let target = Span::from_range_in(index, pos_to_byte(index, &start)..op_start);
+                        spans.push((target.trim(index.source()), SpanKind::AssignmentTarget));
+
+                        let syntax = op
+                            .get_op_assignment_info()
+                            .map_or("=", |(_, _, _, syntax, ..)| syntax);
+                        let operator = Span::from_range_in(index, op_start..op_start + syntax.len());
+                        spans.push((operator, SpanKind::AssignmentOperator));
+                    }
+                    Self::walk_expr(&expr.lhs, index, engine, spans)?;
+
+                    let value = Span::from_pos_in(index, &Self::expr_start(&expr.rhs));
+                    spans.nested((value, SpanKind::AssignmentValue), |spans| {
+                        Self::walk_expr(&expr.rhs, index, engine, spans)
+                    })
+                })?;
             }
             Stmt::FnCall(boxed, pos) => {
-                spans.push(Span::from_pos(script, pos));
-                Self::walk_fn_call(boxed, script, spans)?;
+                spans.nested((Span::from_pos_in(index, pos), SpanKind::FnCall), |spans| {
+                    Self::walk_fn_call(boxed, pos, index, engine, spans)
+                })?;
             }
             Stmt::Block(block) => {
-                spans.push(Span::from_rhai_span(
-                    script,
-                    block.span(),
-                    &block.position(),
-                ));
-                Self::walk_block(block, script, spans)?;
+                let span = Span::from_rhai_span_in(index, block.span(), &block.position());
+                spans.nested((span, SpanKind::Block), |spans| {
+                    Self::walk_block(block, index, engine, spans)
+                })?;
             }
             Stmt::TryCatch(flow, pos) => {
-                spans.push(Span::from_pos(script, pos));
-                Self::walk_flow_control(flow, script, spans)?;
-            }
-            Stmt::Expr(expr) => Self::walk_expr(expr, script, spans)?,
-            Stmt::BreakLoop(opt_expr, _, pos) | Stmt::Return(opt_expr, _, pos) => {
-                spans.push(Span::from_pos(script, pos));
-                if let Some(expr) = opt_expr {
-                    Self::walk_expr(expr, script, spans)?;
-                }
+                spans.nested((Span::from_pos_in(index, pos), SpanKind::TryCatch), |spans| {
+                    let body = Span::from_rhai_span_in(index, flow.body.span(), &flow.body.position());
+                    spans.nested((body, SpanKind::TryBlock), |spans| {
+                        Self::walk_block(&flow.body, index, engine, spans)
+                    })?;
+                    // `catch` without a variable has a unit expression with no position.
+                    if let Expr::Variable(var, _, var_pos) = &flow.expr
+                        && !var_pos.is_none()
+                    {
+                        let start = pos_to_byte(index, var_pos);
+                        let name = Span::from_range_in(index, start..start + var.1.len());
+                        spans.push((name, SpanKind::CatchVariable));
+                    }
+                    let branch =
+                        Span::from_rhai_span_in(index, flow.branch.span(), &flow.branch.position());
+                    spans.nested((branch, SpanKind::CatchBlock), |spans| {
+                        Self::walk_block(&flow.branch, index, engine, spans)
+                    })
+                })?;
+            }
+            Stmt::Switch(boxed, pos) => {
+                let (expr, cases) = &**boxed;
+                spans.nested((Span::from_pos_in(index, pos), SpanKind::Switch), |spans| {
+                    Self::walk_expr(expr, index, engine, spans)?;
+                    // Every case, including range cases and the default case, points
+                    // into `expressions`. Cases without an `if` condition have an
+                    // implicit `true` condition with no position.
+                    for case in cases.expressions.iter() {
+                        if !case.lhs.position().is_none() {
+                            Self::walk_expr(&case.lhs, index, engine, spans)?;
+                        }
+                        Self::walk_expr(&case.rhs, index, engine, spans)?;
+                    }
+                    Ok(())
+                })?;
+            }
+            Stmt::Expr(expr) => Self::walk_expr(expr, index, engine, spans)?,
+            Stmt::BreakLoop(opt_expr, flags, pos) | Stmt::Return(opt_expr, flags, pos) => {
+                let kind = match (stmt, flags.intersects(ASTFlags::BREAK)) {
+                    (Stmt::BreakLoop(..), true) => SpanKind::Break,
+                    (Stmt::BreakLoop(..), false) => SpanKind::Continue,
+                    (_, true) => SpanKind::Throw,
+                    (_, false) => SpanKind::Return,
+                };
+                spans.nested((Span::from_pos_in(index, pos), kind), |spans| match opt_expr {
+                    Some(expr) => Self::walk_expr(expr, index, engine, spans),
+                    None => Ok(()),
+                })?;
             }
             Stmt::Import(boxed, pos) => {
-                spans.push(Span::from_pos(script, pos));
                 let (expr, _) = &**boxed;
-                Self::walk_expr(expr, script, spans)?;
+                spans.nested((Span::from_pos_in(index, pos), SpanKind::Import), |spans| {
+                    let path = (!pos.is_none()).then(|| pos_to_byte(index, pos)).and_then(|start| {
+                        Self::import_path_range(spans.lexemes(index, engine), start)
+                    });
+                    let Some(path) = path else {
+                        return Self::walk_expr(expr, index, engine, spans);
+                    };
+                    let path = Span::from_range_in(index, path);
+                    spans.nested((path, SpanKind::ImportPath), |spans| {
+                        Self::walk_expr(expr, index, engine, spans)
+                    })
+                })?;
             }
             Stmt::Export(..) | Stmt::Share(..) => {}
             &_ => {}
@@ -117,76 +1444,187 @@ impl SpanTracer {
         Ok(())
     }
 
+    /// Returns the byte range of the path of the `import` statement starting at
+    /// byte `start`: everything after the keyword up to the `as` or the end of the
+    /// statement. String paths include their quotes.
+    fn import_path_range(lexemes: &[Lexeme], start: usize) -> Option<Range<usize>> {
+        let keyword = lexemes.partition_point(|lexeme| lexeme.span.start() < start);
+        let mut depth = 0usize;
+        let mut range: Option<Range<usize>> = None;
+
+        for lexeme in lexemes.iter().skip(keyword + 1) {
+            match lexeme.token {
+                Token::As | Token::SemiColon if depth == 0 => break,
+                Token::RightParen | Token::RightBracket | Token::RightBrace if depth == 0 => break,
+                Token::LeftParen | Token::LeftBracket | Token::LeftBrace | Token::MapStart => {
+                    depth += 1
+                }
+                Token::RightParen | Token::RightBracket | Token::RightBrace => depth -= 1,
+                _ => {}
+            }
+            let bytes = lexeme.span.start()..lexeme.span.end();
+            range = Some(range.map_or(bytes.clone(), |range| range.start..bytes.end));
+        }
+
+        range
+    }
+
     fn walk_binary_expr(
         bin: &BinaryExpr,
-        script: &str,
-        spans: &mut Vec<Span>,
+        index: &LineIndex,
+        engine: &Engine,
+        spans: &mut SpanCollector<'_>,
     ) -> Result<(), Box<dyn Error>> {
-        Self::walk_expr(&bin.lhs, script, spans)?;
-        Self::walk_expr(&bin.rhs, script, spans)?;
+        Self::walk_expr(&bin.lhs, index, engine, spans)?;
+        Self::walk_expr(&bin.rhs, index, engine, spans)?;
         Ok(())
     }
 
     fn walk_flow_control(
         flow: &FlowControl,
-        script: &str,
-        spans: &mut Vec<Span>,
+        index: &LineIndex,
+        engine: &Engine,
+        spans: &mut SpanCollector<'_>,
     ) -> Result<(), Box<dyn Error>> {
-        Self::walk_expr(&flow.expr, script, spans)?;
-        Self::walk_block(&flow.body, script, spans)?;
-        Self::walk_block(&flow.branch, script, spans)?;
+        Self::walk_expr(&flow.expr, index, engine, spans)?;
+        Self::walk_block(&flow.body, index, engine, spans)?;
+        Self::walk_block(&flow.branch, index, engine, spans)?;
         Ok(())
     }
 
     fn walk_block(
         block: &StmtBlock,
-        script: &str,
-        spans: &mut Vec<Span>,
+        index: &LineIndex,
+        engine: &Engine,
+        spans: &mut SpanCollector<'_>,
     ) -> Result<(), Box<dyn Error>> {
         for stmt in block.statements() {
-            Self::walk_stmt(stmt, script, spans)?;
+            Self::walk_stmt(stmt, index, engine, spans)?;
         }
         Ok(())
     }
 
-    fn walk_expr(expr: &Expr, script: &str, spans: &mut Vec<Span>) -> Result<(), Box<dyn Error>> {
-        spans.push(Span::from_pos(script, Self::expr_position(expr)));
-
-        match expr {
-            Expr::FnCall(f, _) | Expr::MethodCall(f, _) => {
-                Self::walk_fn_call(f.as_ref(), script, spans)?;
+    fn walk_expr(
+        expr: &Expr,
+        index: &LineIndex,
+        engine: &Engine,
+        spans: &mut SpanCollector<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        let span = match expr {
+            // Cover exactly the property name, so errors on it underline only the name.
+            Expr::Property(property, pos) if !pos.is_none() => {
+                let start = pos_to_byte(index, pos);
+                Span::from_range_in(index, start..start + property.2.len())
             }
-            Expr::Array(arr, _) | Expr::InterpolatedString(arr, _) => {
-                for elem in arr.iter() {
-                    Self::walk_expr(elem, script, spans)?;
+            _ => Span::from_pos_in(index, Self::expr_position(expr)),
+        };
+
+        spans.nested((span, Self::expr_kind(expr, engine)), |spans| {
+            match expr {
+                Expr::FnCall(f, pos) | Expr::MethodCall(f, pos) => {
+                    Self::walk_fn_call(f.as_ref(), pos, index, engine, spans)?;
                 }
-            }
-            Expr::Map(map_box, _) => {
-                let (pairs, _) = &**map_box;
-                for (_, expr) in pairs.iter() {
-                    Self::walk_expr(expr, script, spans)?;
+                Expr::Array(arr, _) | Expr::InterpolatedString(arr, _) => {
+                    for elem in arr.iter() {
+                        Self::walk_expr(elem, index, engine, spans)?;
+                    }
+                }
+                Expr::Map(map_box, _) => {
+                    let (pairs, _) = &**map_box;
+                    for (_, expr) in pairs.iter() {
+                        Self::walk_expr(expr, index, engine, spans)?;
+                    }
                 }
+                Expr::Stmt(block) => Self::walk_block(block, index, engine, spans)?,
+                Expr::Custom(custom, _) => {
+                    for input in custom.inputs.iter() {
+                        Self::walk_expr(input, index, engine, spans)?;
+                    }
+                }
+                // Method call arguments, including closures passed to `map` or
+                // `filter`, sit on the right-hand side of the dot.
+                Expr::Dot(bin, _, _) | Expr::Index(bin, _, _) => {
+                    Self::walk_binary_expr(bin, index, engine, spans)?;
+                }
+                Expr::And(operands, _) | Expr::Or(operands, _) | Expr::Coalesce(operands, _) => {
+                    for operand in operands.iter() {
+                        Self::walk_expr(operand, index, engine, spans)?;
+                    }
+                }
+                _ => {}
             }
-            _ => {}
-        }
-        Ok(())
+            Ok(())
+        })
     }
 
     fn walk_fn_call(
         fn_call: &FnCallExpr,
-        script: &str,
-        spans: &mut Vec<Span>,
+        pos: &Position,
+        index: &LineIndex,
+        engine: &Engine,
+        spans: &mut SpanCollector<'_>,
     ) -> Result<(), Box<dyn Error>> {
-        // Use the first argument's position as an approximation
-        if let Some(arg) = fn_call.args.first() {
-            spans.push(Span::from_pos(script, Self::expr_position(arg)));
+        // A call is positioned at the function's name, after any namespace.
+        // Operators have no name in the source to point at.
+        let start = pos_to_byte(index, pos);
+        let name = start..start + fn_call.name.len();
+        if !pos.is_none()
+            && !Self::is_operator(fn_call, engine)
+            && index.source().get(name.clone()) == Some(fn_call.name.as_str())
+        {
+            spans.push((Span::from_range_in(index, name.clone()), SpanKind::FnName));
+            if let Some(args) = Self::arguments_range(spans.lexemes(index, engine), name.end) {
+                spans.push((Span::from_range_in(index, args), SpanKind::Arguments));
+            }
         }
         for arg in &fn_call.args {
-            Self::walk_expr(arg, script, spans)?;
+            Self::walk_expr(arg, index, engine, spans)?;
         }
         Ok(())
     }
 
+    /// Returns the byte range from the first argument's start to the last argument's
+    /// end of the argument list opening at byte `after_name`, or `None` without
+    /// arguments.
+    fn arguments_range(lexemes: &[Lexeme], after_name: usize) -> Option<Range<usize>> {
+        let open = lexeme_at(lexemes, after_name);
+        if lexemes.get(open)?.token != Token::LeftParen {
+            return None;
+        }
+        bracket_contents(lexemes, open)
+    }
+
+    fn is_operator(fn_call: &FnCallExpr, engine: &Engine) -> bool {
+        fn_call.is_operator_call()
+            || (fn_call.args.len() == 2 && lexer::is_custom_operator(engine, &fn_call.name))
+    }
+
+    fn expr_kind(expr: &Expr, engine: &Engine) -> SpanKind {
+        match expr {
+            Expr::DynamicConstant(..)
+            | Expr::BoolConstant(..)
+            | Expr::IntegerConstant(..)
+            | Expr::FloatConstant(..)
+            | Expr::CharConstant(..)
+            | Expr::StringConstant(..)
+            | Expr::InterpolatedString(..)
+            | Expr::Unit(..) => SpanKind::Literal,
+            Expr::Array(..) => SpanKind::Array,
+            Expr::Map(..) => SpanKind::Map,
+            Expr::Variable(..) | Expr::ThisPtr(..) => SpanKind::Variable,
+            Expr::Property(..) => SpanKind::Property,
+            Expr::MethodCall(..) => SpanKind::MethodCall,
+            Expr::FnCall(f, _) if Self::is_operator(f, engine) => SpanKind::Operator,
+            Expr::FnCall(..) => SpanKind::FnCall,
+            Expr::Dot(..) => SpanKind::Dot,
+            Expr::Index(..) => SpanKind::Index,
+            Expr::And(..) | Expr::Or(..) | Expr::Coalesce(..) => SpanKind::Operator,
+            Expr::Stmt(..) => SpanKind::Block,
+            Expr::Custom(..) => SpanKind::Custom,
+            &_ => SpanKind::Other,
+        }
+    }
+
     fn expr_position(expr: &Expr) -> &Position {
         match expr {
             Expr::DynamicConstant(_, pos)
@@ -213,12 +1651,118 @@ impl SpanTracer {
             Expr::Stmt(block) => block
                 .statements()
                 .first()
-                .map(|s| Self::stmt_position(s))
+                .map(Self::stmt_position)
                 .unwrap_or_else(|| &Position::NONE),
             &_ => &Position::NONE,
         }
     }
 
+    /// Returns every call of a binary operator in `ast`, such as `a / b`, with
+    /// the byte ranges of its operands in `script`.
+    ///
+    /// The `AST` only keeps where expressions start, so operands end after
+    /// their last token as found among the script's tokens.
+    pub(crate) fn binary_operations(
+        ast: &AST,
+        script: &str,
+        engine: &Engine,
+    ) -> Vec<BinaryOperation> {
+        let index = LineIndex::new(script);
+        let lexemes = lexer::tokenize(engine, script);
+        let mut operations = Vec::new();
+
+        ast.walk(&mut |nodes: &[rhai::ASTNode]| {
+            // Calls used as statements are statements of their own.
+            let (f, pos) = match nodes.last() {
+                Some(rhai::ASTNode::Expr(Expr::FnCall(f, pos))) => (&**f, pos),
+                Some(rhai::ASTNode::Stmt(Stmt::FnCall(f, pos))) => (&**f, pos),
+                _ => return true,
+            };
+            if f.is_operator_call()
+                && f.args.len() == 2
+                && !pos.is_none()
+                && let Some(lhs) = Self::expr_range(&f.args[0], &index, &lexemes)
+                && let Some(rhs) = Self::expr_range(&f.args[1], &index, &lexemes)
+            {
+                operations.push(BinaryOperation {
+                    name: f.name.to_string(),
+                    operator: pos_to_byte(&index, pos),
+                    range: lexer::balance(&lexemes, lhs.start..rhs.end),
+                    lhs,
+                    rhs,
+                });
+            }
+            true
+        });
+
+        operations
+    }
+
+    /// Returns the byte range from the first through the last token of `expr`.
+    fn expr_range(expr: &Expr, index: &LineIndex, lexemes: &[Lexeme]) -> Option<Range<usize>> {
+        let start = Self::expr_start(expr);
+        if start.is_none() {
+            return None;
+        }
+        let start = pos_to_byte(index, &start);
+        let end = Self::expr_end(expr, index, lexemes)?;
+        (start < end).then(|| lexer::balance(lexemes, start..end))
+    }
+
+    /// Returns the byte offset right after the last token of `expr`.
+    fn expr_end(expr: &Expr, index: &LineIndex, lexemes: &[Lexeme]) -> Option<usize> {
+        match expr {
+            Expr::FnCall(f, _) if f.is_operator_call() => {
+                Self::expr_end(f.args.last()?, index, lexemes)
+            }
+            Expr::Dot(bin, ..) | Expr::Index(bin, ..) => Self::expr_end(&bin.rhs, index, lexemes),
+            Expr::And(operands, _) | Expr::Or(operands, _) | Expr::Coalesce(operands, _) => {
+                Self::expr_end(operands.last()?, index, lexemes)
+            }
+            // Run through the closing bracket of the arguments or elements.
+            Expr::FnCall(_, pos)
+            | Expr::MethodCall(_, pos)
+            | Expr::Array(_, pos)
+            | Expr::Map(_, pos) => {
+                let at = lexer::lexeme_at(lexemes, pos_to_byte(index, pos));
+                let open = at
+                    + lexemes[at..]
+                        .iter()
+                        .position(|lexeme| lexer::is_open(&lexeme.token))?;
+                let bracket = lexemes[open].span;
+                Some(lexer::balance(lexemes, bracket.start()..bracket.end()).end)
+            }
+            _ => {
+                let pos = expr.start_position();
+                if pos.is_none() {
+                    return None;
+                }
+                let at = lexer::lexeme_at(lexemes, pos_to_byte(index, &pos));
+                // Negative number literals start at their sign.
+                let at = match lexemes.get(at)?.token {
+                    Token::UnaryMinus | Token::Minus | Token::UnaryPlus | Token::Plus => at + 1,
+                    _ => at,
+                };
+                Some(lexemes.get(at)?.span.end())
+            }
+        }
+    }
+
+    /// Returns the position of the leftmost token of `expr`, which for a binary
+    /// operator is that of its left operand rather than the operator itself.
+    fn expr_start(expr: &Expr) -> Position {
+        match expr {
+            Expr::FnCall(f, _) if f.is_operator_call() && f.args.len() == 2 => {
+                Self::expr_start(&f.args[0])
+            }
+            Expr::Dot(bin, ..) | Expr::Index(bin, ..) => Self::expr_start(&bin.lhs),
+            Expr::And(operands, _) | Expr::Or(operands, _) | Expr::Coalesce(operands, _) => {
+                Self::expr_start(&operands[0])
+            }
+            _ => expr.start_position(),
+        }
+    }
+
     fn stmt_position(stmt: &Stmt) -> &Position {
         match stmt {
             Stmt::Noop(pos)
@@ -232,7 +1776,7 @@ impl SpanTracer {
             | Stmt::BreakLoop(_, _, pos)
             | Stmt::Return(_, _, pos)
             | Stmt::Import(_, pos) => pos,
-            Stmt::Assignment(_) => &Position::NONE,
+            Stmt::Assignment(boxed) => Self::expr_position(&boxed.1.lhs),
             Stmt::Block(block) => block
                 .statements()
                 .first()
@@ -244,3 +1788,137 @@ impl SpanTracer {
         }
     }
 }
+
+/// A callback receiving the nodes of a walk, as passed to [`SpanTracer::visit`].
+type NodeVisitor<'v> = dyn FnMut(&SpanNode) -> ControlFlow<()> + 'v;
+
+/// The settings of a walk of an `AST`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct WalkOptions {
+    /// The tab width used for the spans' [`display_column`](Span::display_column).
+    pub tab_width: usize,
+    /// How many nodes deep the walk may descend.
+    pub max_depth: usize,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        WalkOptions {
+            tab_width: 1,
+            max_depth: DEFAULT_MAX_NESTING_DEPTH,
+        }
+    }
+}
+
+/// Collects spans during the walk, remembering the node each one is nested in.
+#[derive(Default)]
+struct SpanCollector<'v> {
+    spans: Vec<(Span, SpanKind)>,
+    parents: Vec<Option<usize>>,
+    parent: Option<usize>,
+    /// When set, spans are handed to the visitor instead of being collected.
+    visitor: Option<&'v mut NodeVisitor<'v>>,
+    /// Set once the visitor breaks, after which nothing more is walked.
+    stopped: bool,
+    /// The tokens of the script, read on first use.
+    lexemes: Option<Vec<Lexeme>>,
+    /// How many nodes the walk is currently nested in, and how many it may be.
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'v> SpanCollector<'v> {
+    fn visiting(visitor: &'v mut NodeVisitor<'v>) -> Self {
+        Self {
+            visitor: Some(visitor),
+            ..Self::default()
+        }
+    }
+
+    fn push(&mut self, (span, kind): (Span, SpanKind)) {
+        if self.stopped {
+            return;
+        }
+
+        match &mut self.visitor {
+            Some(visitor) => {
+                let node = SpanNode {
+                    span: span.with_kind(kind),
+                    kind,
+                };
+                self.stopped = visitor(&node).is_break();
+            }
+            None => {
+                self.spans.push((span, kind));
+                self.parents.push(self.parent);
+            }
+        }
+    }
+
+    /// Returns the tokens of the script, tokenizing it on the first call.
+    fn lexemes(&mut self, index: &LineIndex, engine: &Engine) -> &[Lexeme] {
+        self.lexemes
+            .get_or_insert_with(|| lexer::tokenize(engine, index.source()))
+    }
+
+    /// Pushes `span`, then runs `walk` with `span` as the parent of everything it pushes.
+    fn nested(
+        &mut self,
+        span: (Span, SpanKind),
+        walk: impl FnOnce(&mut Self) -> Result<(), Box<dyn Error>>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.push(span);
+        if self.stopped {
+            return Ok(());
+        }
+        // Every level of nesting is a level of recursion in the walk.
+        if self.depth >= self.max_depth {
+            return Err(Box::new(TraceError::NestingTooDeep {
+                max: self.max_depth,
+            }));
+        }
+
+        let outer = match self.visitor {
+            Some(_) => self.parent,
+            None => self.parent.replace(self.spans.len() - 1),
+        };
+        self.depth += 1;
+        let result = walk(self);
+        self.depth -= 1;
+        self.parent = outer;
+        result
+    }
+
+    /// Builds the tree of collected spans under a root spanning `root`.
+    fn into_tree(self, root: Span) -> SpanTree {
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); self.spans.len()];
+        let mut roots = Vec::new();
+        for (i, parent) in self.parents.iter().enumerate() {
+            match parent {
+                Some(parent) => children[*parent].push(i),
+                None => roots.push(i),
+            }
+        }
+
+        fn build(i: usize, spans: &[(Span, SpanKind)], children: &[Vec<usize>]) -> SpanTree {
+            let (span, kind) = spans[i];
+            SpanTree {
+                span: span.with_kind(kind),
+                kind,
+                children: sorted(children[i].iter().map(|&c| build(c, spans, children)).collect()),
+            }
+        }
+
+        // The walk does not always visit siblings in source order.
+        fn sorted(mut nodes: Vec<SpanTree>) -> Vec<SpanTree> {
+            nodes.sort_by_key(|node| node.span);
+            nodes
+        }
+
+        SpanTree {
+            span: root.with_kind(SpanKind::Script),
+            kind: SpanKind::Script,
+            children: sorted(roots.iter().map(|&i| build(i, &self.spans, &children)).collect()),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+#![cfg(feature = "cli")]
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+
+fn rhai_trace() -> Command {
+    Command::cargo_bin("rhai-trace").unwrap()
+}
+
+#[test]
+fn check_valid_script() {
+    rhai_trace()
+        .args(["check", "tests/fixtures/valid.rhai"])
+        .assert()
+        .code(0)
+        .stdout("");
+}
+
+#[test]
+fn check_reports_lints_as_warnings() {
+    rhai_trace()
+        .args(["check", "tests/fixtures/infinite_loop.rhai"])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::starts_with(
+            "warning[RT0201]: This `while` loop never terminates.\n \
+             --> tests/fixtures/infinite_loop.rhai:2:1\n",
+        ))
+        .stdout(predicate::str::contains("2 | while true {\n  | ^^^^^^^^^^\n"));
+}
+
+#[test]
+fn check_quotes_labels() {
+    rhai_trace()
+        .args(["check", "tests/fixtures/shadowing.rhai"])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::starts_with("warning[RT0203]: "))
+        .stdout(predicate::str::contains(
+            "3 |     let total = 2;\n  |         ^^^^^\n  |\n\
+             1 | let total = 1;\n  |     ^^^^^ the earlier `total` is declared here\n",
+        ));
+}
+
+#[test]
+fn check_reports_syntax_errors() {
+    rhai_trace()
+        .args(["check", "tests/fixtures/syntax_error.rhai"])
+        .assert()
+        .code(2)
+        .stdout(predicate::str::starts_with("error[RT0001]: "))
+        .stdout(predicate::str::contains("--> tests/fixtures/syntax_error.rhai:2:15"));
+}
+
+#[test]
+fn check_formats() {
+    let output = rhai_trace()
+        .args(["check", "tests/fixtures/syntax_error.rhai", "--format", "json"])
+        .assert()
+        .code(2)
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json[0]["severity"], "error");
+    assert_eq!(json[0]["code"], "RT0001");
+    assert_eq!(json[0]["span"]["line"], 2);
+
+    rhai_trace()
+        .args(["check", "tests/fixtures/infinite_loop.rhai", "--format=github"])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::starts_with(
+            "::warning file=tests/fixtures/infinite_loop.rhai,line=2,col=1,endLine=2,endColumn=11,title=RT0201::",
+        ));
+}
+
+#[test]
+fn check_reads_stdin() {
+    rhai_trace()
+        .args(["check", "-"])
+        .write_stdin("let x = ;")
+        .assert()
+        .code(2)
+        .stdout(predicate::str::contains("--> <stdin>:1:9"));
+}
+
+#[test]
+fn spans_as_json() {
+    let output = rhai_trace()
+        .args(["spans", "tests/fixtures/valid.rhai", "--json"])
+        .assert()
+        .code(0)
+        .get_output()
+        .stdout
+        .clone();
+    let spans: Vec<rhai_trace::Span> = serde_json::from_slice(&output).unwrap();
+    assert!(spans.iter().any(|span| span.line() == 5));
+
+    rhai_trace()
+        .args(["spans", "tests/fixtures/syntax_error.rhai"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn explain_error_codes() {
+    rhai_trace()
+        .args(["explain", "RT0104"])
+        .assert()
+        .code(0)
+        .stdout(predicate::str::starts_with("RT0104: data type mismatch\n\n"));
+
+    rhai_trace()
+        .args(["explain", "RT9999"])
+        .assert()
+        .code(3)
+        .stderr("error: Unknown error code 'RT9999'.\n");
+}
+
+#[test]
+fn usage_errors() {
+    rhai_trace().args(["frobnicate", "x"]).assert().code(3);
+    rhai_trace().args(["check"]).assert().code(3);
+    rhai_trace()
+        .args(["check", "tests/fixtures/missing.rhai"])
+        .assert()
+        .code(3)
+        .stderr(predicate::str::starts_with("error: Failed to read"));
+}
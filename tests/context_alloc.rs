@@ -0,0 +1,142 @@
+use rhai::{Dynamic, Engine, EvalAltResult, Position};
+use rhai_trace::{
+    BetterError, ErrorContext, ErrorContextRef, ImproveOptions, SpanTracer, TraceContext, Verbosity,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Counts the allocations and reallocations made by the current thread while enabled.
+struct CountingAllocator;
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.with(Cell::get) {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        }
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if COUNTING.with(Cell::get) {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        }
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    ALLOCATIONS.with(|count| count.set(0));
+    COUNTING.with(|counting| counting.set(true));
+    let result = f();
+    COUNTING.with(|counting| counting.set(false));
+    (result, ALLOCATIONS.with(Cell::get))
+}
+
+/// Number of heap buffers owned by a `BetterError`: those of its strings and labels.
+fn owned_buffers(better: &BetterError) -> usize {
+    let strings = [
+        Some(&better.message),
+        better.help.as_ref(),
+        better.hint.as_ref(),
+        better.note.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .chain(better.labels.iter().map(|(_, label)| label))
+    .filter(|s| s.capacity() > 0)
+    .count();
+
+    strings + usize::from(better.labels.capacity() > 0)
+}
+
+const CODE: &str = r#"
+fn multiply(x, y) { x * y }
+
+let a = 42;
+let z = a / 0;
+"#;
+
+#[test]
+fn test_owned_and_borrowed_contexts_match() {
+    let engine = Engine::new();
+    let err = engine.eval::<Dynamic>(CODE).unwrap_err();
+
+    let owned = ErrorContext::new(CODE, &engine).unwrap();
+
+    let ast = engine.compile(CODE).unwrap();
+    let spans = SpanTracer::new().extract_from(CODE).unwrap();
+    let borrowed = ErrorContextRef::new(CODE, &ast, &spans);
+
+    let from_owned = owned.improve(&err);
+    let from_borrowed = borrowed.improve(&err);
+    let from_owned_ref = owned.borrowed().improve(&err);
+    let from_eval = BetterError::improve_eval_error(&err, CODE, &engine, None, None).unwrap();
+
+    assert_eq!(format!("{:?}", from_owned), format!("{:?}", from_borrowed));
+    assert_eq!(format!("{:?}", from_owned), format!("{:?}", from_owned_ref));
+    assert_eq!(format!("{:?}", from_owned), format!("{:?}", from_eval));
+}
+
+#[test]
+fn test_borrowed_context_only_allocates_output() {
+    let engine = Engine::new();
+    // Arithmetic errors tokenize the script to find the failed operation, so
+    // improve an error that needs nothing but the spans.
+    let err = EvalAltResult::ErrorTooManyOperations(Position::NONE);
+
+    let ast = engine.compile(CODE).unwrap();
+    let spans = SpanTracer::new().extract_from(CODE).unwrap();
+    let context = ErrorContextRef::new(CODE, &ast, &spans);
+
+    let (better, allocations) = count_allocations(|| context.improve(&err));
+
+    assert_eq!(allocations, owned_buffers(&better));
+}
+
+#[test]
+fn test_minimal_borrowed_context_only_allocates_output() {
+    let mut engine = Engine::new();
+    engine.set_max_call_levels(16);
+    engine.set_max_array_size(4);
+    let minimal = ImproveOptions::new().verbosity(Verbosity::Minimal);
+
+    let scripts = [
+        "let a = 42;\nlet z = a / 0;",
+        "fn down(n) { down(n + 1) }\ndown(0);",
+        "let total = 1;\nlet b = totl + 1;",
+        "let a = 1;\nfoo(a, 2);",
+        "fn add(x, y) { x + y }\nadd(1);",
+        "let a = \"text\" - 1;",
+        "let a = [1, 2, 3];\nlet b = a[10];",
+        "fn check(flag) { if flag { 1 } }\ncheck(42);",
+        "import \"missing_module\" as m;",
+        "let a = [];\nfor i in 0..10 { a.push(i); }",
+        "throw \"out of stock\";",
+        "throw #{code: 42, msg: \"out of stock\"};",
+        "try { throw 1; } catch (err) { throw err + 1; }",
+        "fn inner(x) { x + missing }\nfn outer(x) { inner(x) }\nouter(1);",
+    ];
+
+    for code in scripts {
+        let ast = engine.compile(code).unwrap();
+        let err = engine.eval_ast::<Dynamic>(&ast).unwrap_err();
+        let spans = SpanTracer::new().extract_from(code).unwrap();
+        let context = ErrorContextRef::with_options(code, &ast, &spans, minimal);
+
+        let (better, allocations) = count_allocations(|| context.improve(&err));
+
+        assert_eq!(allocations, owned_buffers(&better), "{}: {:?}", code, better);
+    }
+}
@@ -0,0 +1,43 @@
+//! Compares the spans extracted from the scripts in `tests/fixtures/golden` with
+//! the annotated dumps next to them, so changes to the walk show up as text diffs.
+//!
+//! Run with `RHAI_TRACE_BLESS=1` to rewrite the dumps after an intended change.
+
+use rhai_trace::SpanTracer;
+use rhai_trace::render::dump_annotated;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn spans_match_golden_dumps() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/golden");
+    let bless = std::env::var_os("RHAI_TRACE_BLESS").is_some();
+
+    let mut scripts: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rhai"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+
+    for script in scripts {
+        let source = fs::read_to_string(&script).unwrap();
+        let spans = SpanTracer::new().extract_from(&source).unwrap();
+        let dump = dump_annotated(&source, &spans);
+
+        let golden = script.with_extension("spans");
+        if bless {
+            fs::write(&golden, &dump).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&golden)
+            .unwrap_or_else(|_| panic!("{} is missing", golden.display()));
+        assert_eq!(
+            dump,
+            expected,
+            "the spans of {} changed; rerun with RHAI_TRACE_BLESS=1 if this is intended",
+            script.display()
+        );
+    }
+}